@@ -0,0 +1,838 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal ACME (RFC 8555) client that solves DNS-01 challenges using the API.
+
+use data::{Delete, Record, Update};
+use openssl::{
+    asn1::Asn1Time, bn::BigNumContext, ec::{EcGroup, EcKey}, ecdsa::EcdsaSig, hash::MessageDigest,
+    nid::Nid, pkey::{PKey, Private}, sha::sha256, stack::Stack,
+    x509::{extension::SubjectAlternativeName, X509, X509NameBuilder, X509ReqBuilder},
+};
+use reqwest::{header::CONTENT_TYPE, Client, Response};
+use std::{
+    fs, io::Write, path::{Path, PathBuf}, thread, time::Duration,
+};
+use bind9_api_client::Api;
+use Result;
+
+pub const LETSENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+const TTL: u32 = 60;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: u32 = 90;
+const JOSE: &str = "application/jose+json";
+
+#[derive(Debug, Fail)]
+enum AcmeError {
+    #[fail(display = "ACME server error: {}", _0)]
+    Server(String),
+    #[fail(display = "Missing Replay-Nonce header")]
+    Nonce,
+    #[fail(display = "Missing Location header")]
+    Location,
+    #[fail(display = "No dns-01 challenge offered for {}", _0)]
+    NoChallenge(String),
+    #[fail(display = "Validation of {} failed", _0)]
+    Invalid(String),
+    #[fail(display = "Timeout waiting for {}", _0)]
+    Timeout(String),
+    #[fail(display = "No domains given")]
+    NoDomains,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Identifier {
+    #[serde(rename = "type")]
+    kind: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    identifier: Identifier,
+    status: String,
+    #[serde(default)]
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    detail: String,
+}
+
+/// Field order matters: this is also the canonical form used for the
+/// thumbprint (RFC 7638).
+#[derive(Serialize)]
+struct Jwk {
+    crv: &'static str,
+    kty: &'static str,
+    x: String,
+    y: String,
+}
+
+#[derive(Serialize)]
+struct Protected<'a> {
+    alg: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jwk: Option<Jwk>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<&'a str>,
+    nonce: String,
+    url: &'a str,
+}
+
+#[derive(Serialize)]
+struct Jws {
+    protected: String,
+    payload: String,
+    signature: String,
+}
+
+/// Settings for issuing a certificate.
+pub struct Options<'a> {
+    directory: &'a str,
+    domains: Vec<String>,
+    email: Option<&'a str>,
+    account_key: PathBuf,
+    out: PathBuf,
+    ca_cert: Option<&'a str>,
+    wait: u32,
+}
+
+impl<'a> Options<'a> {
+    pub fn new(directory: &'a str, domains: Vec<String>, out: PathBuf, wait: u32) -> Self {
+        Self {
+            directory,
+            domains,
+            email: None,
+            account_key: out.join("account.pem"),
+            out,
+            ca_cert: None,
+            wait,
+        }
+    }
+
+    pub fn set_email(&mut self, email: Option<&'a str>) {
+        self.email = email;
+    }
+
+    pub fn set_account_key(&mut self, path: PathBuf) {
+        self.account_key = path;
+    }
+
+    pub fn set_ca_cert(&mut self, path: Option<&'a str>) {
+        self.ca_cert = path;
+    }
+}
+
+fn b64(data: &[u8]) -> String {
+    ::base64::encode_config(data, ::base64::URL_SAFE_NO_PAD)
+}
+
+/// Returns the value of the TXT record for a challenge (RFC 8555, section
+/// 8.4).
+fn challenge_value(token: &str, thumbprint: &str) -> String {
+    let key_auth = format!("{}.{}", token, thumbprint);
+    b64(&sha256(key_auth.as_bytes()))
+}
+
+/// Writes `data` to `path`. Private keys are only readable by the owner.
+fn write_file(path: &Path, data: &[u8], private: bool) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        if private {
+            options.mode(0o600);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    options.open(path)?.write_all(data)?;
+    Ok(())
+}
+
+fn generate_key() -> Result<EcKey<Private>> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    Ok(EcKey::generate(&group)?)
+}
+
+fn load_or_create_key(path: &Path) -> Result<EcKey<Private>> {
+    if path.exists() {
+        return Ok(PKey::private_key_from_pem(&fs::read(path)?)?.ec_key()?);
+    }
+    info!("creating account key {}", path.display());
+    let key = generate_key()?;
+    write_file(
+        path,
+        &PKey::from_ec_key(key.clone())?.private_key_to_pem_pkcs8()?,
+        true,
+    )?;
+    Ok(key)
+}
+
+struct Account {
+    client: Client,
+    key: EcKey<Private>,
+    directory: Directory,
+    nonce: Option<String>,
+    kid: Option<String>,
+}
+
+impl Account {
+    fn new(options: &Options) -> Result<Self> {
+        let mut client = Client::builder();
+        if let Some(path) = options.ca_cert {
+            client = client.add_root_certificate(reqwest::Certificate::from_pem(&fs::read(path)?)?);
+        }
+        let client = client.build()?;
+        let directory = client.get(options.directory).send()?.json()?;
+        Ok(Self {
+            client,
+            key: load_or_create_key(&options.account_key)?,
+            directory,
+            nonce: None,
+            kid: None,
+        })
+    }
+
+    fn jwk(&self) -> Result<Jwk> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let mut x = ::openssl::bn::BigNum::new()?;
+        let mut y = ::openssl::bn::BigNum::new()?;
+        let mut ctx = BigNumContext::new()?;
+        self.key
+            .public_key()
+            .affine_coordinates_gfp(&group, &mut x, &mut y, &mut ctx)?;
+        Ok(Jwk {
+            crv: "P-256",
+            kty: "EC",
+            x: b64(&x.to_vec_padded(32)?),
+            y: b64(&y.to_vec_padded(32)?),
+        })
+    }
+
+    fn thumbprint(&self) -> Result<String> {
+        Ok(b64(&sha256(::serde_json::to_string(&self.jwk()?)?.as_bytes())))
+    }
+
+    fn nonce(&mut self) -> Result<String> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let res = self.client.head(&self.directory.new_nonce).send()?;
+        replay_nonce(&res).ok_or_else(|| AcmeError::Nonce.into())
+    }
+
+    fn sign(&mut self, url: &str, payload: &str) -> Result<Jws> {
+        let nonce = self.nonce()?;
+        let protected = Protected {
+            alg: "ES256",
+            jwk: match self.kid {
+                Some(_) => None,
+                None => Some(self.jwk()?),
+            },
+            kid: self.kid.as_deref(),
+            nonce,
+            url,
+        };
+        let protected = b64(::serde_json::to_string(&protected)?.as_bytes());
+        let payload = b64(payload.as_bytes());
+        let input = format!("{}.{}", protected, payload);
+        let sig = EcdsaSig::sign(&sha256(input.as_bytes()), &self.key)?;
+        let mut signature = sig.r().to_vec_padded(32)?;
+        signature.extend(sig.s().to_vec_padded(32)?);
+        Ok(Jws {
+            protected,
+            payload,
+            signature: b64(&signature),
+        })
+    }
+
+    /// Sends a signed request. An empty payload is a POST-as-GET. Requests
+    /// rejected because of a stale nonce are retried once.
+    fn post(&mut self, url: &str, payload: &str) -> Result<Response> {
+        let mut retried = false;
+        loop {
+            let jws = self.sign(url, payload)?;
+            let mut res = self
+                .client
+                .post(url)
+                .header(CONTENT_TYPE, JOSE)
+                .body(::serde_json::to_string(&jws)?)
+                .send()?;
+            self.nonce = replay_nonce(&res);
+            if res.status().is_success() {
+                return Ok(res);
+            }
+            let problem: Problem = res.json()?;
+            if problem.kind.ends_with(":badNonce") && !retried {
+                retried = true;
+                continue;
+            }
+            return Err(AcmeError::Server(format!("{} {}", problem.kind, problem.detail)).into());
+        }
+    }
+
+    fn register(&mut self, email: Option<&str>) -> Result<()> {
+        let contact: Vec<String> = email.map(|e| format!("mailto:{}", e)).into_iter().collect();
+        let payload = json_string(&NewAccount {
+            terms_of_service_agreed: true,
+            contact,
+        })?;
+        let url = self.directory.new_account.clone();
+        let res = self.post(&url, &payload)?;
+        self.kid = Some(location(&res)?);
+        Ok(())
+    }
+
+    fn poll<T, F>(&mut self, url: &str, what: &str, done: F) -> Result<T>
+    where
+        T: ::serde::de::DeserializeOwned,
+        F: Fn(&T) -> Result<bool>,
+    {
+        for _ in 0..POLL_ATTEMPTS {
+            let resource = self.post(url, "")?.json()?;
+            if done(&resource)? {
+                return Ok(resource);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Err(AcmeError::Timeout(what.to_owned()).into())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NewAccount {
+    terms_of_service_agreed: bool,
+    contact: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct NewOrder<'a> {
+    identifiers: &'a [Identifier],
+}
+
+#[derive(Serialize)]
+struct Finalize {
+    csr: String,
+}
+
+fn json_string<T: ::serde::Serialize>(value: &T) -> Result<String> {
+    Ok(::serde_json::to_string(value)?)
+}
+
+fn replay_nonce(res: &Response) -> Option<String> {
+    res.headers()
+        .get("Replay-Nonce")
+        .and_then(|n| n.to_str().ok())
+        .map(str::to_owned)
+}
+
+fn location(res: &Response) -> Result<String> {
+    res.headers()
+        .get("Location")
+        .and_then(|l| l.to_str().ok())
+        .map(str::to_owned)
+        .ok_or_else(|| AcmeError::Location.into())
+}
+
+fn csr(domains: &[String], key: &PKey<Private>) -> Result<Vec<u8>> {
+    let mut builder = X509ReqBuilder::new()?;
+    if domains[0].len() <= 64 {
+        let mut name = X509NameBuilder::new()?;
+        name.append_entry_by_nid(Nid::COMMONNAME, &domains[0])?;
+        builder.set_subject_name(&name.build())?;
+    }
+    builder.set_pubkey(key)?;
+    let mut san = SubjectAlternativeName::new();
+    for domain in domains {
+        san.dns(domain);
+    }
+    let san = san.build(&builder.x509v3_context(None))?;
+    let mut extensions = Stack::new()?;
+    extensions.push(san)?;
+    builder.add_extensions(&extensions)?;
+    builder.sign(key, MessageDigest::sha256())?;
+    Ok(builder.build().to_der()?)
+}
+
+/// Publishes the TXT record for a pending authorization and returns the
+/// published record so it can be removed again.
+fn publish<A: Api>(
+    api: &A,
+    account: &Account,
+    authorization: &Authorization,
+    wait: u32,
+) -> Result<(Delete, String)> {
+    let domain = &authorization.identifier.value;
+    let challenge = authorization
+        .challenges
+        .iter()
+        .find(|c| c.kind == "dns-01")
+        .ok_or_else(|| AcmeError::NoChallenge(domain.to_owned()))?;
+    let value = challenge_value(&challenge.token, &account.thumbprint()?);
+    let name = format!("_acme-challenge.{}", domain);
+    info!("publishing challenge for {}", domain);
+    let mut update = Update::new(name.clone(), value.clone(), Record::TXT, TTL);
+    update.set_wait(Some(wait));
+    ::report(&api.update(&update)?)?;
+    let mut delete = Delete::new(name, Record::TXT);
+    delete.set_value(Some(value));
+    Ok((delete, challenge.url.clone()))
+}
+
+fn authorize<A: Api>(
+    api: &A,
+    account: &mut Account,
+    order: &Order,
+    published: &mut Vec<Delete>,
+    wait: u32,
+) -> Result<()> {
+    let mut pending = Vec::new();
+    for url in &order.authorizations {
+        let authorization: Authorization = account.post(url, "")?.json()?;
+        if authorization.status == "valid" {
+            continue;
+        }
+        let (delete, challenge) = publish(api, account, &authorization, wait)?;
+        published.push(delete);
+        pending.push((url.clone(), challenge, authorization.identifier.value));
+    }
+    for (url, challenge, domain) in pending {
+        account.post(&challenge, "{}")?;
+        account.poll(&url, &domain, |a: &Authorization| match a.status.as_str() {
+            "valid" => Ok(true),
+            "pending" | "processing" => Ok(false),
+            _ => Err(AcmeError::Invalid(domain.clone()).into()),
+        })?;
+    }
+    Ok(())
+}
+
+/// Requests a certificate for `options.domains` and writes `privkey.pem`,
+/// `cert.pem`, `chain.pem` and `fullchain.pem` to the output directory. The
+/// challenge records are removed again, even if the issuance fails.
+pub fn issue<A: Api>(api: &A, options: &Options) -> Result<()> {
+    if options.domains.is_empty() {
+        return Err(AcmeError::NoDomains.into());
+    }
+    fs::create_dir_all(&options.out)?;
+    let mut account = Account::new(options)?;
+    account.register(options.email)?;
+    let identifiers: Vec<_> = options
+        .domains
+        .iter()
+        .map(|d| Identifier {
+            kind: "dns".to_owned(),
+            value: d.to_owned(),
+        })
+        .collect();
+    let url = account.directory.new_order.clone();
+    let mut res = account.post(
+        &url,
+        &json_string(&NewOrder {
+            identifiers: &identifiers,
+        })?,
+    )?;
+    let order_url = location(&res)?;
+    let order: Order = res.json()?;
+    let mut published = Vec::new();
+    let result = authorize(api, &mut account, &order, &mut published, options.wait);
+    for delete in &published {
+        if let Err(e) = api.delete(delete) {
+            warn!("cannot remove challenge {}: {}", delete.name(), e);
+        }
+    }
+    result?;
+
+    let key = PKey::from_ec_key(generate_key()?)?;
+    account.post(
+        &order.finalize,
+        &json_string(&Finalize {
+            csr: b64(&csr(&options.domains, &key)?),
+        })?,
+    )?;
+    let order: Order = account.poll(&order_url, "order", |o: &Order| {
+        match o.status.as_str() {
+            "valid" => Ok(true),
+            "pending" | "ready" | "processing" => Ok(false),
+            _ => Err(AcmeError::Invalid("order".to_owned()).into()),
+        }
+    })?;
+    let certificate = order
+        .certificate
+        .ok_or_else(|| AcmeError::Server("missing certificate URL".to_owned()))?;
+    let fullchain = account.post(&certificate, "")?.text()?;
+    let certs = X509::stack_from_pem(fullchain.as_bytes())?;
+    let mut certs = certs.iter();
+    let cert = certs
+        .next()
+        .ok_or_else(|| AcmeError::Server("empty certificate chain".to_owned()))?;
+    let mut chain = Vec::new();
+    for cert in certs {
+        chain.extend(cert.to_pem()?);
+    }
+    write_file(
+        &options.out.join("privkey.pem"),
+        &key.private_key_to_pem_pkcs8()?,
+        true,
+    )?;
+    write_file(&options.out.join("cert.pem"), &cert.to_pem()?, false)?;
+    write_file(&options.out.join("chain.pem"), &chain, false)?;
+    write_file(
+        &options.out.join("fullchain.pem"),
+        fullchain.as_bytes(),
+        false,
+    )?;
+    info!("certificate written to {}", options.out.display());
+    Ok(())
+}
+
+/// Reissues the certificate in the output directory if it expires within
+/// `days` days or does not exist yet. Without explicit domains, the names of
+/// the existing certificate are reused.
+pub fn renew<A: Api>(api: &A, mut options: Options, days: u32) -> Result<()> {
+    let path = options.out.join("cert.pem");
+    if path.exists() {
+        let cert = X509::from_pem(&fs::read(&path)?)?;
+        if cert.not_after() > Asn1Time::days_from_now(days)? {
+            info!("certificate is valid until {}, not renewing", cert.not_after());
+            return Ok(());
+        }
+        if options.domains.is_empty() {
+            options.domains = cert
+                .subject_alt_names()
+                .map(|names| {
+                    names
+                        .iter()
+                        .filter_map(|n| n.dnsname().map(str::to_owned))
+                        .collect()
+                })
+                .unwrap_or_default();
+        }
+    }
+    issue(api, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bind9_api_client::{self, Outcome};
+    use data::{Batch, Lease, Propagation, ResourceRecord, Wait};
+    use openssl::{bn::BigNum, ec::EcPoint, x509::X509Req};
+    use std::{
+        cell::RefCell, io::{BufRead, BufReader, Read}, net::TcpListener,
+        sync::atomic::{AtomicUsize, Ordering}, sync::Arc,
+    };
+
+    /// The P-256 key of RFC 7517, appendix A.2.
+    fn rfc_key() -> EcKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let d = BigNum::from_hex_str(
+            "f3bd0c07a81fb932781ed52752f60cc89a6be5e51934fe01938ddb55d8f77801",
+        ).unwrap();
+        let mut public = EcPoint::new(&group).unwrap();
+        public
+            .mul_generator(&group, &d, &BigNumContext::new().unwrap())
+            .unwrap();
+        EcKey::from_private_components(&group, &d, &public).unwrap()
+    }
+
+    fn account(key: EcKey<Private>, base: &str) -> Account {
+        Account {
+            client: Client::new(),
+            key,
+            directory: Directory {
+                new_nonce: format!("{}/nonce", base),
+                new_account: format!("{}/account", base),
+                new_order: format!("{}/order", base),
+            },
+            nonce: Some("nonce".to_owned()),
+            kid: None,
+        }
+    }
+
+    fn decode(data: &str) -> Vec<u8> {
+        ::base64::decode_config(data, ::base64::URL_SAFE_NO_PAD).unwrap()
+    }
+
+    #[test]
+    fn key_authorization() {
+        let account = account(rfc_key(), "http://localhost");
+        assert_eq!(
+            account.thumbprint().unwrap(),
+            "cn-I_WNMClehiVp51i_0VpOENW1upEerA8sEam5hn-s"
+        );
+        // example of RFC 8555, section 8.1
+        assert_eq!(
+            challenge_value(
+                "evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA",
+                "nP1qzpXGymHBrUEepNY9HCsQk7K8KhOypzEt62jcerQ"
+            ),
+            "NGwKoXBgCT8JhEa0bK7AwfSqHyu_ZWeugV07fLGIVq0"
+        );
+    }
+
+    #[test]
+    fn sign_requests() {
+        let key = rfc_key();
+        let mut account = account(key.clone(), "http://localhost");
+        let jws = account.sign("http://localhost/account", "{}").unwrap();
+        let protected: ::serde_json::Value =
+            ::serde_json::from_slice(&decode(&jws.protected)).unwrap();
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["nonce"], "nonce");
+        assert_eq!(protected["url"], "http://localhost/account");
+        assert_eq!(protected["jwk"]["x"], "MKBCTNIcKUSDii11ySs3526iDZ8AiTo7Tu6KPAqv7D4");
+        assert!(protected.get("kid").is_none());
+        assert_eq!(decode(&jws.payload), b"{}");
+
+        let signature = decode(&jws.signature);
+        assert_eq!(signature.len(), 64);
+        let sig = EcdsaSig::from_private_components(
+            BigNum::from_slice(&signature[..32]).unwrap(),
+            BigNum::from_slice(&signature[32..]).unwrap(),
+        ).unwrap();
+        let input = format!("{}.{}", jws.protected, jws.payload);
+        assert!(sig.verify(&sha256(input.as_bytes()), &key).unwrap());
+
+        // once registered, the account URL replaces the key
+        account.kid = Some("http://localhost/acct/1".to_owned());
+        account.nonce = Some("next".to_owned());
+        let jws = account.sign("http://localhost/order", "").unwrap();
+        let protected: ::serde_json::Value =
+            ::serde_json::from_slice(&decode(&jws.protected)).unwrap();
+        assert_eq!(protected["kid"], "http://localhost/acct/1");
+        assert!(protected.get("jwk").is_none());
+        assert_eq!(jws.payload, "");
+    }
+
+    #[test]
+    fn build_csr() {
+        let key = PKey::from_ec_key(generate_key().unwrap()).unwrap();
+        let domains = vec!["example.com".to_owned(), "*.example.com".to_owned()];
+        let req = X509Req::from_der(&csr(&domains, &key).unwrap()).unwrap();
+        assert!(req.verify(&key).unwrap());
+        let cn = req
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .unwrap()
+            .data()
+            .as_utf8()
+            .unwrap();
+        assert_eq!(cn.to_string(), "example.com");
+        let text = String::from_utf8(req.to_text().unwrap()).unwrap();
+        assert!(text.contains("DNS:example.com, DNS:*.example.com"));
+    }
+
+    /// Records the names and values of changes instead of sending them.
+    #[derive(Default)]
+    struct MockApi {
+        updates: RefCell<Vec<(String, String)>>,
+        deletes: RefCell<Vec<(String, Option<String>)>>,
+    }
+
+    impl Api for MockApi {
+        fn update(&self, update: &Update) -> bind9_api_client::Result<Outcome> {
+            self.updates
+                .borrow_mut()
+                .push((update.name().to_owned(), update.value().to_owned()));
+            Ok(Outcome::Applied)
+        }
+
+        fn delete(&self, delete: &Delete) -> bind9_api_client::Result<Outcome> {
+            self.deletes.borrow_mut().push((
+                delete.name().to_owned(),
+                delete.value().map(str::to_owned),
+            ));
+            Ok(Outcome::Applied)
+        }
+
+        fn batch(&self, _: &Batch) -> bind9_api_client::Result<Outcome> {
+            unimplemented!()
+        }
+
+        fn read(&self, _: &str, _: Record) -> bind9_api_client::Result<Vec<ResourceRecord>> {
+            unimplemented!()
+        }
+
+        fn wait(&self, _: &Wait) -> bind9_api_client::Result<Propagation> {
+            unimplemented!()
+        }
+
+        fn leases(&self) -> bind9_api_client::Result<Vec<Lease>> {
+            unimplemented!()
+        }
+    }
+
+    /// Serves an ACME server whose only authorization fails validation.
+    fn acme_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let polls = Arc::new(AtomicUsize::new(0));
+        let url = base.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split_whitespace().nth(1).unwrap_or("").to_owned();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    let header = header.to_lowercase();
+                    if let Some(value) = header.strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                reader.take(length).read_to_end(&mut Vec::new()).unwrap();
+                let status = if polls.load(Ordering::SeqCst) > 0 { "invalid" } else { "pending" };
+                let (code, location, body) = match path.as_str() {
+                    "/directory" => (
+                        "200 OK",
+                        String::new(),
+                        format!(
+                            r#"{{"newNonce": "{0}/nonce", "newAccount": "{0}/account",
+                            "newOrder": "{0}/order"}}"#,
+                            url
+                        ),
+                    ),
+                    "/account" => ("201 Created", format!("{}/acct/1", url), "{}".to_owned()),
+                    "/order" => (
+                        "201 Created",
+                        format!("{}/order/1", url),
+                        format!(
+                            r#"{{"status": "pending", "authorizations": ["{0}/authz/1"],
+                            "finalize": "{0}/finalize"}}"#,
+                            url
+                        ),
+                    ),
+                    "/authz/1" => {
+                        if status == "pending" && line.starts_with("POST") {
+                            polls.fetch_add(1, Ordering::SeqCst);
+                        }
+                        (
+                            "200 OK",
+                            String::new(),
+                            format!(
+                                r#"{{"identifier": {{"type": "dns", "value": "example.com"}},
+                                "status": "{}", "challenges": [{{"type": "dns-01",
+                                "url": "{}/chall/1", "token": "token"}}]}}"#,
+                                status, url
+                            ),
+                        )
+                    }
+                    _ => ("200 OK", String::new(), "{}".to_owned()),
+                };
+                let location = if location.is_empty() {
+                    String::new()
+                } else {
+                    format!("Location: {}\r\n", location)
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nReplay-Nonce: nonce\r\n{}Content-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    code,
+                    location,
+                    body.len(),
+                    body
+                ).unwrap();
+            }
+        });
+        base
+    }
+
+    #[test]
+    fn remove_challenges_on_failure() {
+        let out = ::std::env::temp_dir().join("bind9-api-acme-test");
+        let _ = fs::remove_dir_all(&out);
+        let directory = format!("{}/directory", acme_server());
+        let api = MockApi::default();
+        let options = Options::new(&directory, vec!["example.com".to_owned()], out.clone(), 0);
+        assert!(issue(&api, &options).is_err());
+
+        let updates = api.updates.borrow();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].0, "_acme-challenge.example.com");
+        assert_eq!(
+            *api.deletes.borrow(),
+            vec![(updates[0].0.clone(), Some(updates[0].1.clone()))]
+        );
+        assert!(!out.join("cert.pem").exists());
+        fs::remove_dir_all(&out).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Subcommands for the admin API to manage tokens at runtime.
+
+use bind9_api_client::Client;
+use data::{NewToken, TokenInfo};
+use std::time::{SystemTime, UNIX_EPOCH};
+use Result;
+
+fn now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Converts a duration in seconds into a UNIX timestamp.
+pub fn from_now(seconds: u64) -> Result<u64> {
+    Ok(now()? + seconds)
+}
+
+fn print(token: &TokenInfo) {
+    let records: Vec<_> = token.records().iter().map(|r| r.to_string()).collect();
+    let operations: Vec<_> = token
+        .operations()
+        .iter()
+        .map(|o| format!("{:?}", o).to_lowercase())
+        .collect();
+    println!(
+        "{} {:?}{} names={} records={} operations={} created={} expires={} {}",
+        token.name(),
+        token.kind(),
+        if token.admin() { " admin" } else { "" },
+        token.names().join(","),
+        if records.is_empty() {
+            "*".to_owned()
+        } else {
+            records.join(",")
+        },
+        if operations.is_empty() {
+            "update,delete".to_owned()
+        } else {
+            operations.join(",")
+        },
+        token.created(),
+        token
+            .not_after()
+            .map_or_else(|| "never".to_owned(), |t| t.to_string()),
+        if token.revoked() {
+            "revoked"
+        } else if token.active(now().unwrap_or(0)) {
+            "active"
+        } else {
+            "expired"
+        }
+    );
+}
+
+/// Creates a token and prints the generated password or secret, which cannot
+/// be retrieved later.
+pub fn create(client: &Client, token: &NewToken) -> Result<()> {
+    let created = client.create_token(token)?;
+    print(created.token());
+    if let Some(secret) = created.secret() {
+        println!("{}", secret);
+    }
+    Ok(())
+}
+
+pub fn list(client: &Client) -> Result<()> {
+    client.tokens()?.iter().for_each(print);
+    Ok(())
+}
+
+pub fn show(client: &Client, name: &str) -> Result<()> {
+    print(&client.token(name)?);
+    Ok(())
+}
+
+pub fn revoke(client: &Client, name: &str) -> Result<()> {
+    print(&client.revoke_token(name)?);
+    Ok(())
+}
+
+/// Lets a token expire at `not_after` or immediately.
+pub fn expire(client: &Client, name: &str, not_after: Option<u64>) -> Result<()> {
+    print(&client.expire_token(name, not_after)?);
+    Ok(())
+}
@@ -0,0 +1,152 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signing requests using a key held by an SSH agent (`SSH_AUTH_SOCK`). The
+//! signatures use the `sshsig` format, see `crypto::sshsig`.
+
+use failure::Error;
+use std::{
+    env, fs,
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+};
+
+type Result<T> = ::std::result::Result<T, Error>;
+
+const FAILURE: u8 = 5;
+const REQUEST_IDENTITIES: u8 = 11;
+const IDENTITIES_ANSWER: u8 = 12;
+const SIGN_REQUEST: u8 = 13;
+const SIGN_RESPONSE: u8 = 14;
+/// Requests `rsa-sha2-512` instead of SHA1 signatures from RSA keys.
+const RSA_SHA2_512: u32 = 4;
+
+#[derive(Debug, Fail)]
+enum AgentError {
+    #[fail(display = "SSH_AUTH_SOCK is not set")]
+    NoAgent,
+    #[fail(display = "Invalid SSH public key in {}", _0)]
+    InvalidKey(String),
+    #[fail(display = "The SSH agent does not hold the key {}", _0)]
+    UnknownKey(String),
+    #[fail(display = "The SSH agent refused to sign the request")]
+    Refused,
+    #[fail(display = "Malformed response from the SSH agent")]
+    Malformed,
+}
+
+fn put_string(buf: &mut Vec<u8>, value: &[u8]) {
+    buf.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    buf.extend_from_slice(value);
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(AgentError::Malformed.into());
+    }
+    let (value, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(value)
+}
+
+fn take_u32(buf: &mut &[u8]) -> Result<u32> {
+    Ok(take(buf, 4)?.iter().fold(0, |acc, &b| (acc << 8) | u32::from(b)))
+}
+
+fn take_string<'a>(buf: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = take_u32(buf)? as usize;
+    take(buf, len)
+}
+
+/// Reads the public key from an OpenSSH public key file (`id_ed25519.pub`).
+pub fn read_public_key(path: &str) -> Result<Vec<u8>> {
+    let line = fs::read_to_string(path)?;
+    line.split_whitespace()
+        .nth(1)
+        .and_then(|key| ::base64::decode(key).ok())
+        .ok_or_else(|| AgentError::InvalidKey(path.to_owned()).into())
+}
+
+/// Sends a message to the agent and returns the response.
+fn call(stream: &mut UnixStream, msg: &[u8]) -> Result<Vec<u8>> {
+    stream.write_all(&(msg.len() as u32).to_be_bytes())?;
+    stream.write_all(msg)?;
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let mut response = vec![0; take_u32(&mut &len[..])? as usize];
+    stream.read_exact(&mut response)?;
+    Ok(response)
+}
+
+/// Signs `msg` using the agent's key `public_key` and returns the `sshsig`
+/// blob.
+pub fn sign(public_key: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+    let path = env::var("SSH_AUTH_SOCK").map_err(|_| AgentError::NoAgent)?;
+    let mut stream = UnixStream::connect(path)?;
+
+    let response = call(&mut stream, &[REQUEST_IDENTITIES])?;
+    let mut buf = &response[..];
+    if take(&mut buf, 1)? != [IDENTITIES_ANSWER] {
+        return Err(AgentError::Malformed.into());
+    }
+    let mut found = false;
+    for _ in 0..take_u32(&mut buf)? {
+        found |= take_string(&mut buf)? == public_key;
+        take_string(&mut buf)?;
+    }
+    if !found {
+        return Err(AgentError::UnknownKey(::crypto::sshsig::key_id(public_key)).into());
+    }
+
+    let mut request = vec![SIGN_REQUEST];
+    put_string(&mut request, public_key);
+    put_string(&mut request, &::crypto::sshsig::signed_data(msg));
+    let flags = match ::crypto::sshsig::key_type(public_key) {
+        Some("ssh-rsa") => RSA_SHA2_512,
+        _ => 0,
+    };
+    request.extend_from_slice(&flags.to_be_bytes());
+    let response = call(&mut stream, &request)?;
+    let mut buf = &response[..];
+    match take(&mut buf, 1)? {
+        [SIGN_RESPONSE] => Ok(::crypto::sshsig::encode(public_key, take_string(&mut buf)?)),
+        [FAILURE] => Err(AgentError::Refused.into()),
+        _ => Err(AgentError::Malformed.into()),
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The async client, based on futures.
+
+use data::{Batch, Delete, Lease, Propagation, Record, ResourceRecord, Update, Wait};
+use futures::{future, Future, Stream};
+use request::{self, Method};
+use reqwest::{async, header::CONTENT_TYPE};
+use std::time::Duration;
+use {ClientError, Config, Outcome, Result};
+
+/// Future returned by the async client.
+pub type Response<T> = Box<dyn Future<Item = T, Error = ClientError> + Send>;
+
+/// Operations on records, returning futures.
+pub trait AsyncApi {
+    /// Creates or updates a record.
+    fn update(&self, update: &Update) -> Response<Outcome>;
+
+    /// Deletes a RRset or a single value of it.
+    fn delete(&self, delete: &Delete) -> Response<Outcome>;
+
+    /// Applies deletes and updates atomically.
+    fn batch(&self, batch: &Batch) -> Response<Outcome>;
+
+    /// Returns the RRset as it is served by the primary server.
+    fn read(&self, name: &str, record: Record) -> Response<Vec<ResourceRecord>>;
+
+    /// Waits until the RRset reached the expected state on all authoritative
+    /// servers.
+    fn wait(&self, wait: &Wait) -> Response<Propagation>;
+
+    /// Returns the active leases.
+    fn leases(&self) -> Response<Vec<Lease>>;
+}
+
+/// Sends requests to the API without blocking. The futures have to be run on
+/// a tokio runtime. Signing requests using the SSH agent still blocks until the
+/// agent responds.
+pub struct AsyncClient {
+    config: Config,
+    identity: Option<Vec<u8>>,
+    ca_cert: Option<Vec<u8>>,
+}
+
+impl AsyncClient {
+    /// Creates a new AsyncClient object. Fails if the client certificate or the
+    /// CA certificate cannot be loaded.
+    pub fn new(config: Config) -> Result<Self> {
+        let identity = request::identity(&config)?;
+        let ca_cert = request::ca_cert(&config)?;
+        Ok(Self {
+            config,
+            identity,
+            ca_cert,
+        })
+    }
+
+    /// Returns a reference to the configuration.
+    #[inline]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn http(&self, wait: Option<u32>) -> Result<async::Client> {
+        let mut client = async::Client::builder();
+        if let Some(wait) = wait {
+            client = client.timeout(Duration::from_secs(u64::from(wait) + 30));
+        }
+        if let Some(ref identity) = self.identity {
+            client = client.identity(reqwest::Identity::from_pkcs12_der(identity, "")?);
+        }
+        if let Some(ref ca_cert) = self.ca_cert {
+            client = client.add_root_certificate(reqwest::Certificate::from_pem(ca_cert)?);
+        }
+        Ok(client.build()?)
+    }
+
+    fn call<D: ::serde::Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        data: Option<&D>,
+        wait: Option<u32>,
+    ) -> Response<String> {
+        let prepared = request::prepare(&self.config, method, path, query, data);
+        let (prepared, client) = match prepared.and_then(|p| Ok((p, self.http(wait)?))) {
+            Ok(ok) => ok,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        let mut builder = match prepared.method {
+            Method::GET => client.get(prepared.url),
+            Method::POST => client.post(prepared.url),
+            Method::DELETE => client.delete(prepared.url),
+        };
+        for (name, value) in prepared.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = prepared.body {
+            builder = builder.header(CONTENT_TYPE, "application/json").body(body);
+        }
+        Box::new(builder.send().from_err().and_then(|res| {
+            let status = res.status();
+            res.into_body().concat2().from_err().and_then(move |body| {
+                request::check(status, String::from_utf8_lossy(&body).into_owned())
+            })
+        }))
+    }
+
+    fn call_json<D, T>(&self, method: Method, path: &str, query: &[(&str, &str)], data: Option<&D>) -> Response<T>
+    where
+        D: ::serde::Serialize,
+        T: ::serde::de::DeserializeOwned + Send + 'static,
+    {
+        Box::new(
+            self.call(method, path, query, data, None)
+                .and_then(|body| Ok(::serde_json::from_str(&body)?)),
+        )
+    }
+
+    fn change<D: ::serde::Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        data: &D,
+        dry_run: bool,
+        wait: Option<u32>,
+    ) -> Response<Outcome> {
+        Box::new(
+            self.call(method, path, &[], Some(data), wait)
+                .and_then(move |body| request::outcome(&body, dry_run, wait)),
+        )
+    }
+}
+
+impl AsyncApi for AsyncClient {
+    fn update(&self, update: &Update) -> Response<Outcome> {
+        self.change(Method::POST, "/record", update, update.dry_run(), update.wait())
+    }
+
+    fn delete(&self, delete: &Delete) -> Response<Outcome> {
+        self.change(Method::DELETE, "/record", delete, delete.dry_run(), delete.wait())
+    }
+
+    fn batch(&self, batch: &Batch) -> Response<Outcome> {
+        self.change(Method::POST, "/batch", batch, batch.dry_run(), None)
+    }
+
+    fn read(&self, name: &str, record: Record) -> Response<Vec<ResourceRecord>> {
+        let record = record.to_string();
+        self.call_json::<(), _>(
+            Method::GET,
+            "/record",
+            &[("name", name), ("record", &record)],
+            None,
+        )
+    }
+
+    fn wait(&self, wait: &Wait) -> Response<Propagation> {
+        Box::new(
+            self.call(Method::POST, "/wait", &[], Some(wait), Some(wait.timeout()))
+                .and_then(|body| Ok(::serde_json::from_str(&body)?)),
+        )
+    }
+
+    fn leases(&self) -> Response<Vec<Lease>> {
+        self.call_json::<(), _>(Method::GET, "/leases", &[], None)
+    }
+}
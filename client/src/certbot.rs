@@ -0,0 +1,89 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hook mode for certbot's `--manual-auth-hook` and `--manual-cleanup-hook`.
+
+use bind9_api_client::{Api, Client};
+use data::{Delete, Record, Update};
+use std::env;
+use Result;
+
+const TTL: u32 = 60;
+
+#[derive(Debug, Fail)]
+enum CertbotError {
+    #[fail(display = "Missing environment variable {}", _0)]
+    MissingVariable(&'static str),
+}
+
+fn var(name: &'static str) -> Result<String> {
+    env::var(name).map_err(|_| CertbotError::MissingVariable(name).into())
+}
+
+fn challenge_name() -> Result<String> {
+    Ok(format!("_acme-challenge.{}", var("CERTBOT_DOMAIN")?))
+}
+
+/// Publishes the validation value of the current challenge. Propagation is only
+/// awaited for the last challenge: secondaries transfer the zone serial by
+/// serial, so once the last value is visible, all previous ones are as well.
+pub fn auth(client: &Client, dry_run: bool, wait: Option<u32>) -> Result<()> {
+    let remaining: u32 = env::var("CERTBOT_REMAINING_CHALLENGES")
+        .ok()
+        .and_then(|r| r.parse().ok())
+        .unwrap_or(0);
+    let mut update = Update::new(
+        challenge_name()?,
+        var("CERTBOT_VALIDATION")?,
+        Record::TXT,
+        TTL,
+    );
+    update.set_dry_run(dry_run);
+    update.set_wait(if remaining == 0 { wait } else { None });
+    ::report(&client.update(&update)?)
+}
+
+/// Removes the validation value of the current challenge. Values of other
+/// challenges for the same name are left alone.
+pub fn cleanup(client: &Client, dry_run: bool) -> Result<()> {
+    let mut delete = Delete::new(challenge_name()?, Record::TXT);
+    delete.set_value(Some(var("CERTBOT_VALIDATION")?));
+    delete.set_dry_run(dry_run);
+    ::report(&client.delete(&delete)?)
+}
@@ -43,15 +43,124 @@ pub fn parse_cli() -> ::clap::ArgMatches<'static> {
         (author: crate_authors!())
         (about: crate_description!())
         (@arg CONFIG: -c --config +takes_value "Path to config file (Defaults to /etc/bind9apiclient.toml)")
-        (@arg DOMAIN: -d --domain +takes_value +required "Domain to create")
-        (@arg RECORD: -r --record +takes_value "The record type (Defaults to TXT)")
+        (@arg PROFILE: -p --profile +takes_value "Profile to use (Defaults to the profile matching the domain)")
+        (@arg DOMAIN: -d --domain +takes_value "Domain to create (Required for update and delete)")
+        (@arg RECORD: -r --record +takes_value "The record type (Defaults to the profile's record type or TXT)")
+        (@arg DRYRUN: -n --("dry-run") "Print the planned operation without applying it")
+        (@arg WAIT: -w --wait +takes_value "Wait up to this many seconds until the change is visible on all authoritative servers")
         (@subcommand update =>
             (about: "Creates a new record")
             (@arg VALUE: -v --value +takes_value +required "Value to write in the record")
-            (@arg TTL: -t --ttl + takes_value "TTL of the record (Defaults to 8640)")
+            (@arg TTL: -t --ttl + takes_value "TTL of the record (Defaults to the profile's TTL or 8640)")
+            (@arg LEASE: -l --lease +takes_value "Let the server delete the record after this many seconds")
         )
         (@subcommand delete =>
             (about: "Deletes a record")
         )
+        (@subcommand plan =>
+            (about: "Shows the changes needed to reach the RRsets in a records file")
+            (@arg FILE: +required "Path to the records file (TOML, or YAML if ending in .yaml or .yml)")
+        )
+        (@subcommand apply =>
+            (about: "Applies the changes needed to reach the RRsets in a records file as one batch")
+            (@arg FILE: +required "Path to the records file (TOML, or YAML if ending in .yaml or .yml)")
+        )
+        (@subcommand certbot =>
+            (about: "Hook for certbot's manual DNS-01 challenge mode, reads the CERTBOT_* environment variables")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand auth =>
+                (about: "Publishes the challenge (--manual-auth-hook)")
+            )
+            (@subcommand cleanup =>
+                (about: "Removes the challenge (--manual-cleanup-hook)")
+            )
+        )
+        (@subcommand present =>
+            (about: "Creates a challenge TXT record (lego exec provider)")
+            (@arg FQDN: +required "Name of the TXT record")
+            (@arg VALUE: +required "Value of the TXT record")
+        )
+        (@subcommand cleanup =>
+            (about: "Removes a challenge TXT record (lego exec provider)")
+            (@arg FQDN: +required "Name of the TXT record")
+            (@arg VALUE: +required "Value of the TXT record")
+        )
+        (@subcommand timeout =>
+            (about: "Prints the propagation timeout and polling interval as JSON (lego exec provider)")
+            (@arg TIMEOUT: -t --timeout +takes_value default_value("120") "Propagation timeout in seconds")
+            (@arg INTERVAL: -i --interval +takes_value default_value("5") "Polling interval in seconds")
+        )
+        (@subcommand leases =>
+            (about: "Lists records with an active lease")
+        )
+        (@subcommand keygen =>
+            (about: "Generates an Ed25519 key pair for request signing and prints the public key")
+            (@arg OUT: +required "Path to write the private key to")
+        )
+        (@subcommand delegate =>
+            (about: "Mints a short-lived token with a subset of the scope of the configured token and prints a config file for it")
+            (@arg PATTERN: -p --pattern +takes_value +multiple number_of_values(1) +required "Name the token may modify, `*.` matches all names below a domain")
+            (@arg RECORDS: --records +takes_value +multiple number_of_values(1) "Record type the token may modify (Defaults to the types of the configured token)")
+            (@arg OPERATIONS: --operations +takes_value +multiple number_of_values(1) possible_value[update delete override] "Operation the token may perform (Defaults to the operations of the configured token)")
+            (@arg TTL: --ttl +takes_value default_value("600") "Lifetime of the token in seconds")
+        )
+        (@subcommand admin =>
+            (about: "Uses the admin API")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand token =>
+                (about: "Manages tokens")
+                (@setting SubcommandRequiredElseHelp)
+                (@subcommand create =>
+                    (about: "Creates a token and prints the generated password or secret")
+                    (@arg NAME: +required "Name of the token, used as user name or key id")
+                    (@arg KIND: -k --kind +takes_value possible_value[password secret ed25519] default_value("secret") "Kind of credential")
+                    (@arg PATTERN: -p --pattern +takes_value +multiple number_of_values(1) +required "Name the token may modify, `*.` matches all names below a domain")
+                    (@arg RECORDS: --records +takes_value +multiple number_of_values(1) "Record type the token may modify (Defaults to all types)")
+                    (@arg OPERATIONS: --operations +takes_value +multiple number_of_values(1) possible_value[update delete override] "Operation the token may perform (Defaults to update and delete)")
+                    (@arg ADMIN: --admin "Allow the token to use the admin API")
+                    (@arg PUBLICKEY: --("public-key") +takes_value "Hex encoded public key of ed25519 tokens")
+                    (@arg EXPIRES: -e --expires +takes_value "Let the token expire after this many seconds")
+                )
+                (@subcommand list =>
+                    (about: "Lists all tokens")
+                )
+                (@subcommand show =>
+                    (about: "Shows a token")
+                    (@arg NAME: +required "Name of the token")
+                )
+                (@subcommand revoke =>
+                    (about: "Revokes a token")
+                    (@arg NAME: +required "Name of the token")
+                )
+                (@subcommand expire =>
+                    (about: "Lets a token expire")
+                    (@arg NAME: +required "Name of the token")
+                    (@arg IN: --in +takes_value "Expire after this many seconds (Defaults to now)")
+                )
+            )
+        )
+        (@subcommand cert =>
+            (about: "Obtains certificates via ACME, solving DNS-01 challenges through the API")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand issue =>
+                (about: "Issues a new certificate")
+                (@arg NAME: -d --domain +takes_value +multiple number_of_values(1) +required "Domain to include in the certificate, may be given multiple times")
+                (@arg DIRECTORY: --directory +takes_value "ACME directory URL (Defaults to Let's Encrypt)")
+                (@arg EMAIL: --email +takes_value "Contact address for the ACME account")
+                (@arg ACCOUNTKEY: --("account-key") +takes_value "Path to the account key, created if missing (Defaults to <out>/account.pem)")
+                (@arg OUT: -o --out +takes_value +required "Directory to write the key and certificates to")
+                (@arg CACERT: --("ca-cert") +takes_value "Additional root certificate to trust for the ACME server, e.g. for Pebble")
+            )
+            (@subcommand renew =>
+                (about: "Reissues the certificate in the output directory if it expires soon")
+                (@arg NAME: -d --domain +takes_value +multiple number_of_values(1) "Domain to include in the certificate (Defaults to the names of the existing certificate)")
+                (@arg DIRECTORY: --directory +takes_value "ACME directory URL (Defaults to Let's Encrypt)")
+                (@arg EMAIL: --email +takes_value "Contact address for the ACME account")
+                (@arg ACCOUNTKEY: --("account-key") +takes_value "Path to the account key, created if missing (Defaults to <out>/account.pem)")
+                (@arg OUT: -o --out +takes_value +required "Directory containing the certificate")
+                (@arg CACERT: --("ca-cert") +takes_value "Additional root certificate to trust for the ACME server, e.g. for Pebble")
+                (@arg DAYS: --days +takes_value default_value("30") "Renew if the certificate expires within this many days")
+            )
+        )
     ).get_matches()
 }
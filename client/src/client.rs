@@ -0,0 +1,206 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The blocking client.
+
+use data::{
+    Batch, CreatedToken, DelegatedToken, Delegation, Delete, Expiry, Lease, NewToken,
+    Propagation, Record, ResourceRecord, TokenInfo, Update, Wait,
+};
+use request::{self, Method};
+use reqwest::header::CONTENT_TYPE;
+use std::time::Duration;
+use {Api, Config, Outcome, Result};
+
+/// Sends blocking requests to the API.
+pub struct Client {
+    config: Config,
+    identity: Option<Vec<u8>>,
+    ca_cert: Option<Vec<u8>>,
+}
+
+impl Client {
+    /// Creates a new Client object. Fails if the client certificate or the CA
+    /// certificate cannot be loaded.
+    pub fn new(config: Config) -> Result<Self> {
+        let identity = request::identity(&config)?;
+        let ca_cert = request::ca_cert(&config)?;
+        Ok(Self {
+            config,
+            identity,
+            ca_cert,
+        })
+    }
+
+    /// Returns a reference to the configuration.
+    #[inline]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn http(&self, wait: Option<u32>) -> Result<reqwest::Client> {
+        let mut client = reqwest::Client::builder();
+        if let Some(wait) = wait {
+            // the server holds the request open while waiting for propagation
+            client = client.timeout(Duration::from_secs(u64::from(wait) + 30));
+        }
+        if let Some(ref identity) = self.identity {
+            client = client.identity(reqwest::Identity::from_pkcs12_der(identity, "")?);
+        }
+        if let Some(ref ca_cert) = self.ca_cert {
+            client = client.add_root_certificate(reqwest::Certificate::from_pem(ca_cert)?);
+        }
+        Ok(client.build()?)
+    }
+
+    fn call<D: ::serde::Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        data: Option<&D>,
+        wait: Option<u32>,
+    ) -> Result<String> {
+        let prepared = request::prepare(&self.config, method, path, query, data)?;
+        let client = self.http(wait)?;
+        let mut builder = match prepared.method {
+            Method::GET => client.get(prepared.url),
+            Method::POST => client.post(prepared.url),
+            Method::DELETE => client.delete(prepared.url),
+        };
+        for (name, value) in prepared.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = prepared.body {
+            builder = builder.header(CONTENT_TYPE, "application/json").body(body);
+        }
+        let mut res = builder.send()?;
+        let body = res.text()?;
+        request::check(res.status(), body)
+    }
+
+    fn call_json<D: ::serde::Serialize, T: ::serde::de::DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        data: Option<&D>,
+    ) -> Result<T> {
+        Ok(::serde_json::from_str(
+            &self.call(method, path, &[], data, None)?,
+        )?)
+    }
+
+    /// Mints a delegated token with a subset of the scope of the client's
+    /// credentials.
+    pub fn delegate(&self, delegation: &Delegation) -> Result<DelegatedToken> {
+        self.call_json(Method::POST, "/tokens/delegate", Some(delegation))
+    }
+
+    /// Creates a token using the admin API. The generated password or secret
+    /// is only returned once.
+    pub fn create_token(&self, token: &NewToken) -> Result<CreatedToken> {
+        self.call_json(Method::POST, "/admin/tokens", Some(token))
+    }
+
+    /// Returns all tokens managed using the admin API.
+    pub fn tokens(&self) -> Result<Vec<TokenInfo>> {
+        self.call_json::<(), _>(Method::GET, "/admin/tokens", None)
+    }
+
+    /// Returns a single token.
+    pub fn token(&self, name: &str) -> Result<TokenInfo> {
+        self.call_json::<(), _>(Method::GET, &token_path(name), None)
+    }
+
+    /// Revokes a token.
+    pub fn revoke_token(&self, name: &str) -> Result<TokenInfo> {
+        self.call_json::<(), _>(Method::DELETE, &token_path(name), None)
+    }
+
+    /// Lets a token expire at `not_after` (a UNIX timestamp) or immediately.
+    pub fn expire_token(&self, name: &str, not_after: Option<u64>) -> Result<TokenInfo> {
+        self.call_json(
+            Method::POST,
+            &format!("{}/expire", token_path(name)),
+            Some(&Expiry::new(not_after)),
+        )
+    }
+}
+
+fn token_path(name: &str) -> String {
+    format!("/admin/tokens/{}", name)
+}
+
+impl Api for Client {
+    fn update(&self, update: &Update) -> Result<Outcome> {
+        let body = self.call(Method::POST, "/record", &[], Some(update), update.wait())?;
+        request::outcome(&body, update.dry_run(), update.wait())
+    }
+
+    fn delete(&self, delete: &Delete) -> Result<Outcome> {
+        let body = self.call(Method::DELETE, "/record", &[], Some(delete), delete.wait())?;
+        request::outcome(&body, delete.dry_run(), delete.wait())
+    }
+
+    fn batch(&self, batch: &Batch) -> Result<Outcome> {
+        let body = self.call(Method::POST, "/batch", &[], Some(batch), None)?;
+        request::outcome(&body, batch.dry_run(), None)
+    }
+
+    fn read(&self, name: &str, record: Record) -> Result<Vec<ResourceRecord>> {
+        let record = record.to_string();
+        let body = self.call::<()>(
+            Method::GET,
+            "/record",
+            &[("name", name), ("record", &record)],
+            None,
+            None,
+        )?;
+        Ok(::serde_json::from_str(&body)?)
+    }
+
+    fn wait(&self, wait: &Wait) -> Result<Propagation> {
+        let body = self.call(Method::POST, "/wait", &[], Some(wait), Some(wait.timeout()))?;
+        Ok(::serde_json::from_str(&body)?)
+    }
+
+    fn leases(&self) -> Result<Vec<Lease>> {
+        self.call_json::<(), _>(Method::GET, "/leases", None)
+    }
+}
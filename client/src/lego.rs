@@ -0,0 +1,84 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Calling convention of the lego `exec` DNS provider:
+//! `present|cleanup <fqdn> <value>` and `timeout`.
+
+use bind9_api_client::{Api, Client};
+use data::{Delete, Record, Update};
+use Result;
+
+const TTL: u32 = 60;
+
+#[derive(Serialize)]
+struct Timeout {
+    timeout: u32,
+    interval: u32,
+}
+
+/// Creates the challenge TXT record.
+pub fn present(
+    client: &Client,
+    fqdn: &str,
+    value: &str,
+    dry_run: bool,
+    wait: Option<u32>,
+) -> Result<()> {
+    let mut update = Update::new(fqdn.to_owned(), value.to_owned(), Record::TXT, TTL);
+    update.set_dry_run(dry_run);
+    update.set_wait(wait);
+    ::report(&client.update(&update)?)
+}
+
+/// Removes the value of the challenge TXT record.
+pub fn cleanup(client: &Client, fqdn: &str, value: &str, dry_run: bool) -> Result<()> {
+    let mut delete = Delete::new(fqdn.to_owned(), Record::TXT);
+    delete.set_value(Some(value.to_owned()));
+    delete.set_dry_run(dry_run);
+    ::report(&client.delete(&delete)?)
+}
+
+/// Prints the propagation timeout and polling interval lego should use.
+pub fn timeout(timeout: u32, interval: u32) -> Result<()> {
+    println!(
+        "{}",
+        ::serde_json::to_string(&Timeout { timeout, interval })?
+    );
+    Ok(())
+}
@@ -0,0 +1,462 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+#![deny(missing_docs)]
+
+//! Client library for the BIND9 API.
+//!
+//! [`Client`](struct.Client.html) sends blocking requests,
+//! [`AsyncClient`](struct.AsyncClient.html) returns futures. The record
+//! operations of both are defined by the [`Api`](trait.Api.html) and
+//! [`AsyncApi`](trait.AsyncApi.html) traits, so code using the API can be
+//! tested against a mock implementation.
+//!
+//! ```no_run
+//! # extern crate bind9_api_client;
+//! # extern crate data;
+//! use bind9_api_client::{Api, Client, Config};
+//! use data::{Record, Update};
+//!
+//! # fn main() -> Result<(), bind9_api_client::ClientError> {
+//! let client = Client::new(Config::open("/etc/bind9apiclient.toml")?)?;
+//! client.update(&Update::new(
+//!     "www.example.com".to_owned(),
+//!     "127.0.0.1".to_owned(),
+//!     Record::A,
+//!     3600,
+//! ))?;
+//! # Ok(())
+//! # }
+//! ```
+
+extern crate base64;
+extern crate crypto;
+extern crate data;
+#[macro_use]
+extern crate failure;
+extern crate futures;
+#[macro_use]
+extern crate log;
+extern crate openssl;
+extern crate reqwest;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
+
+mod agent;
+mod async_client;
+mod client;
+mod request;
+
+pub use async_client::{AsyncApi, AsyncClient, Response};
+pub use client::Client;
+
+use data::{Batch, Delete, Lease, Propagation, Record, ResourceRecord, Update, Wait};
+use std::{collections::BTreeMap, fs, path::Path, str::FromStr};
+
+/// Result type of the client.
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// Errors returned by the client.
+#[derive(Debug, Fail)]
+pub enum ClientError {
+    /// The request was rejected as invalid (400)
+    #[fail(display = "Bad request: {}", _0)]
+    BadRequest(String),
+    /// The request could not be authenticated (401)
+    #[fail(display = "Unauthorized: {}", _0)]
+    Unauthorized(String),
+    /// The credentials do not allow the request (403)
+    #[fail(display = "Forbidden: {}", _0)]
+    Forbidden(String),
+    /// The resource does not exist or the feature is disabled (404)
+    #[fail(display = "Not found: {}", _0)]
+    NotFound(String),
+    /// The resource already exists (409)
+    #[fail(display = "Conflict: {}", _0)]
+    Conflict(String),
+    /// Any other unsuccessful status code
+    #[fail(display = "Server error {}: {}", _0, _1)]
+    Status(u16, String),
+    /// The request could not be sent or the response not be received
+    #[fail(display = "Request failed: {}", _0)]
+    Http(#[cause] reqwest::Error),
+    /// The response could not be parsed
+    #[fail(display = "Invalid response: {}", _0)]
+    InvalidResponse(#[cause] serde_json::Error),
+    /// The configuration could not be read
+    #[fail(display = "Invalid configuration: {}", _0)]
+    Config(String),
+    /// The request could not be signed or the client certificate not be loaded
+    #[fail(display = "Cannot authenticate request: {}", _0)]
+    Credentials(String),
+}
+
+impl ClientError {
+    fn credentials<E: ::std::fmt::Display>(e: E) -> Self {
+        ClientError::Credentials(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(e: serde_json::Error) -> Self {
+        ClientError::InvalidResponse(e)
+    }
+}
+
+/// Server and credentials, usually read from `/etc/bind9apiclient.toml`:
+///
+/// ```toml
+/// host = "http://127.0.0.1:8080"
+/// secret = "topsecret"
+/// ```
+///
+/// Instead of the shared secret, requests can be signed using a key id and
+/// secret (`key_id`, `secret`), an Ed25519 key (`key_id`, `private_key`) or an
+/// SSH key in the agent (`ssh_key`), or be authenticated by an OIDC token
+/// (`bearer_token_env`) or TLS client certificate (`client_cert`,
+/// `client_key`). `ca_cert` adds a CA certificate that is trusted for the
+/// server's certificate.
+///
+/// `ttl` and `record` are the defaults used by the command line client,
+/// `domains` lists the domain suffixes that select the profile (see
+/// [`Profiles`](struct.Profiles.html)).
+#[derive(Deserialize, Clone, Debug)]
+pub struct Config {
+    host: String,
+    #[serde(default)]
+    secret: String,
+    #[serde(default)]
+    key_id: Option<String>,
+    #[serde(default)]
+    private_key: Option<String>,
+    #[serde(default)]
+    ssh_key: Option<String>,
+    #[serde(default)]
+    bearer_token_env: Option<String>,
+    #[serde(default)]
+    client_cert: Option<String>,
+    #[serde(default)]
+    client_key: Option<String>,
+    #[serde(default)]
+    ca_cert: Option<String>,
+    #[serde(default)]
+    ttl: Option<u32>,
+    #[serde(default)]
+    record: Option<Record>,
+    #[serde(default)]
+    domains: Vec<String>,
+}
+
+impl Config {
+    /// Creates a new Config object for requests signed with the shared
+    /// secret.
+    pub fn new(host: String, secret: String) -> Self {
+        Self {
+            host,
+            secret,
+            key_id: None,
+            private_key: None,
+            ssh_key: None,
+            bearer_token_env: None,
+            client_cert: None,
+            client_key: None,
+            ca_cert: None,
+            ttl: None,
+            record: None,
+            domains: Vec::new(),
+        }
+    }
+
+    /// Reads the configuration from a TOML file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config = fs::read_to_string(path).map_err(|e| ClientError::Config(e.to_string()))?;
+        toml::from_str(&config).map_err(|e| ClientError::Config(e.to_string()))
+    }
+
+    /// Sets the key id, that selects the secret or key on the server.
+    pub fn set_key_id(&mut self, key_id: Option<String>) {
+        self.key_id = key_id;
+    }
+
+    /// Signs requests with the PKCS#8 encoded Ed25519 key in the file `path`.
+    pub fn set_private_key(&mut self, path: Option<String>) {
+        self.private_key = path;
+    }
+
+    /// Signs requests using the SSH agent, with the key whose public key is in
+    /// the file `path`.
+    pub fn set_ssh_key(&mut self, path: Option<String>) {
+        self.ssh_key = path;
+    }
+
+    /// Authenticates requests using the OIDC token in the environment variable
+    /// `var`.
+    pub fn set_bearer_token_env(&mut self, var: Option<String>) {
+        self.bearer_token_env = var;
+    }
+
+    /// Authenticates requests using the TLS client certificate and key in the
+    /// PEM files `cert` and `key`.
+    pub fn set_client_cert(&mut self, cert: Option<String>, key: Option<String>) {
+        self.client_cert = cert;
+        self.client_key = key;
+    }
+
+    /// Trusts the PEM encoded CA certificate in the file `path` for the
+    /// server's TLS certificate.
+    pub fn set_ca_cert(&mut self, path: Option<String>) {
+        self.ca_cert = path;
+    }
+
+    /// Sets the default TTL.
+    pub fn set_ttl(&mut self, ttl: Option<u32>) {
+        self.ttl = ttl;
+    }
+
+    /// Sets the default record type.
+    pub fn set_record(&mut self, record: Option<Record>) {
+        self.record = record;
+    }
+
+    /// Sets the domain suffixes that select this profile.
+    pub fn set_domains(&mut self, domains: Vec<String>) {
+        self.domains = domains;
+    }
+
+    /// Returns a reference to the host field.
+    #[inline]
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns the default TTL.
+    #[inline]
+    pub fn ttl(&self) -> Option<u32> {
+        self.ttl
+    }
+
+    /// Returns the default record type.
+    #[inline]
+    pub fn record(&self) -> Option<Record> {
+        self.record
+    }
+
+    /// Returns a reference to the domains field.
+    #[inline]
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+
+    /// Returns the length of the longest configured suffix that `domain`
+    /// belongs to.
+    fn suffix_len(&self, domain: &str) -> Option<usize> {
+        let domain = normalize(domain);
+        self.domains
+            .iter()
+            .map(|suffix| normalize(suffix))
+            .filter(|suffix| {
+                domain == *suffix || domain.ends_with(&format!(".{}", suffix))
+            }).map(|suffix| suffix.len())
+            .max()
+    }
+}
+
+fn normalize(domain: &str) -> String {
+    domain.trim_end_matches('.').to_lowercase()
+}
+
+/// Named profiles, read from the same file as [`Config`](struct.Config.html):
+///
+/// ```toml
+/// host = "https://dns.example.com"
+/// secret = "topsecret"
+///
+/// [profile.lab]
+/// host = "https://dns.lab.example.net"
+/// key_id = "lab"
+/// secret = "labsecret"
+/// ca_cert = "/etc/ssl/lab-ca.pem"
+/// ttl = 300
+/// record = "A"
+/// domains = ["lab.example.net"]
+/// ```
+///
+/// The top level fields form the default profile. A file without profiles is a
+/// plain configuration.
+#[derive(Clone, Debug)]
+pub struct Profiles {
+    default: Option<Config>,
+    profiles: BTreeMap<String, Config>,
+}
+
+impl Profiles {
+    /// Reads the profiles from a TOML file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        fs::read_to_string(path)
+            .map_err(|e| ClientError::Config(e.to_string()))?
+            .parse()
+    }
+
+    /// Returns the profile called `name`.
+    pub fn get(&self, name: &str) -> Option<&Config> {
+        self.profiles.get(name)
+    }
+
+    /// Returns the default profile.
+    #[inline]
+    pub fn default_profile(&self) -> Option<&Config> {
+        self.default.as_ref()
+    }
+
+    /// Returns the profile with the longest domain suffix matching `domain`
+    /// or the default profile, if none matches.
+    pub fn select(&self, domain: Option<&str>) -> Option<&Config> {
+        domain
+            .and_then(|domain| {
+                self.profiles
+                    .values()
+                    .filter_map(|config| config.suffix_len(domain).map(|len| (len, config)))
+                    .max_by_key(|&(len, _)| len)
+                    .map(|(_, config)| config)
+            }).or_else(|| self.default_profile())
+    }
+}
+
+impl FromStr for Profiles {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = |e: toml::de::Error| ClientError::Config(e.to_string());
+        let mut table: toml::value::Table = toml::from_str(s).map_err(invalid)?;
+        let profiles = match table.remove("profile") {
+            Some(profiles) => profiles.try_into().map_err(invalid)?,
+            None => BTreeMap::new(),
+        };
+        let default = if table.is_empty() {
+            None
+        } else {
+            Some(toml::Value::Table(table).try_into().map_err(invalid)?)
+        };
+        Ok(Self { default, profiles })
+    }
+}
+
+/// Result of a change.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The change was applied
+    Applied,
+    /// The change was a dry run, contains the `nsupdate` script the server
+    /// would have executed
+    DryRun(String),
+    /// The change was applied and the server waited for it to propagate
+    Propagated(Propagation),
+}
+
+/// Operations on records.
+pub trait Api {
+    /// Creates or updates a record.
+    fn update(&self, update: &Update) -> Result<Outcome>;
+
+    /// Deletes a RRset or a single value of it.
+    fn delete(&self, delete: &Delete) -> Result<Outcome>;
+
+    /// Applies deletes and updates atomically.
+    fn batch(&self, batch: &Batch) -> Result<Outcome>;
+
+    /// Returns the RRset as it is served by the primary server.
+    fn read(&self, name: &str, record: Record) -> Result<Vec<ResourceRecord>>;
+
+    /// Waits until the RRset reached the expected state on all authoritative
+    /// servers.
+    fn wait(&self, wait: &Wait) -> Result<Propagation>;
+
+    /// Returns the active leases.
+    fn leases(&self) -> Result<Vec<Lease>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_profile() {
+        let profiles: Profiles = r#"
+host = "https://dns.example.com"
+secret = "topsecret"
+
+[profile.lab]
+host = "https://dns.lab.example.net"
+secret = "lab"
+ttl = 300
+record = "A"
+domains = ["example.net"]
+
+[profile.edge]
+host = "https://dns.edge.example.net"
+secret = "edge"
+domains = ["Edge.Example.NET."]
+"#.parse()
+        .unwrap();
+        let host = |domain| profiles.select(domain).map(Config::host);
+        assert_eq!(host(Some("www.example.net")), Some("https://dns.lab.example.net"));
+        assert_eq!(host(Some("edge.example.net")), Some("https://dns.edge.example.net"));
+        assert_eq!(host(Some("a.edge.example.net.")), Some("https://dns.edge.example.net"));
+        assert_eq!(host(Some("notexample.net")), Some("https://dns.example.com"));
+        assert_eq!(host(None), Some("https://dns.example.com"));
+        let lab = profiles.get("lab").unwrap();
+        assert_eq!(lab.ttl(), Some(300));
+        assert_eq!(lab.record(), Some(Record::A));
+        assert!(profiles.get("missing").is_none());
+
+        let profiles: Profiles = "[profile.lab]\nhost = \"https://dns.lab.example.net\"\n"
+            .parse()
+            .unwrap();
+        assert!(profiles.default_profile().is_none());
+        assert!(profiles.select(Some("www.example.com")).is_none());
+    }
+}
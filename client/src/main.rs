@@ -37,86 +37,118 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+extern crate base64;
+extern crate bind9_api_client;
 #[macro_use]
 extern crate clap;
 extern crate crypto;
 extern crate data;
+#[macro_use]
 extern crate failure;
-extern crate hyper;
 #[macro_use]
 extern crate log;
+extern crate openssl;
 extern crate openssl_probe;
 extern crate pretty_env_logger;
 extern crate reqwest;
 extern crate serde;
-extern crate toml;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
 
+mod acme;
+mod admin;
+mod certbot;
 mod cli;
+mod lego;
+mod plan;
+
+use bind9_api_client::{Api, Client, Outcome, Profiles};
 
 use failure::Error;
 
-use data::{ApiError, Delete, Record, Update};
+use data::{ApiError, CredentialKind, Delegation, Delete, NewToken, Propagation, Record, Update};
 
-use std::borrow::Cow;
+use std::io::Write;
 
 type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Eq, PartialEq, Clone, Copy)]
-enum Method {
-    POST,
-    DELETE,
+/// Mints a delegated token and prints a client configuration that uses it.
+fn delegate(client: &Client, delegation: &Delegation) -> Result<()> {
+    let token = client.delegate(delegation)?;
+    println!("# valid until {}", token.not_after());
+    println!("host = {:?}", client.config().host());
+    println!("key_id = {:?}", token.key_id());
+    println!("secret = {:?}", token.secret());
+    Ok(())
 }
 
-#[derive(Deserialize)]
-struct Config<'a> {
-    #[serde(borrow)]
-    host: Cow<'a, str>,
-    #[serde(borrow)]
-    secret: Cow<'a, str>,
+/// Prints the script of a dry run or the propagation state of a change.
+fn report(outcome: &Outcome) -> Result<()> {
+    match *outcome {
+        Outcome::DryRun(ref script) => print!("{}", script),
+        Outcome::Propagated(ref propagation) => report_propagation(propagation)?,
+        Outcome::Applied => {}
+    }
+    Ok(())
 }
 
-fn delete(config: &Config, record: Record, domain: &str) -> Result<()> {
-    let delete = Delete::new(domain.to_owned(), record);
-    let res = call_api(config, delete, Method::DELETE)?;
-    if res.status().is_success() {
+fn report_propagation(propagation: &Propagation) -> Result<()> {
+    for server in propagation.servers() {
+        println!(
+            "{}: {}",
+            server.server(),
+            if server.visible() {
+                "visible"
+            } else {
+                "not visible"
+            }
+        );
+    }
+    if propagation.complete() {
         Ok(())
     } else {
-        Err(ApiError::RequestError.into())
+        Err(ApiError::Propagation.into())
     }
 }
 
-fn update(config: &Config, record: Record, domain: &str, value: &str, ttl: u32) -> Result<()> {
-    let update = Update::new(domain.to_owned(), value.to_owned(), record, ttl);
-    let res = call_api(config, update, Method::POST)?;
-    if res.status().is_success() {
-        Ok(())
-    } else {
-        Err(ApiError::RequestError.into())
+/// Returns the domain the command operates on, that selects the profile if
+/// none is given.
+fn target_domain(matches: &clap::ArgMatches) -> Option<String> {
+    if let Some(domain) = matches.value_of("DOMAIN") {
+        return Some(domain.to_owned());
+    }
+    match matches.subcommand() {
+        ("present", Some(matches)) | ("cleanup", Some(matches)) => {
+            matches.value_of("FQDN").map(str::to_owned)
+        }
+        ("certbot", _) => std::env::var("CERTBOT_DOMAIN").ok(),
+        ("cert", Some(matches)) => matches
+            .subcommand()
+            .1
+            .and_then(|matches| matches.values_of("NAME"))
+            .and_then(|mut names| names.next())
+            .map(str::to_owned),
+        _ => None,
     }
 }
 
-fn call_api<D: serde::Serialize>(
-    config: &Config,
-    data: D,
-    method: Method,
-) -> Result<reqwest::Response> {
-    let data_s = serde_json::to_string(&data)?;
-    info!("body: {}", data_s);
-    let signature = crypto::sign(config.secret.as_bytes(), data_s.as_bytes());
-    let signature = crypto::bytes_to_hex_str(&signature);
-    let client = reqwest::Client::new();
-    let url = format!("{}/record", config.host);
-    Ok(if method == Method::POST {
-        client.post(&url)
-    } else {
-        client.delete(&url)
+/// Writes a new Ed25519 key pair to `path` and prints the public key, that has
+/// to be added to the server's token file.
+fn keygen(path: &str) -> Result<()> {
+    let key = crypto::generate_ed25519_key()?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
     }
-    .header("X-Api-Token", signature)
-    .json(&data)
-    .send()?)
+    options.open(path)?.write_all(&key)?;
+    println!("{}", crypto::bytes_to_hex_str(&crypto::ed25519_public_key(&key)?));
+    Ok(())
 }
 
 fn main() -> Result<()> {
@@ -124,32 +156,236 @@ fn main() -> Result<()> {
     std::env::set_var("RUST_LOG", "info");
     pretty_env_logger::init();
     let matches = cli::parse_cli();
-    let record: Record = matches
-        .value_of("RECORD")
-        .unwrap_or("TXT")
-        .parse()
-        .expect("Invalid record type");
-    let domain = matches.value_of("DOMAIN").unwrap();
+    let domain = matches.value_of("DOMAIN");
+    let dry_run = matches.is_present("DRYRUN");
+    let wait = matches
+        .value_of("WAIT")
+        .map(|w| w.parse().expect("Cannot parse wait timeout"));
+    if let Some(matches) = matches.subcommand_matches("timeout") {
+        let parse = |name| {
+            matches
+                .value_of(name)
+                .unwrap()
+                .parse()
+                .expect("Cannot parse duration")
+        };
+        return lego::timeout(parse("TIMEOUT"), parse("INTERVAL"));
+    }
+    if let Some(matches) = matches.subcommand_matches("keygen") {
+        return keygen(matches.value_of("OUT").unwrap());
+    }
     let config_path = matches
         .value_of("CONFIG")
         .unwrap_or("/etc/bind9apiclient.toml");
-    let config = std::fs::read_to_string(config_path).expect("Cannot read config file");
-    let config: Config = toml::from_str(&config).expect("Cannot parse config file");
-    if let Some(matches) = matches.subcommand_matches("update") {
+    let profiles = Profiles::open(config_path).expect("Cannot read config file");
+    let config = match matches.value_of("PROFILE") {
+        Some(name) => profiles
+            .get(name)
+            .ok_or_else(|| format_err!("Unknown profile: {}", name))?,
+        None => profiles
+            .select(target_domain(&matches).as_deref())
+            .ok_or_else(|| format_err!("No profile matches and no default is configured"))?,
+    };
+    let record = matches
+        .value_of("RECORD")
+        .map(|r| r.parse().expect("Invalid record type"))
+        .or_else(|| config.record())
+        .unwrap_or(Record::TXT);
+    let default_ttl = config.ttl().unwrap_or(8640);
+    let client = Client::new(config.clone())?;
+    let output = if let Some(matches) = matches.subcommand_matches("update") {
         let ttl = matches
             .value_of("TTL")
-            .unwrap_or("8640")
-            .parse()
-            .expect("Cannot parse TTL");
-        update(
-            &config,
+            .map(|t| t.parse().expect("Cannot parse TTL"))
+            .unwrap_or(default_ttl);
+        let mut data = Update::new(
+            domain.expect("Missing domain").to_owned(),
+            matches.value_of("VALUE").unwrap().to_owned(),
             record,
-            domain,
-            matches.value_of("VALUE").unwrap(),
             ttl,
-        )?;
+        );
+        data.set_dry_run(dry_run);
+        data.set_wait(wait);
+        data.set_lease(
+            matches
+                .value_of("LEASE")
+                .map(|l| l.parse().expect("Cannot parse lease duration")),
+        );
+        Some(client.update(&data)?)
     } else if matches.subcommand_matches("delete").is_some() {
-        delete(&config, record, domain)?;
+        let mut data = Delete::new(domain.expect("Missing domain").to_owned(), record);
+        data.set_dry_run(dry_run);
+        data.set_wait(wait);
+        Some(client.delete(&data)?)
+    } else if let Some(matches) = matches.subcommand_matches("plan") {
+        let plan = plan::plan(&client, matches.value_of("FILE").unwrap().as_ref(), default_ttl)?;
+        if plan.is_empty() {
+            println!("No changes");
+        } else {
+            print!("{}", plan);
+        }
+        None
+    } else if let Some(matches) = matches.subcommand_matches("apply") {
+        let path = std::path::Path::new(matches.value_of("FILE").unwrap());
+        let plan = plan::plan(&client, path, default_ttl)?;
+        print!("{}", plan);
+        Some(plan::apply(&client, path, plan, dry_run)?)
+    } else if let Some(matches) = matches.subcommand_matches("certbot") {
+        if matches.subcommand_matches("auth").is_some() {
+            certbot::auth(&client, dry_run, wait)?;
+        } else if matches.subcommand_matches("cleanup").is_some() {
+            certbot::cleanup(&client, dry_run)?;
+        }
+        None
+    } else if let Some(matches) = matches.subcommand_matches("present") {
+        lego::present(
+            &client,
+            matches.value_of("FQDN").unwrap(),
+            matches.value_of("VALUE").unwrap(),
+            dry_run,
+            wait,
+        )?;
+        None
+    } else if let Some(matches) = matches.subcommand_matches("cleanup") {
+        lego::cleanup(
+            &client,
+            matches.value_of("FQDN").unwrap(),
+            matches.value_of("VALUE").unwrap(),
+            dry_run,
+        )?;
+        None
+    } else if matches.subcommand_matches("leases").is_some() {
+        for lease in client.leases()? {
+            println!(
+                "{} {} \"{}\" expires {}",
+                lease.name(),
+                lease.record(),
+                lease.value(),
+                lease.expires()
+            );
+        }
+        None
+    } else if let Some(matches) = matches.subcommand_matches("delegate") {
+        let mut delegation = Delegation::new(
+            matches
+                .values_of("PATTERN")
+                .unwrap()
+                .map(str::to_owned)
+                .collect(),
+            matches
+                .value_of("TTL")
+                .unwrap()
+                .parse()
+                .expect("Cannot parse TTL"),
+        );
+        delegation.set_records(
+            matches
+                .values_of("RECORDS")
+                .map(|records| {
+                    records
+                        .map(|r| r.parse().expect("Invalid record type"))
+                        .collect()
+                }).unwrap_or_default(),
+        );
+        delegation.set_operations(
+            matches
+                .values_of("OPERATIONS")
+                .map(|operations| operations.map(|o| o.parse().unwrap()).collect())
+                .unwrap_or_default(),
+        );
+        delegate(&client, &delegation)?;
+        None
+    } else if let Some(matches) = matches.subcommand_matches("admin") {
+        let matches = matches.subcommand_matches("token").unwrap();
+        let parse_seconds = |s: &str| admin::from_now(s.parse().expect("Cannot parse duration"));
+        match matches.subcommand() {
+            ("create", Some(matches)) => {
+                let kind: CredentialKind = matches.value_of("KIND").unwrap().parse()?;
+                let mut token = NewToken::new(
+                    matches.value_of("NAME").unwrap().to_owned(),
+                    kind,
+                    matches
+                        .values_of("PATTERN")
+                        .unwrap()
+                        .map(str::to_owned)
+                        .collect(),
+                );
+                token.set_records(
+                    matches
+                        .values_of("RECORDS")
+                        .map(|records| {
+                            records
+                                .map(|r| r.parse().expect("Invalid record type"))
+                                .collect()
+                        }).unwrap_or_default(),
+                );
+                token.set_operations(
+                    matches
+                        .values_of("OPERATIONS")
+                        .map(|operations| operations.map(|o| o.parse().unwrap()).collect())
+                        .unwrap_or_default(),
+                );
+                token.set_admin(matches.is_present("ADMIN"));
+                token.set_public_key(matches.value_of("PUBLICKEY").map(str::to_owned));
+                token.set_not_after(match matches.value_of("EXPIRES") {
+                    Some(expires) => Some(parse_seconds(expires)?),
+                    None => None,
+                });
+                admin::create(&client, &token)?;
+            }
+            ("list", _) => admin::list(&client)?,
+            ("show", Some(matches)) => admin::show(&client, matches.value_of("NAME").unwrap())?,
+            ("revoke", Some(matches)) => {
+                admin::revoke(&client, matches.value_of("NAME").unwrap())?
+            }
+            ("expire", Some(matches)) => admin::expire(
+                &client,
+                matches.value_of("NAME").unwrap(),
+                match matches.value_of("IN") {
+                    Some(seconds) => Some(parse_seconds(seconds)?),
+                    None => None,
+                },
+            )?,
+            _ => unreachable!(),
+        }
+        None
+    } else if let Some(matches) = matches.subcommand_matches("cert") {
+        let (renew, matches) = match matches.subcommand() {
+            ("renew", Some(matches)) => (true, matches),
+            (_, Some(matches)) => (false, matches),
+            _ => unreachable!(),
+        };
+        let out = std::path::PathBuf::from(matches.value_of("OUT").unwrap());
+        let mut options = acme::Options::new(
+            matches.value_of("DIRECTORY").unwrap_or(acme::LETSENCRYPT),
+            matches
+                .values_of("NAME")
+                .map(|names| names.map(str::to_owned).collect())
+                .unwrap_or_default(),
+            out,
+            wait.unwrap_or(120),
+        );
+        options.set_email(matches.value_of("EMAIL"));
+        options.set_ca_cert(matches.value_of("CACERT"));
+        if let Some(path) = matches.value_of("ACCOUNTKEY") {
+            options.set_account_key(path.into());
+        }
+        if renew {
+            let days = matches
+                .value_of("DAYS")
+                .unwrap()
+                .parse()
+                .expect("Cannot parse days");
+            acme::renew(&client, options, days)?;
+        } else {
+            acme::issue(&client, &options)?;
+        }
+        None
+    } else {
+        None
+    };
+    if let Some(outcome) = output {
+        report(&outcome)?;
     }
     Ok(())
 }
@@ -0,0 +1,351 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Declarative record files. `plan` compares the RRsets listed in a file with
+//! the records served by the primary, `apply` sends the difference as one
+//! batch. Files ending in `.yaml` or `.yml` are read as YAML, all others as
+//! TOML.
+//!
+//! The file owns the RRsets it lists. The owned RRsets are remembered in a
+//! state file next to it, so RRsets that are removed from the file are pruned
+//! by the next `apply`. RRsets that were never listed are not touched.
+
+use bind9_api_client::{Api, Outcome};
+use data::{Batch, Delete, Record, ResourceRecord, Update};
+use serde_yaml;
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+use toml;
+use Result;
+
+#[derive(Debug, Fail)]
+enum PlanError {
+    #[fail(display = "{} {} is listed more than once", _0, _1)]
+    Duplicate(String, Record),
+}
+
+/// A RRset in the records file.
+#[derive(Deserialize)]
+struct RRset {
+    name: String,
+    record: Record,
+    #[serde(default)]
+    ttl: Option<u32>,
+    values: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct RecordFile {
+    #[serde(default, rename = "rrset")]
+    rrsets: Vec<RRset>,
+}
+
+/// A RRset owned by the records file.
+#[derive(Deserialize, Serialize)]
+struct Owned {
+    name: String,
+    record: Record,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+struct State {
+    #[serde(default, rename = "rrset")]
+    owned: Vec<Owned>,
+}
+
+/// The changes needed to reach the state of the records file.
+pub struct Plan {
+    deletes: Vec<Delete>,
+    updates: Vec<Update>,
+    owned: State,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.deletes.is_empty() && self.updates.is_empty()
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for delete in &self.deletes {
+            match delete.value() {
+                Some(value) => writeln!(f, "- {} {} {}", delete.name(), delete.record(), value)?,
+                None => writeln!(f, "- {} {}", delete.name(), delete.record())?,
+            }
+        }
+        for update in &self.updates {
+            writeln!(
+                f,
+                "+ {} {} {} {}",
+                update.name(),
+                update.ttl(),
+                update.record(),
+                update.value()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Normalizes a name, as names differing in case or the trailing dot are the
+/// same.
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_lowercase()
+}
+
+/// Reads the records file, as YAML or TOML depending on its extension.
+fn read_records(path: &Path) -> Result<RecordFile> {
+    let content = fs::read_to_string(path)?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml") | Some("yml") => Ok(serde_yaml::from_str(&content)?),
+        _ => Ok(toml::from_str(&content)?),
+    }
+}
+
+fn state_path(path: &Path) -> ::std::path::PathBuf {
+    let mut state = path.as_os_str().to_owned();
+    state.push(".state");
+    state.into()
+}
+
+/// Adds the changes that turn the `current` records of a RRset into the
+/// `desired` TTL and values. A changed TTL replaces the whole RRset.
+fn diff(
+    name: &str,
+    record: Record,
+    desired: Option<(u32, &[String])>,
+    current: &[ResourceRecord],
+    deletes: &mut Vec<Delete>,
+    updates: &mut Vec<Update>,
+) {
+    let (ttl, values) = match desired {
+        Some(desired) => desired,
+        None => {
+            if !current.is_empty() {
+                deletes.push(Delete::new(name.to_owned(), record));
+            }
+            return;
+        }
+    };
+    let add = |value: &String, updates: &mut Vec<Update>| {
+        updates.push(Update::new(name.to_owned(), value.clone(), record, ttl))
+    };
+    if current.iter().any(|r| r.ttl() != ttl) {
+        deletes.push(Delete::new(name.to_owned(), record));
+        for value in values {
+            add(value, updates);
+        }
+        return;
+    }
+    let wanted: Vec<_> = values.iter().map(|v| record.normalize_value(v)).collect();
+    for r in current {
+        if !wanted.contains(&record.normalize_value(r.value())) {
+            let mut delete = Delete::new(name.to_owned(), record);
+            delete.set_value(Some(r.value().to_owned()));
+            deletes.push(delete);
+        }
+    }
+    let served: Vec<_> = current
+        .iter()
+        .map(|r| record.normalize_value(r.value()))
+        .collect();
+    for value in values {
+        if !served.contains(&record.normalize_value(value)) {
+            add(value, updates);
+        }
+    }
+}
+
+/// Compares the records file at `path` with the records served by the
+/// primary. RRsets without a TTL use `default_ttl`.
+pub fn plan<A: Api>(api: &A, path: &Path, default_ttl: u32) -> Result<Plan> {
+    let file = read_records(path)?;
+    let state: State = match fs::read_to_string(state_path(path)) {
+        Ok(state) => toml::from_str(&state)?,
+        Err(ref e) if e.kind() == ::std::io::ErrorKind::NotFound => State::default(),
+        Err(e) => return Err(e.into()),
+    };
+    // the key is normalized, so names differing in case or the trailing dot
+    // are the same RRset
+    let mut rrsets = BTreeMap::new();
+    for rrset in &file.rrsets {
+        let key = (normalize(&rrset.name), rrset.record.to_string());
+        let desired = (rrset.ttl.unwrap_or(default_ttl), rrset.values.as_slice());
+        if rrsets
+            .insert(key, (&rrset.name, rrset.record, Some(desired)))
+            .is_some()
+        {
+            return Err(PlanError::Duplicate(rrset.name.clone(), rrset.record).into());
+        }
+    }
+    let owned = State {
+        owned: file
+            .rrsets
+            .iter()
+            .map(|rrset| Owned {
+                name: rrset.name.clone(),
+                record: rrset.record,
+            }).collect(),
+    };
+    for rrset in &state.owned {
+        rrsets
+            .entry((normalize(&rrset.name), rrset.record.to_string()))
+            .or_insert((&rrset.name, rrset.record, None));
+    }
+    let mut deletes = Vec::new();
+    let mut updates = Vec::new();
+    for &(name, record, desired) in rrsets.values() {
+        let current = api.read(name, record)?;
+        diff(name, record, desired, &current, &mut deletes, &mut updates);
+    }
+    Ok(Plan {
+        deletes,
+        updates,
+        owned,
+    })
+}
+
+/// Applies the plan as one batch and records the owned RRsets in the state
+/// file. A dry run returns the script and leaves the state file unchanged.
+pub fn apply<A: Api>(api: &A, path: &Path, plan: Plan, dry_run: bool) -> Result<Outcome> {
+    let outcome = if plan.is_empty() {
+        Outcome::Applied
+    } else {
+        let mut batch = Batch::new(plan.deletes, plan.updates);
+        batch.set_dry_run(dry_run);
+        api.batch(&batch)?
+    };
+    if !dry_run {
+        fs::write(state_path(path), toml::to_string(&plan.owned)?)?;
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rr(ttl: u32, value: &str) -> ResourceRecord {
+        ResourceRecord::new("www.example.com.".to_owned(), ttl, Record::TXT, value.to_owned())
+    }
+
+    #[test]
+    fn minimal_changes() {
+        let values = vec!["a".to_owned(), "b".to_owned()];
+        let current = [rr(300, "\"a\""), rr(300, "\"c\"")];
+        let (mut deletes, mut updates) = (Vec::new(), Vec::new());
+        diff(
+            "www.example.com",
+            Record::TXT,
+            Some((300, &values)),
+            &current,
+            &mut deletes,
+            &mut updates,
+        );
+        assert_eq!(deletes.len(), 1);
+        assert_eq!(deletes[0].value(), Some("\"c\""));
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].value(), "b");
+
+        let (mut deletes, mut updates) = (Vec::new(), Vec::new());
+        diff(
+            "www.example.com",
+            Record::TXT,
+            Some((600, &values)),
+            &current,
+            &mut deletes,
+            &mut updates,
+        );
+        assert_eq!(deletes.len(), 1);
+        assert_eq!(deletes[0].value(), None);
+        assert_eq!(updates.len(), 2);
+
+        let (mut deletes, mut updates) = (Vec::new(), Vec::new());
+        diff("www.example.com", Record::TXT, None, &current, &mut deletes, &mut updates);
+        assert_eq!(deletes.len(), 1);
+        assert!(updates.is_empty());
+        diff("gone.example.com", Record::TXT, None, &[], &mut deletes, &mut updates);
+        assert_eq!(deletes.len(), 1);
+    }
+
+    #[test]
+    fn compare_values() {
+        let current = [rr(300, "\"Token\"")];
+        let (mut deletes, mut updates) = (Vec::new(), Vec::new());
+        let values = vec!["token".to_owned()];
+        diff(
+            "www.example.com",
+            Record::TXT,
+            Some((300, &values)),
+            &current,
+            &mut deletes,
+            &mut updates,
+        );
+        assert_eq!(deletes.len(), 1);
+        assert_eq!(updates.len(), 1);
+    }
+
+    #[test]
+    fn read_yaml_and_toml() {
+        let dir = ::std::env::temp_dir().join("bind9-api-plan-test");
+        fs::create_dir_all(&dir).unwrap();
+        let yaml = dir.join("records.yaml");
+        fs::write(
+            &yaml,
+            "rrset:\n  - name: www.example.com\n    record: A\n    ttl: 300\n    values:\n      \
+             - 192.0.2.1\n",
+        ).unwrap();
+        let toml = dir.join("records.toml");
+        fs::write(
+            &toml,
+            "[[rrset]]\nname = \"www.example.com\"\nrecord = \"A\"\nvalues = [\"192.0.2.1\"]\n",
+        ).unwrap();
+        for path in &[yaml, toml] {
+            let file = read_records(path).unwrap();
+            assert_eq!(file.rrsets.len(), 1);
+            assert_eq!(file.rrsets[0].name, "www.example.com");
+            assert_eq!(file.rrsets[0].record, Record::A);
+            assert_eq!(file.rrsets[0].values, vec!["192.0.2.1".to_owned()]);
+        }
+        assert_eq!(read_records(&dir.join("records.yaml")).unwrap().rrsets[0].ttl, Some(300));
+        fs::write(dir.join("records.yml"), "rrset: [").unwrap();
+        assert!(read_records(&dir.join("records.yml")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,270 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Building and authenticating requests, shared by the blocking and the async
+//! client.
+
+use agent;
+use openssl::{pkcs12::Pkcs12, pkey::PKey, stack::Stack, x509::X509};
+use reqwest::{StatusCode, Url};
+use std::{
+    env, fs,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use {ClientError, Config, Outcome, Result};
+
+#[derive(Eq, PartialEq, Clone, Copy)]
+pub enum Method {
+    GET,
+    POST,
+    DELETE,
+}
+
+impl Method {
+    fn as_str(self) -> &'static str {
+        match self {
+            Method::GET => "GET",
+            Method::POST => "POST",
+            Method::DELETE => "DELETE",
+        }
+    }
+}
+
+/// A request with its authentication headers and JSON body.
+pub struct Prepared {
+    pub method: Method,
+    pub url: Url,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Option<String>,
+}
+
+/// Builds and authenticates a request. The body is serialized once, so the
+/// signature covers exactly the bytes that are sent.
+pub fn prepare<D: ::serde::Serialize>(
+    config: &Config,
+    method: Method,
+    path: &str,
+    query: &[(&str, &str)],
+    data: Option<&D>,
+) -> Result<Prepared> {
+    let body = match data {
+        Some(data) => Some(::serde_json::to_string(data)?),
+        None => None,
+    };
+    info!("body: {}", body.as_deref().unwrap_or_default());
+    let mut url = Url::parse(&format!("{}{}", config.host, path))
+        .map_err(|e| ClientError::Config(e.to_string()))?;
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+    let headers = auth_headers(
+        config,
+        method,
+        &url,
+        body.as_ref().map(String::as_bytes).unwrap_or_default(),
+    ).map_err(ClientError::credentials)?;
+    Ok(Prepared {
+        method,
+        url,
+        headers,
+        body,
+    })
+}
+
+/// Loads the TLS client certificate (followed by its chain) and the private key
+/// from PEM files and returns them as PKCS#12 archive for
+/// `reqwest::Identity::from_pkcs12_der`.
+pub fn identity(config: &Config) -> Result<Option<Vec<u8>>> {
+    let (cert, key) = match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => (cert, key),
+        _ => return Ok(None),
+    };
+    let load = || -> ::std::result::Result<Vec<u8>, ::failure::Error> {
+        let mut certs = X509::stack_from_pem(&fs::read(cert)?)?.into_iter();
+        let cert = certs
+            .next()
+            .ok_or_else(|| ClientError::Credentials(format!("No certificate in {}", cert)))?;
+        let mut chain = Stack::new()?;
+        for ca in certs {
+            chain.push(ca)?;
+        }
+        let key = PKey::private_key_from_pem(&fs::read(key)?)?;
+        let pkcs12 = Pkcs12::builder()
+            .name("bind9-api-client")
+            .pkey(&key)
+            .cert(&cert)
+            .ca(chain)
+            .build2("")?;
+        Ok(pkcs12.to_der()?)
+    };
+    load().map(Some).map_err(ClientError::credentials)
+}
+
+/// Reads the PEM encoded CA certificate that is trusted for the server's TLS
+/// certificate, in addition to the system roots.
+pub fn ca_cert(config: &Config) -> Result<Option<Vec<u8>>> {
+    match config.ca_cert {
+        Some(ref path) => fs::read(path)
+            .map(Some)
+            .map_err(|e| ClientError::Config(format!("Cannot read {}: {}", path, e))),
+        None => Ok(None),
+    }
+}
+
+/// Returns the headers that authenticate a request. Requests are either signed
+/// (see `crypto::canonical_request`), carry the OIDC token from the
+/// environment variable named by `bearer_token_env` or are authenticated by
+/// the TLS client certificate.
+fn auth_headers(
+    config: &Config,
+    method: Method,
+    url: &Url,
+    body: &[u8],
+) -> ::std::result::Result<Vec<(&'static str, String)>, ::failure::Error> {
+    if let Some(ref var) = config.bearer_token_env {
+        let token = env::var(var)?;
+        return Ok(vec![("Authorization", format!("Bearer {}", token.trim()))]);
+    }
+    if config.client_cert.is_some() {
+        return Ok(Vec::new());
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)?
+        .as_secs()
+        .to_string();
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_owned(),
+    };
+    let ssh_key = match config.ssh_key {
+        Some(ref path) => Some(agent::read_public_key(path)?),
+        None => None,
+    };
+    let key_id = match ssh_key {
+        Some(ref key) => Some(::crypto::sshsig::key_id(key)),
+        None => config.key_id.clone(),
+    };
+    let mut headers = vec![("host", host.as_str()), ("x-api-timestamp", &timestamp)];
+    if let Some(ref key_id) = key_id {
+        headers.push(("x-api-key-id", key_id));
+    }
+    let msg = ::crypto::canonical_request(
+        method.as_str(),
+        url.path(),
+        url.query().unwrap_or_default(),
+        &headers,
+        body,
+    );
+    let signature = match (ssh_key, &config.private_key) {
+        (Some(key), _) => agent::sign(&key, &msg)?,
+        (None, Some(path)) => ::crypto::sign_ed25519(&fs::read(path)?, &msg)?,
+        (None, None) => ::crypto::sign(config.secret.as_bytes(), &msg),
+    };
+    let mut auth = vec![
+        (::data::TOKEN_HEADER, ::crypto::bytes_to_hex_str(&signature)),
+        (::data::TIMESTAMP_HEADER, timestamp.clone()),
+    ];
+    if let Some(key_id) = key_id {
+        auth.push((::data::KEY_ID_HEADER, key_id));
+    }
+    Ok(auth)
+}
+
+/// Returns the body of a successful response, or the error matching the status
+/// code.
+pub fn check(status: StatusCode, body: String) -> Result<String> {
+    if status.is_success() {
+        return Ok(body);
+    }
+    Err(match status.as_u16() {
+        400 => ClientError::BadRequest(body),
+        401 => ClientError::Unauthorized(body),
+        403 => ClientError::Forbidden(body),
+        404 => ClientError::NotFound(body),
+        409 => ClientError::Conflict(body),
+        status => ClientError::Status(status, body),
+    })
+}
+
+/// Interprets the response to a change.
+pub fn outcome(body: &str, dry_run: bool, wait: Option<u32>) -> Result<Outcome> {
+    Ok(if dry_run {
+        Outcome::DryRun(body.to_owned())
+    } else if wait.is_some() {
+        Outcome::Propagated(::serde_json::from_str(body)?)
+    } else {
+        Outcome::Applied
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_errors() {
+        assert_eq!(check(StatusCode::OK, "OK".to_owned()).unwrap(), "OK");
+        match check(StatusCode::FORBIDDEN, "Name not allowed".to_owned()) {
+            Err(ClientError::Forbidden(ref message)) => assert_eq!(message, "Name not allowed"),
+            _ => panic!("expected Forbidden"),
+        }
+        match check(StatusCode::BAD_GATEWAY, String::new()) {
+            Err(ClientError::Status(502, _)) => {}
+            _ => panic!("expected Status"),
+        }
+    }
+
+    #[test]
+    fn signed_query() {
+        let config = Config::new("http://127.0.0.1:8080".to_owned(), "secret".to_owned());
+        let prepared = prepare::<()>(
+            &config,
+            Method::GET,
+            "/record",
+            &[("name", "www.example.com"), ("record", "A")],
+            None,
+        ).unwrap();
+        assert_eq!(prepared.url.query(), Some("name=www.example.com&record=A"));
+        assert!(prepared.body.is_none());
+        assert!(prepared
+            .headers
+            .iter()
+            .any(|(name, _)| *name == ::data::TOKEN_HEADER));
+    }
+}
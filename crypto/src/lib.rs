@@ -40,9 +40,11 @@
 
 //! Helper crate for cryptographic operations.
 
+#[macro_use]
 extern crate failure;
 extern crate hex;
 extern crate ring;
+extern crate untrusted;
 
 #[cfg(test)]
 #[macro_use]
@@ -50,10 +52,31 @@ extern crate proptest;
 
 use failure::Error;
 use hex::{FromHex, ToHex};
-use ring::{digest, hmac};
+use ring::{
+    constant_time, digest, hmac, pbkdf2, rand::{SecureRandom, SystemRandom},
+    signature::{self, primitive, Ed25519KeyPair},
+};
+use untrusted::Input;
+
+pub mod sshsig;
 
 type Result<T> = std::result::Result<T, Error>;
 
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const DELEGATED_PREFIX: &str = "d.";
+const DELEGATED_CONTEXT: &[u8] = b"bind9-api delegate";
+
+/// Error types of this crate.
+#[derive(Debug, Fail)]
+pub enum CryptoError {
+    /// The system random number generator failed
+    #[fail(display = "Cannot generate random bytes")]
+    Random,
+    /// The key is not a valid PKCS#8 encoded Ed25519 key
+    #[fail(display = "Invalid Ed25519 key")]
+    InvalidKey,
+}
+
 /// Converts a byte slice to a lowercase hex string.
 pub fn bytes_to_hex_str(bytes: &[u8]) -> String {
     bytes.encode_hex::<String>()
@@ -79,6 +102,166 @@ pub fn sign(key: &[u8], msg: &[u8]) -> Vec<u8> {
     signature.as_ref().into_iter().cloned().collect()
 }
 
+/// Generates a new Ed25519 key pair, encoded as PKCS#8 document.
+pub fn generate_ed25519_key() -> Result<Vec<u8>> {
+    Ok(Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| CryptoError::Random)?
+        .to_vec())
+}
+
+fn ed25519_key_pair(pkcs8: &[u8]) -> Result<Ed25519KeyPair> {
+    Ok(Ed25519KeyPair::from_pkcs8(Input::from(pkcs8)).map_err(|_| CryptoError::InvalidKey)?)
+}
+
+/// Extracts the public key from a PKCS#8 encoded Ed25519 key pair.
+pub fn ed25519_public_key(pkcs8: &[u8]) -> Result<Vec<u8>> {
+    Ok(ed25519_key_pair(pkcs8)?.public_key_bytes().to_vec())
+}
+
+/// Creates an Ed25519 signature using a PKCS#8 encoded key pair.
+pub fn sign_ed25519(pkcs8: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+    Ok(ed25519_key_pair(pkcs8)?.sign(msg).as_ref().to_vec())
+}
+
+/// Verifies an Ed25519 signature.
+pub fn verify_ed25519(public_key: &[u8], msg: &[u8], signature: &[u8]) -> bool {
+    signature::verify(
+        &signature::ED25519,
+        Input::from(public_key),
+        Input::from(msg),
+        Input::from(signature),
+    ).is_ok()
+}
+
+/// Builds the canonical form of a request. It is signed instead of the plain
+/// body, so method, target and the selected headers are authenticated as well:
+///
+/// ```text
+/// METHOD
+/// /path
+/// query=parameters&sorted=by-pair
+/// header-name:value (one line per header, lowercase names, sorted)
+/// hex encoded SHA256 digest of the body
+/// ```
+pub fn canonical_request(
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Vec<u8> {
+    let mut query: Vec<_> = query.split('&').filter(|p| !p.is_empty()).collect();
+    query.sort();
+    let mut headers: Vec<_> = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name.to_lowercase(), value.trim()))
+        .collect();
+    headers.sort();
+    format!(
+        "{}\n{}\n{}\n{}{}",
+        method.to_uppercase(),
+        path,
+        query.join("&"),
+        headers.concat(),
+        bytes_to_hex_str(&sha256(body))
+    ).into_bytes()
+}
+
+/// Verifies a RSASSA-PKCS1-v1_5 SHA256 signature (`RS256` in JWS) using the
+/// public key given as big endian modulus `n` and exponent `e`.
+pub fn verify_rsa_sha256(n: &[u8], e: &[u8], msg: &[u8], signature: &[u8]) -> bool {
+    verify_rsa(&signature::RSA_PKCS1_2048_8192_SHA256, n, e, msg, signature)
+}
+
+fn verify_rsa(
+    params: &signature::RSAParameters,
+    n: &[u8],
+    e: &[u8],
+    msg: &[u8],
+    signature: &[u8],
+) -> bool {
+    fn strip(bytes: &[u8]) -> &[u8] {
+        let start = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+        &bytes[start..]
+    }
+    primitive::verify_rsa(
+        params,
+        (Input::from(strip(n)), Input::from(strip(e))),
+        Input::from(msg),
+        Input::from(signature),
+    ).is_ok()
+}
+
+/// Verifies an ECDSA P-256 SHA256 signature in fixed length encoding (`ES256`
+/// in JWS) using the public key given as big endian coordinates `x` and `y`.
+pub fn verify_ecdsa_p256_sha256(x: &[u8], y: &[u8], msg: &[u8], signature: &[u8]) -> bool {
+    let mut point = vec![4];
+    point.extend_from_slice(x);
+    point.extend_from_slice(y);
+    signature::verify(
+        &signature::ECDSA_P256_SHA256_FIXED,
+        Input::from(&point),
+        Input::from(msg),
+        Input::from(signature),
+    ).is_ok()
+}
+
+/// Creates the key id of a delegated token, which carries its own description:
+/// `d.<hex encoded payload>`. The matching secret is derived from the key id
+/// using `delegated_secret`, so delegated tokens need not be stored.
+pub fn delegated_key_id(payload: &[u8]) -> String {
+    format!("{}{}", DELEGATED_PREFIX, bytes_to_hex_str(payload))
+}
+
+/// Returns the payload of a delegated key id, or `None` if `key_id` is not a
+/// delegated key id.
+pub fn delegated_payload(key_id: &str) -> Option<Vec<u8>> {
+    key_id
+        .strip_prefix(DELEGATED_PREFIX)
+        .and_then(|payload| hex_str_to_bytes(payload).ok())
+}
+
+/// Derives the secret of a delegated token as HMAC SHA256 over the key id,
+/// using a subkey of the issuer's key. Only the issuer can derive the secret,
+/// so a valid signature proves that the payload was issued by it. The subkey
+/// (`HMAC(key, "bind9-api delegate")`) separates delegated secrets from
+/// signatures made using the issuer's key itself.
+pub fn delegated_secret(key: &[u8], key_id: &str) -> Vec<u8> {
+    sign(&sign(key, DELEGATED_CONTEXT), key_id.as_bytes())
+}
+
+/// Calculates the SHA256 digest of a message.
+pub fn sha256(msg: &[u8]) -> Vec<u8> {
+    digest::digest(&digest::SHA256, msg).as_ref().to_vec()
+}
+
+/// Generates `len` cryptographically secure random bytes.
+pub fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| CryptoError::Random)?;
+    Ok(bytes)
+}
+
+/// Derives a PBKDF2 SHA256 hash from a password, that can be stored instead of
+/// the password itself.
+pub fn hash_password(password: &[u8], salt: &[u8]) -> Vec<u8> {
+    let mut hash = vec![0; digest::SHA256_OUTPUT_LEN];
+    pbkdf2::derive(&digest::SHA256, PBKDF2_ITERATIONS, salt, password, &mut hash);
+    hash
+}
+
+/// Verifies a password against a hash created by `hash_password`.
+pub fn verify_password(password: &[u8], salt: &[u8], hash: &[u8]) -> bool {
+    pbkdf2::verify(&digest::SHA256, PBKDF2_ITERATIONS, salt, password, hash).is_ok()
+}
+
+/// Compares two byte slices in constant time.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    constant_time::verify_slices_are_equal(a, b).is_ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -91,10 +274,70 @@ mod tests {
         }
     }
 
+    #[test]
+    fn sign_verify_ed25519() {
+        let key = generate_ed25519_key().unwrap();
+        let public_key = ed25519_public_key(&key).unwrap();
+        let sig = sign_ed25519(&key, b"message").unwrap();
+        assert!(verify_ed25519(&public_key, b"message", &sig));
+        assert!(!verify_ed25519(&public_key, b"other message", &sig));
+        let other = ed25519_public_key(&generate_ed25519_key().unwrap()).unwrap();
+        assert!(!verify_ed25519(&other, b"message", &sig));
+    }
+
+    #[test]
+    fn canonical_request_binds_target() {
+        let headers = [("Host", "api.example.com"), ("X-Api-Timestamp", "1545234000")];
+        let canonical = canonical_request("post", "/record", "b=2&a=1", &headers, b"{}");
+        assert_eq!(
+            canonical,
+            canonical_request(
+                "POST",
+                "/record",
+                "a=1&b=2",
+                &[headers[1], headers[0]],
+                b"{}"
+            )
+        );
+        assert_ne!(
+            canonical,
+            canonical_request("DELETE", "/record", "a=1&b=2", &headers, b"{}")
+        );
+        assert_ne!(
+            canonical,
+            canonical_request("POST", "/present", "a=1&b=2", &headers, b"{}")
+        );
+        assert_ne!(
+            canonical,
+            canonical_request("POST", "/record", "a=1&b=2", &headers[..1], b"{}")
+        );
+    }
+
+    #[test]
+    fn delegated_token() {
+        let key_id = delegated_key_id(b"payload");
+        assert_eq!(delegated_payload(&key_id).unwrap(), b"payload");
+        assert!(delegated_payload("ci").is_none());
+        let secret = delegated_secret(b"issuer", &key_id);
+        assert_ne!(secret, delegated_secret(b"other", &key_id));
+        let forged = delegated_key_id(b"other payload");
+        assert_ne!(secret, delegated_secret(b"issuer", &forged));
+        assert_ne!(secret, sign(b"issuer", key_id.as_bytes()));
+    }
+
+    #[test]
+    fn hash_verify_password() {
+        let salt = random_bytes(16).unwrap();
+        let hash = hash_password(b"topsecret", &salt);
+        assert!(verify_password(b"topsecret", &salt, &hash));
+        assert!(!verify_password(b"wrong", &salt, &hash));
+        assert!(!verify_password(b"topsecret", b"othersalt", &hash));
+    }
+
     proptest! {
         #[test]
         fn to_from_hex(data: Vec<u8>) {
-            assert_eq!(hex_str_to_bytes(&bytes_to_hex_str(&data).unwrap()).unwrap(), data);
+            assert_eq!(hex_str_to_bytes(&bytes_to_hex_str(&data)).unwrap(), data);
         }
     }
 }
@@ -0,0 +1,252 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The SSH signature format (`sshsig`) as produced by `ssh-keygen -Y sign`
+//! and SSH agents. Supported are Ed25519, ECDSA P-256 and RSA (SHA-2) keys.
+//!
+//! All functions take public keys and signatures in the SSH wire format, i.e.
+//! the base64 decoded part of an `authorized_keys` line.
+
+use ring::{
+    digest,
+    signature::{self, ECDSA_P256_SHA256_FIXED},
+};
+use untrusted::Input;
+
+/// Namespace of request signatures. Signatures for other purposes, e.g. signed
+/// files or git commits, are not accepted.
+pub const NAMESPACE: &str = "bind9-api";
+/// Prefix of key ids that name a SSH key.
+pub const KEY_ID_PREFIX: &str = "ssh:";
+
+const MAGIC: &[u8] = b"SSHSIG";
+const VERSION: u32 = 1;
+const HASH_ALGORITHM: &str = "sha512";
+
+/// Reads the fields of the SSH wire format.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn u32(&mut self) -> Option<u32> {
+        if self.0.len() < 4 {
+            return None;
+        }
+        let (value, rest) = self.0.split_at(4);
+        self.0 = rest;
+        Some(value.iter().fold(0, |acc, &b| (acc << 8) | u32::from(b)))
+    }
+
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if self.0.len() < len {
+            return None;
+        }
+        let (value, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(value)
+    }
+
+    fn finish(&self) -> Option<()> {
+        if self.0.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, value: &[u8]) {
+    buf.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    buf.extend_from_slice(value);
+}
+
+/// Returns the key type of a public key, e.g. `ssh-ed25519`.
+pub fn key_type(public_key: &[u8]) -> Option<&str> {
+    Reader(public_key)
+        .string()
+        .and_then(|t| ::std::str::from_utf8(t).ok())
+}
+
+/// Returns the key id of a public key: `ssh:` followed by the hex encoded
+/// SHA256 fingerprint.
+pub fn key_id(public_key: &[u8]) -> String {
+    format!("{}{}", KEY_ID_PREFIX, super::bytes_to_hex_str(&super::sha256(public_key)))
+}
+
+fn hash(algorithm: &[u8], msg: &[u8]) -> Option<Vec<u8>> {
+    let algorithm = match algorithm {
+        b"sha256" => &digest::SHA256,
+        b"sha512" => &digest::SHA512,
+        _ => return None,
+    };
+    Some(digest::digest(algorithm, msg).as_ref().to_vec())
+}
+
+fn signed_data_with(reserved: &[u8], algorithm: &[u8], digest: &[u8]) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    put_string(&mut data, NAMESPACE.as_bytes());
+    put_string(&mut data, reserved);
+    put_string(&mut data, algorithm);
+    put_string(&mut data, digest);
+    data
+}
+
+/// Returns the data the SSH key has to sign for a signature over `msg`, e.g.
+/// using the sign request of an SSH agent.
+pub fn signed_data(msg: &[u8]) -> Vec<u8> {
+    let digest = hash(HASH_ALGORITHM.as_bytes(), msg).unwrap_or_default();
+    signed_data_with(b"", HASH_ALGORITHM.as_bytes(), &digest)
+}
+
+/// Wraps the raw signature of the `signed_data` as returned by the SSH agent
+/// into a `sshsig` blob.
+pub fn encode(public_key: &[u8], signature: &[u8]) -> Vec<u8> {
+    let mut blob = MAGIC.to_vec();
+    blob.extend_from_slice(&VERSION.to_be_bytes());
+    put_string(&mut blob, public_key);
+    put_string(&mut blob, NAMESPACE.as_bytes());
+    put_string(&mut blob, b"");
+    put_string(&mut blob, HASH_ALGORITHM.as_bytes());
+    put_string(&mut blob, signature);
+    blob
+}
+
+/// Verifies a `sshsig` blob over `msg`. The signature has to be made by
+/// `public_key` for the request namespace.
+pub fn verify(public_key: &[u8], msg: &[u8], blob: &[u8]) -> bool {
+    verify_blob(public_key, msg, blob).unwrap_or(false)
+}
+
+fn verify_blob(public_key: &[u8], msg: &[u8], blob: &[u8]) -> Option<bool> {
+    if !blob.starts_with(MAGIC) {
+        return None;
+    }
+    let mut reader = Reader(&blob[MAGIC.len()..]);
+    if reader.u32()? != VERSION
+        || !super::constant_time_eq(reader.string()?, public_key)
+        || reader.string()? != NAMESPACE.as_bytes()
+    {
+        return None;
+    }
+    let reserved = reader.string()?;
+    let algorithm = reader.string()?;
+    let signature = reader.string()?;
+    reader.finish()?;
+    let data = signed_data_with(reserved, algorithm, &hash(algorithm, msg)?);
+    verify_raw(public_key, &data, signature)
+}
+
+/// Verifies a raw SSH signature, e.g. as created by an SSH agent.
+fn verify_raw(public_key: &[u8], msg: &[u8], signature: &[u8]) -> Option<bool> {
+    let mut key = Reader(public_key);
+    let mut signature = Reader(signature);
+    let key_type = key.string()?;
+    let signature_type = signature.string()?;
+    let raw = signature.string()?;
+    signature.finish()?;
+    Some(match (key_type, signature_type) {
+        (b"ssh-ed25519", b"ssh-ed25519") => {
+            let point = key.string()?;
+            key.finish()?;
+            super::verify_ed25519(point, msg, raw)
+        }
+        (b"ssh-rsa", b"rsa-sha2-256") | (b"ssh-rsa", b"rsa-sha2-512") => {
+            let e = key.string()?;
+            let n = key.string()?;
+            key.finish()?;
+            let params = if signature_type == b"rsa-sha2-256" {
+                &signature::RSA_PKCS1_2048_8192_SHA256
+            } else {
+                &signature::RSA_PKCS1_2048_8192_SHA512
+            };
+            super::verify_rsa(params, n, e, msg, raw)
+        }
+        (b"ecdsa-sha2-nistp256", b"ecdsa-sha2-nistp256") => {
+            if key.string()? != b"nistp256" {
+                return None;
+            }
+            let point = key.string()?;
+            key.finish()?;
+            // the signature consists of the mpints r and s
+            let mut mpints = Reader(raw);
+            let mut fixed = Vec::with_capacity(64);
+            for _ in 0..2 {
+                let int = mpints.string()?;
+                let start = int.iter().position(|&b| b != 0).unwrap_or(int.len());
+                let int = &int[start..];
+                if int.len() > 32 {
+                    return None;
+                }
+                let len = fixed.len() + 32 - int.len();
+                fixed.resize(len, 0);
+                fixed.extend_from_slice(int);
+            }
+            mpints.finish()?;
+            signature::verify(
+                &ECDSA_P256_SHA256_FIXED,
+                Input::from(point),
+                Input::from(msg),
+                Input::from(&fixed),
+            ).is_ok()
+        }
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_verify_ed25519() {
+        let pkcs8 = ::generate_ed25519_key().unwrap();
+        let mut public_key = Vec::new();
+        put_string(&mut public_key, b"ssh-ed25519");
+        put_string(&mut public_key, &::ed25519_public_key(&pkcs8).unwrap());
+        let raw = ::sign_ed25519(&pkcs8, &signed_data(b"request")).unwrap();
+        let mut signature = Vec::new();
+        put_string(&mut signature, b"ssh-ed25519");
+        put_string(&mut signature, &raw);
+        let blob = encode(&public_key, &signature);
+        assert!(verify(&public_key, b"request", &blob));
+        assert!(!verify(&public_key, b"other request", &blob));
+        assert_eq!(key_type(&public_key), Some("ssh-ed25519"));
+        assert!(key_id(&public_key).starts_with(KEY_ID_PREFIX));
+    }
+}
@@ -48,6 +48,14 @@ extern crate serde_derive;
 /// The name of the API token header: `X-Api-Token`
 pub const TOKEN_HEADER: &str = "X-Api-Token";
 
+/// The name of the header that selects the key the request is signed with:
+/// `X-Api-Key-Id`. Requests without it are signed with the shared secret.
+pub const KEY_ID_HEADER: &str = "X-Api-Key-Id";
+
+/// The name of the header carrying the time the request was signed at, in
+/// seconds since the epoch: `X-Api-Timestamp`
+pub const TIMESTAMP_HEADER: &str = "X-Api-Timestamp";
+
 /// Enumeration of DNS record types
 #[derive(Eq, PartialEq, Deserialize, Serialize, Debug, Clone, Copy)]
 pub enum Record {
@@ -142,6 +150,15 @@ pub enum ApiError {
     /// Error while handling a request
     #[fail(display = "API Error")]
     RequestError,
+    /// A change did not propagate to all authoritative servers in time
+    #[fail(display = "Change did not propagate to all servers")]
+    Propagation,
+    /// Error while parsing a credential kind
+    #[fail(display = "Parse credential kind error")]
+    ParseCredentialKind,
+    /// Error while parsing an operation
+    #[fail(display = "Parse operation error")]
+    ParseOperation,
 }
 
 impl std::str::FromStr for Record {
@@ -245,6 +262,36 @@ impl std::fmt::Display for Record {
     }
 }
 
+impl Record {
+    /// Normalizes a value of this record type for comparisons, as `dig` quotes
+    /// TXT values and prints names fully qualified. Only addresses, names and
+    /// hex digests are compared ignoring case, other values like TXT are
+    /// case-sensitive.
+    pub fn normalize_value(self, value: &str) -> String {
+        let value = value.trim().trim_matches('"');
+        match self {
+            Record::A
+            | Record::AAAA
+            | Record::AFSDB
+            | Record::ALIAS
+            | Record::CDS
+            | Record::CNAME
+            | Record::DLV
+            | Record::DNAME
+            | Record::DS
+            | Record::KX
+            | Record::MX
+            | Record::NS
+            | Record::PTR
+            | Record::SRV
+            | Record::SSHFP
+            | Record::TA
+            | Record::TLSA => value.trim_end_matches('.').to_lowercase(),
+            _ => value.to_owned(),
+        }
+    }
+}
+
 /// Data for an update request containing the domain name, record type,
 /// record value and TTL.
 #[derive(Deserialize, Serialize)]
@@ -253,6 +300,12 @@ pub struct Update {
     value: String,
     record: Record,
     ttl: u32,
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    wait: Option<u32>,
+    #[serde(default)]
+    lease: Option<u32>,
 }
 
 impl Update {
@@ -263,9 +316,33 @@ impl Update {
             value,
             record,
             ttl,
+            dry_run: false,
+            wait: None,
+            lease: None,
         }
     }
 
+    /// Requests the record to be deleted by the server after `duration`
+    /// seconds, unless the lease is renewed by sending the same update again.
+    #[inline]
+    pub fn set_lease(&mut self, duration: Option<u32>) {
+        self.lease = duration;
+    }
+
+    /// Marks the request as a dry run. The server will validate the request
+    /// and return the planned operation without applying it.
+    #[inline]
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Lets the server wait up to `timeout` seconds until the change is visible
+    /// on all authoritative servers of the zone.
+    #[inline]
+    pub fn set_wait(&mut self, timeout: Option<u32>) {
+        self.wait = timeout;
+    }
+
     /// Returns a reference to the name field.
     #[inline]
     pub fn name(&self) -> &str {
@@ -289,17 +366,163 @@ impl Update {
     pub fn ttl(&self) -> u32 {
         self.ttl
     }
+
+    /// Returns `true` if the request is a dry run.
+    #[inline]
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Returns the propagation timeout in seconds, if the server should wait
+    /// for the change to propagate.
+    #[inline]
+    pub fn wait(&self) -> Option<u32> {
+        self.wait
+    }
+
+    /// Returns the lease duration in seconds.
+    #[inline]
+    pub fn lease(&self) -> Option<u32> {
+        self.lease
+    }
 }
 
-/// Data of a delete request, containing the domain name and record type.
+/// Data of a delete request, containing the domain name and record type. If a
+/// value is set, only this value is removed from the RRset.
 #[derive(Deserialize, Serialize)]
 pub struct Delete {
     name: String,
     record: Record,
+    #[serde(default)]
+    value: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    wait: Option<u32>,
 }
 
 impl Delete {
     /// Creates a new Delete object.
+    pub fn new(name: String, record: Record) -> Self {
+        Self {
+            name,
+            record,
+            value: None,
+            dry_run: false,
+            wait: None,
+        }
+    }
+
+    /// Restricts the delete to a single value of the RRset.
+    #[inline]
+    pub fn set_value(&mut self, value: Option<String>) {
+        self.value = value;
+    }
+
+    /// Marks the request as a dry run. The server will validate the request
+    /// and return the planned operation without applying it.
+    #[inline]
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Lets the server wait up to `timeout` seconds until the change is visible
+    /// on all authoritative servers of the zone.
+    #[inline]
+    pub fn set_wait(&mut self, timeout: Option<u32>) {
+        self.wait = timeout;
+    }
+
+    /// Returns a reference to the name field.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the record type.
+    #[inline]
+    pub fn record(&self) -> Record {
+        self.record
+    }
+
+    /// Returns the value to remove, if the delete is restricted to a single
+    /// value.
+    #[inline]
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// Returns `true` if the request is a dry run.
+    #[inline]
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Returns the propagation timeout in seconds, if the server should wait
+    /// for the change to propagate.
+    #[inline]
+    pub fn wait(&self) -> Option<u32> {
+        self.wait
+    }
+}
+
+/// Deletes and updates that are applied atomically, in a single update message.
+/// The `dry_run` and `wait` fields of the single changes are ignored.
+#[derive(Deserialize, Serialize, Default)]
+pub struct Batch {
+    #[serde(default)]
+    deletes: Vec<Delete>,
+    #[serde(default)]
+    updates: Vec<Update>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+impl Batch {
+    /// Creates a new Batch object. Deletes are applied before updates.
+    pub fn new(deletes: Vec<Delete>, updates: Vec<Update>) -> Self {
+        Self {
+            deletes,
+            updates,
+            dry_run: false,
+        }
+    }
+
+    /// Marks the request as a dry run. The server will validate the request
+    /// and return the planned operation without applying it.
+    #[inline]
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Returns the deletes.
+    #[inline]
+    pub fn deletes(&self) -> &[Delete] {
+        &self.deletes
+    }
+
+    /// Returns the updates.
+    #[inline]
+    pub fn updates(&self) -> &[Update] {
+        &self.updates
+    }
+
+    /// Returns `true` if the request is a dry run.
+    #[inline]
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+}
+
+/// Query for a RRset, as it is served by the primary server.
+#[derive(Deserialize, Serialize)]
+pub struct RecordQuery {
+    name: String,
+    record: Record,
+}
+
+impl RecordQuery {
+    /// Creates a new RecordQuery object.
     pub fn new(name: String, record: Record) -> Self {
         Self { name, record }
     }
@@ -317,6 +540,643 @@ impl Delete {
     }
 }
 
+/// The state a RRset is expected to reach.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Expectation {
+    /// The RRset contains the value
+    Present(String),
+    /// The RRset does not contain the value
+    Missing(String),
+    /// The RRset does not exist
+    Absent,
+}
+
+/// Request to wait until a RRset reached the expected state on all
+/// authoritative servers of its zone, e.g. after a batch.
+#[derive(Deserialize, Serialize)]
+pub struct Wait {
+    name: String,
+    record: Record,
+    expect: Expectation,
+    timeout: u32,
+}
+
+impl Wait {
+    /// Creates a new Wait object. The server waits up to `timeout` seconds.
+    pub fn new(name: String, record: Record, expect: Expectation, timeout: u32) -> Self {
+        Self {
+            name,
+            record,
+            expect,
+            timeout,
+        }
+    }
+
+    /// Returns a reference to the name field.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the record type.
+    #[inline]
+    pub fn record(&self) -> Record {
+        self.record
+    }
+
+    /// Returns the expected state.
+    #[inline]
+    pub fn expect(&self) -> &Expectation {
+        &self.expect
+    }
+
+    /// Returns the timeout in seconds.
+    #[inline]
+    pub fn timeout(&self) -> u32 {
+        self.timeout
+    }
+}
+
+/// A single resource record, as it is served by the DNS server.
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct ResourceRecord {
+    name: String,
+    ttl: u32,
+    record: Record,
+    value: String,
+}
+
+impl ResourceRecord {
+    /// Creates a new ResourceRecord object.
+    pub fn new(name: String, ttl: u32, record: Record, value: String) -> Self {
+        Self {
+            name,
+            ttl,
+            record,
+            value,
+        }
+    }
+
+    /// Returns a reference to the name field.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the TTL.
+    #[inline]
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    /// Returns the record type.
+    #[inline]
+    pub fn record(&self) -> Record {
+        self.record
+    }
+
+    /// Returns a reference to the value field.
+    #[inline]
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+/// A record that is deleted by the server once its lease expires.
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct Lease {
+    name: String,
+    record: Record,
+    value: String,
+    expires: u64,
+}
+
+impl Lease {
+    /// Creates a new Lease object. `expires` is a UNIX timestamp.
+    pub fn new(name: String, record: Record, value: String, expires: u64) -> Self {
+        Self {
+            name,
+            record,
+            value,
+            expires,
+        }
+    }
+
+    /// Returns a reference to the name field.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the record type.
+    #[inline]
+    pub fn record(&self) -> Record {
+        self.record
+    }
+
+    /// Returns a reference to the value field.
+    #[inline]
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Returns the UNIX timestamp at which the lease expires.
+    #[inline]
+    pub fn expires(&self) -> u64 {
+        self.expires
+    }
+
+    /// Returns `true` if the lease belongs to the given record.
+    pub fn is_for(&self, name: &str, record: Record, value: Option<&str>) -> bool {
+        self.name
+            .trim_end_matches('.')
+            .eq_ignore_ascii_case(name.trim_end_matches('.'))
+            && self.record == record
+            && value.map(|v| v == self.value).unwrap_or(true)
+    }
+}
+
+/// Visibility of a change on a single authoritative server.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ServerStatus {
+    server: String,
+    visible: bool,
+}
+
+impl ServerStatus {
+    /// Creates a new ServerStatus object.
+    pub fn new(server: String, visible: bool) -> Self {
+        Self { server, visible }
+    }
+
+    /// Returns a reference to the server name.
+    #[inline]
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Returns `true` if the change was visible on the server.
+    #[inline]
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+}
+
+/// Result of waiting for a change to propagate to all authoritative servers of
+/// a zone.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Propagation {
+    zone: String,
+    servers: Vec<ServerStatus>,
+}
+
+impl Propagation {
+    /// Creates a new Propagation object.
+    pub fn new(zone: String, servers: Vec<ServerStatus>) -> Self {
+        Self { zone, servers }
+    }
+
+    /// Returns a reference to the zone name.
+    #[inline]
+    pub fn zone(&self) -> &str {
+        &self.zone
+    }
+
+    /// Returns the state of every authoritative server.
+    #[inline]
+    pub fn servers(&self) -> &[ServerStatus] {
+        &self.servers
+    }
+
+    /// Returns `true` if the change is visible on all servers. Without any
+    /// known server, the change is never complete.
+    #[inline]
+    pub fn complete(&self) -> bool {
+        !self.servers.is_empty() && self.servers.iter().all(ServerStatus::visible)
+    }
+}
+
+/// Kind of credential of a token that is managed using the admin API.
+#[derive(Eq, PartialEq, Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialKind {
+    /// Password for HTTP basic auth, only a hash is stored
+    Password,
+    /// Shared secret for HMAC request signatures
+    Secret,
+    /// Ed25519 public key for request signatures
+    Ed25519,
+}
+
+impl std::str::FromStr for CredentialKind {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "password" => Ok(CredentialKind::Password),
+            "secret" => Ok(CredentialKind::Secret),
+            "ed25519" => Ok(CredentialKind::Ed25519),
+            _ => Err(ApiError::ParseCredentialKind),
+        }
+    }
+}
+
+/// Request to create a token using the admin API.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct NewToken {
+    name: String,
+    kind: CredentialKind,
+    names: Vec<String>,
+    #[serde(default)]
+    records: Vec<Record>,
+    #[serde(default)]
+    operations: Vec<Operation>,
+    #[serde(default)]
+    admin: bool,
+    #[serde(default)]
+    public_key: Option<String>,
+    #[serde(default)]
+    not_after: Option<u64>,
+}
+
+impl NewToken {
+    /// Creates a new NewToken object for a token that may modify the records
+    /// matching `names`.
+    pub fn new(name: String, kind: CredentialKind, names: Vec<String>) -> Self {
+        Self {
+            name,
+            kind,
+            names,
+            records: Vec::new(),
+            operations: Vec::new(),
+            admin: false,
+            public_key: None,
+            not_after: None,
+        }
+    }
+
+    /// Restricts the token to the given record types. An empty list allows all
+    /// types.
+    pub fn set_records(&mut self, records: Vec<Record>) {
+        self.records = records;
+    }
+
+    /// Restricts the token to the given operations. An empty list allows all
+    /// operations except for `override`.
+    pub fn set_operations(&mut self, operations: Vec<Operation>) {
+        self.operations = operations;
+    }
+
+    /// Allows the token to use the admin API.
+    pub fn set_admin(&mut self, admin: bool) {
+        self.admin = admin;
+    }
+
+    /// Sets the hex encoded public key of `ed25519` tokens.
+    pub fn set_public_key(&mut self, public_key: Option<String>) {
+        self.public_key = public_key;
+    }
+
+    /// Sets the UNIX timestamp after which the token is no longer valid.
+    pub fn set_not_after(&mut self, not_after: Option<u64>) {
+        self.not_after = not_after;
+    }
+
+    /// Returns a reference to the name field.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the credential kind.
+    #[inline]
+    pub fn kind(&self) -> CredentialKind {
+        self.kind
+    }
+
+    /// Returns the name patterns the token may modify.
+    #[inline]
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the record types the token may modify.
+    #[inline]
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Returns the operations the token may perform.
+    #[inline]
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Returns `true` if the token may use the admin API.
+    #[inline]
+    pub fn admin(&self) -> bool {
+        self.admin
+    }
+
+    /// Returns the public key of `ed25519` tokens.
+    #[inline]
+    pub fn public_key(&self) -> Option<&str> {
+        self.public_key.as_deref()
+    }
+
+    /// Returns the UNIX timestamp after which the token is no longer valid.
+    #[inline]
+    pub fn not_after(&self) -> Option<u64> {
+        self.not_after
+    }
+}
+
+/// A token as reported by the admin API. The credential itself is never
+/// included.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TokenInfo {
+    name: String,
+    kind: CredentialKind,
+    names: Vec<String>,
+    #[serde(default)]
+    records: Vec<Record>,
+    #[serde(default)]
+    operations: Vec<Operation>,
+    #[serde(default)]
+    admin: bool,
+    created: u64,
+    #[serde(default)]
+    not_after: Option<u64>,
+    #[serde(default)]
+    revoked: bool,
+}
+
+impl TokenInfo {
+    /// Creates a new TokenInfo object from a token request. `created` is a UNIX
+    /// timestamp.
+    pub fn new(request: &NewToken, created: u64) -> Self {
+        Self {
+            name: request.name.clone(),
+            kind: request.kind,
+            names: request.names.clone(),
+            records: request.records.clone(),
+            operations: request.operations.clone(),
+            admin: request.admin,
+            created,
+            not_after: request.not_after,
+            revoked: false,
+        }
+    }
+
+    /// Sets the UNIX timestamp after which the token is no longer valid.
+    pub fn set_not_after(&mut self, not_after: Option<u64>) {
+        self.not_after = not_after;
+    }
+
+    /// Marks the token as revoked.
+    pub fn revoke(&mut self) {
+        self.revoked = true;
+    }
+
+    /// Returns a reference to the name field.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the credential kind.
+    #[inline]
+    pub fn kind(&self) -> CredentialKind {
+        self.kind
+    }
+
+    /// Returns the name patterns the token may modify.
+    #[inline]
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the record types the token may modify. An empty list allows all
+    /// types.
+    #[inline]
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Returns the operations the token may perform. An empty list allows all
+    /// operations except for `override`.
+    #[inline]
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Returns `true` if the token may use the admin API.
+    #[inline]
+    pub fn admin(&self) -> bool {
+        self.admin
+    }
+
+    /// Returns the UNIX timestamp at which the token was created.
+    #[inline]
+    pub fn created(&self) -> u64 {
+        self.created
+    }
+
+    /// Returns the UNIX timestamp after which the token is no longer valid.
+    #[inline]
+    pub fn not_after(&self) -> Option<u64> {
+        self.not_after
+    }
+
+    /// Returns `true` if the token was revoked.
+    #[inline]
+    pub fn revoked(&self) -> bool {
+        self.revoked
+    }
+
+    /// Returns `true` if the token is neither revoked nor expired at `time`.
+    pub fn active(&self, time: u64) -> bool {
+        !self.revoked && self.not_after.iter().all(|&t| time < t)
+    }
+}
+
+/// Response to the creation of a token. The generated password or secret is
+/// only returned once.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CreatedToken {
+    #[serde(flatten)]
+    token: TokenInfo,
+    #[serde(default)]
+    secret: Option<String>,
+}
+
+impl CreatedToken {
+    /// Creates a new CreatedToken object.
+    pub fn new(token: TokenInfo, secret: Option<String>) -> Self {
+        Self { token, secret }
+    }
+
+    /// Returns the created token.
+    #[inline]
+    pub fn token(&self) -> &TokenInfo {
+        &self.token
+    }
+
+    /// Returns the generated password or secret.
+    #[inline]
+    pub fn secret(&self) -> Option<&str> {
+        self.secret.as_deref()
+    }
+}
+
+/// Request to expire a token using the admin API.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Expiry {
+    #[serde(default)]
+    not_after: Option<u64>,
+}
+
+impl Expiry {
+    /// Creates a new Expiry object. Without a UNIX timestamp, the token expires
+    /// immediately.
+    pub fn new(not_after: Option<u64>) -> Self {
+        Self { not_after }
+    }
+
+    /// Returns the UNIX timestamp after which the token is no longer valid.
+    #[inline]
+    pub fn not_after(&self) -> Option<u64> {
+        self.not_after
+    }
+}
+
+/// Operation a scoped token may perform on a record.
+#[derive(Eq, PartialEq, Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// Creating or updating records
+    Update,
+    /// Deleting records
+    Delete,
+    /// Changing records that are owned by another identity or were not
+    /// created through the API. Scoped tokens only get this permission if it
+    /// is listed explicitly.
+    Override,
+}
+
+impl std::str::FromStr for Operation {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "update" => Ok(Operation::Update),
+            "delete" => Ok(Operation::Delete),
+            "override" => Ok(Operation::Override),
+            _ => Err(ApiError::ParseOperation),
+        }
+    }
+}
+
+/// Request to mint a short-lived token, whose scope is a subset of the scope
+/// of the requesting token.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Delegation {
+    names: Vec<String>,
+    #[serde(default)]
+    records: Vec<Record>,
+    #[serde(default)]
+    operations: Vec<Operation>,
+    ttl: u32,
+}
+
+impl Delegation {
+    /// Creates a new Delegation object for a token that may modify the records
+    /// matching `names` for `ttl` seconds.
+    pub fn new(names: Vec<String>, ttl: u32) -> Self {
+        Self {
+            names,
+            records: Vec::new(),
+            operations: Vec::new(),
+            ttl,
+        }
+    }
+
+    /// Restricts the token to the given record types. An empty list inherits
+    /// the record types of the requesting token.
+    pub fn set_records(&mut self, records: Vec<Record>) {
+        self.records = records;
+    }
+
+    /// Restricts the token to the given operations. An empty list inherits the
+    /// operations of the requesting token.
+    pub fn set_operations(&mut self, operations: Vec<Operation>) {
+        self.operations = operations;
+    }
+
+    /// Returns the name patterns the token may modify.
+    #[inline]
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the record types the token may modify.
+    #[inline]
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Returns the operations the token may perform.
+    #[inline]
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Returns the lifetime of the token in seconds.
+    #[inline]
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+}
+
+/// A delegated token. Requests are signed like with any other secret, using
+/// `key_id` as key id.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DelegatedToken {
+    key_id: String,
+    secret: String,
+    not_after: u64,
+}
+
+impl DelegatedToken {
+    /// Creates a new DelegatedToken object. `not_after` is a UNIX timestamp.
+    pub fn new(key_id: String, secret: String, not_after: u64) -> Self {
+        Self {
+            key_id,
+            secret,
+            not_after,
+        }
+    }
+
+    /// Returns a reference to the key id.
+    #[inline]
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Returns a reference to the secret.
+    #[inline]
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    /// Returns the UNIX timestamp after which the token is no longer valid.
+    #[inline]
+    pub fn not_after(&self) -> u64 {
+        self.not_after
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -378,6 +1238,24 @@ mod tests {
         assert!(validate_record_parsing(Record::ALIAS));
     }
 
+    #[test]
+    fn normalize_values() {
+        assert_eq!(Record::TXT.normalize_value("\"Token\""), "Token");
+        assert_eq!(Record::TXT.normalize_value("\"end.\""), "end.");
+        assert_eq!(Record::CNAME.normalize_value("WWW.Example.com."), "www.example.com");
+        assert_eq!(Record::MX.normalize_value("10 Mail.example.com."), "10 mail.example.com");
+    }
+
+    #[test]
+    fn match_leases() {
+        let lease = Lease::new("www.example.com.".to_owned(), Record::TXT, "a".to_owned(), 0);
+        assert!(lease.is_for("www.example.com", Record::TXT, None));
+        assert!(lease.is_for("WWW.example.com.", Record::TXT, Some("a")));
+        assert!(!lease.is_for("www.example.com", Record::TXT, Some("b")));
+        assert!(!lease.is_for("www.example.com", Record::A, None));
+        assert!(!lease.is_for("example.com", Record::TXT, None));
+    }
+
     fn validate_record_parsing(record: Record) -> bool {
         format!("{}", record).parse::<Record>().unwrap() == record
     }
@@ -0,0 +1,562 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use data::{Operation, Record};
+use failure::Error;
+use std::{fs, path::Path};
+
+/// Names, record types and operations a token may use. Empty lists of record
+/// types or operations allow all of them, except for `override`, that has to
+/// be listed explicitly.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Scope {
+    names: Vec<String>,
+    #[serde(default)]
+    records: Vec<Record>,
+    #[serde(default)]
+    operations: Vec<Operation>,
+}
+
+impl Scope {
+    pub fn new(names: Vec<String>, records: Vec<Record>, operations: Vec<Operation>) -> Self {
+        Self {
+            names,
+            records,
+            operations,
+        }
+    }
+
+    #[inline]
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    #[inline]
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Returns `true` if `name` matches one of the name patterns and `record`
+    /// is one of the allowed record types.
+    pub fn covers(&self, name: &str, record: Record) -> bool {
+        self.names.iter().any(|p| name_matches(p, name))
+            && (self.records.is_empty() || self.records.contains(&record))
+    }
+
+    /// Returns `true` if the scope covers the record and allows `operation`.
+    pub fn allows(&self, name: &str, record: Record, operation: Operation) -> bool {
+        self.covers(name, record) && self.permits(operation)
+    }
+
+    fn permits(&self, operation: Operation) -> bool {
+        self.operations.contains(&operation)
+            || (self.operations.is_empty() && operation != Operation::Override)
+    }
+
+    /// Returns `true` if everything `other` allows is allowed by this scope as
+    /// well.
+    pub fn contains(&self, other: &Scope) -> bool {
+        fn subset<T: PartialEq>(outer: &[T], inner: &[T]) -> bool {
+            outer.is_empty() || (!inner.is_empty() && inner.iter().all(|i| outer.contains(i)))
+        }
+        other
+            .names
+            .iter()
+            .all(|n| self.names.iter().any(|p| name_matches(p, n)))
+            && subset(&self.records, &other.records)
+            && subset(&self.operations, &other.operations)
+            && other.operations.iter().all(|&o| self.permits(o))
+    }
+}
+
+/// Matches a name against a pattern. Patterns are either fully qualified names
+/// or start with `*.` to match all names below a domain. A pattern also
+/// matches narrower patterns, e.g. `*.example.com` matches `*.a.example.com`.
+pub fn name_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.').to_lowercase();
+    let name = name.trim_end_matches('.').to_lowercase();
+    if pattern.starts_with("*.") {
+        name.ends_with(&pattern[1..])
+    } else {
+        pattern == name
+    }
+}
+
+/// A named identity that is restricted to a scope. The secret is used for
+/// HTTP basic auth and may be omitted, if the identity only signs requests
+/// using keys or secrets with its name as key id.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Token {
+    name: String,
+    #[serde(default)]
+    secret: Option<String>,
+    #[serde(flatten)]
+    scope: Scope,
+}
+
+/// The verified identity of a request and what it is allowed to do.
+#[derive(Clone, Debug)]
+pub struct Grant {
+    name: String,
+    owner: Option<String>,
+    scope: Option<Scope>,
+    admin: bool,
+    not_after: Option<u64>,
+}
+
+impl Grant {
+    /// Unrestricted access including the admin API, granted to the shared
+    /// secret given on the command line.
+    pub fn master() -> Self {
+        Self {
+            name: "master".to_owned(),
+            owner: None,
+            scope: None,
+            admin: true,
+            not_after: None,
+        }
+    }
+
+    /// Access to the records in `scope`, or to all records if it is `None`.
+    pub fn new(name: String, scope: Option<Scope>, admin: bool) -> Self {
+        Self {
+            name,
+            owner: None,
+            scope,
+            admin,
+            not_after: None,
+        }
+    }
+
+    /// Sets the identity that owns the records created using the credential,
+    /// if it is not the credential itself, e.g. for delegated tokens.
+    pub fn set_owner(&mut self, owner: Option<String>) {
+        self.owner = owner;
+    }
+
+    /// Sets the UNIX timestamp after which the credential is no longer valid.
+    pub fn set_not_after(&mut self, not_after: Option<u64>) {
+        self.not_after = not_after;
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the identity that owns the records created using the
+    /// credential.
+    #[inline]
+    pub fn owner(&self) -> &str {
+        self.owner.as_ref().unwrap_or(&self.name)
+    }
+
+    #[inline]
+    pub fn admin(&self) -> bool {
+        self.admin
+    }
+
+    #[inline]
+    pub fn scope(&self) -> Option<&Scope> {
+        self.scope.as_ref()
+    }
+
+    #[inline]
+    pub fn not_after(&self) -> Option<u64> {
+        self.not_after
+    }
+
+    /// Returns `true` if the records are visible to the credential, e.g. when
+    /// listing leases.
+    pub fn covers(&self, name: &str, record: Record) -> bool {
+        self.scope.iter().all(|scope| scope.covers(name, record))
+    }
+
+    pub fn allows(&self, name: &str, record: Record, operation: Operation) -> bool {
+        self.scope
+            .iter()
+            .all(|scope| scope.allows(name, record, operation))
+    }
+
+    /// Returns `true` if the credential may delegate `scope`.
+    pub fn contains(&self, scope: &Scope) -> bool {
+        self.scope.iter().all(|own| own.contains(scope))
+    }
+}
+
+impl From<&Token> for Grant {
+    fn from(token: &Token) -> Self {
+        Self::new(token.name.clone(), Some(token.scope.clone()), false)
+    }
+}
+
+/// The time span a key is valid in, in seconds since the epoch. Keys are
+/// valid from `not_before` (inclusive) until `not_after` (exclusive). Missing
+/// bounds are unlimited.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Validity {
+    #[serde(default, deserialize_with = "from_date")]
+    not_before: Option<u64>,
+    #[serde(default, deserialize_with = "from_date")]
+    not_after: Option<u64>,
+}
+
+impl Validity {
+    pub fn contains(&self, time: u64) -> bool {
+        self.not_before.iter().all(|&t| t <= time) && self.not_after.iter().all(|&t| time < t)
+    }
+}
+
+/// An Ed25519 public key, that can be used instead of the shared secret to
+/// sign requests. The id is the name of the token whose scope applies.
+#[derive(Deserialize, Clone, Debug)]
+pub struct PublicKey {
+    id: String,
+    #[serde(deserialize_with = "from_hex")]
+    public_key: Vec<u8>,
+    #[serde(flatten)]
+    validity: Validity,
+}
+
+/// A shared secret for HMAC signatures, selected by its key id. Several
+/// secrets may share an id, so secrets can be rotated by adding the new one
+/// before the old one expires. Like for public keys, the id is the name of
+/// the token whose scope applies.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Secret {
+    id: String,
+    secret: String,
+    #[serde(flatten)]
+    validity: Validity,
+}
+
+fn from_hex<'de, D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    use serde::de::{Deserialize, Error};
+    let hex = String::deserialize(deserializer)?;
+    ::crypto::hex_str_to_bytes(&hex).map_err(D::Error::custom)
+}
+
+fn from_date<'de, D: ::serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    use serde::de::{Deserialize, Error};
+    let date = String::deserialize(deserializer)?;
+    parse_date(&date)
+        .map(Some)
+        .ok_or_else(|| D::Error::custom(format!("invalid date: {}", date)))
+}
+
+/// Parses an UTC date (`2019-01-31`) or date and time (`2019-01-31T12:00:00Z`)
+/// into seconds since the epoch.
+pub fn parse_date(date: &str) -> Option<u64> {
+    let (date, time) = match date.find('T') {
+        Some(idx) => (&date[..idx], Some(date[idx + 1..].trim_end_matches('Z'))),
+        None => (date, None),
+    };
+    let mut parts = date.splitn(3, '-').map(str::parse::<u64>);
+    let (year, month, day) = (parts.next()?.ok()?, parts.next()?.ok()?, parts.next()?.ok()?);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let seconds = match time {
+        Some(time) => {
+            let mut parts = time.splitn(3, ':').map(str::parse::<u64>);
+            let (h, m, s) = (parts.next()?.ok()?, parts.next()?.ok()?, parts.next()?.ok()?);
+            if h > 23 || m > 59 || s > 60 {
+                return None;
+            }
+            h * 3600 + m * 60 + s
+        }
+        None => 0,
+    };
+    // days since the epoch of the proleptic gregorian calendar, shifting the
+    // year to start in March, so the leap day is the last day of the year
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some(days * 86_400 + seconds)
+}
+
+/// The scoped tokens, public keys and secrets, read from a TOML file:
+///
+/// ```toml
+/// [[token]]
+/// name = "router"
+/// secret = "topsecret"
+/// names = ["home.example.com", "*.dyn.example.com"]
+/// records = ["A", "AAAA"]
+///
+/// [[token]]
+/// name = "ci"
+/// names = ["*.ci.example.com"]
+///
+/// [[key]]
+/// id = "ci"
+/// public_key = "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c"
+///
+/// [[secret]]
+/// id = "ci"
+/// secret = "newsecret"
+/// not_before = "2019-01-01"
+/// ```
+#[derive(Deserialize, Default)]
+pub struct Tokens {
+    #[serde(default, rename = "token")]
+    tokens: Vec<Token>,
+    #[serde(default, rename = "key")]
+    keys: Vec<PublicKey>,
+    #[serde(default, rename = "secret")]
+    secrets: Vec<Secret>,
+}
+
+impl Tokens {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let tokens: Self = ::toml::from_str(&fs::read_to_string(path)?)?;
+        tokens.check()?;
+        Ok(tokens)
+    }
+
+    /// Checks that every key and secret belongs to a token.
+    fn check(&self) -> Result<(), Error> {
+        let ids = self.keys.iter().map(|k| &k.id).chain(self.secrets.iter().map(|s| &s.id));
+        for id in ids {
+            if !self.tokens.iter().any(|t| &t.name == id) {
+                bail!("No token for key id {}", id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies a request signature using the keys and secrets with id
+    /// `key_id`, that are valid at `time`. The grant is restricted to the
+    /// scope of the token named `key_id`.
+    pub fn verify(&self, key_id: &str, msg: &[u8], signature: &[u8], time: u64) -> Option<Grant> {
+        let token = self.tokens.iter().find(|t| t.name == key_id)?;
+        let valid = self
+            .keys
+            .iter()
+            .filter(|k| k.id == key_id && k.validity.contains(time))
+            .any(|k| ::crypto::verify_ed25519(&k.public_key, msg, signature))
+            || self
+                .secrets
+                .iter()
+                .filter(|s| s.id == key_id && s.validity.contains(time))
+                .any(|s| ::crypto::verify_signature(s.secret.as_bytes(), msg, signature));
+        if valid {
+            Some(Grant::from(token))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the token matching the HTTP basic auth credentials.
+    pub fn basic_auth(&self, user: &str, password: &str) -> Option<&Token> {
+        self.tokens.iter().find(|t| {
+            t.name == user
+                && t.secret.as_ref().is_some_and(|secret| {
+                    ::crypto::constant_time_eq(secret.as_bytes(), password.as_bytes())
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_names() {
+        assert!(name_matches("home.example.com", "home.example.com"));
+        assert!(name_matches("home.example.com.", "HOME.example.com"));
+        assert!(!name_matches("home.example.com", "www.home.example.com"));
+        assert!(name_matches("*.example.com", "www.example.com"));
+        assert!(name_matches("*.example.com", "a.b.example.com."));
+        assert!(!name_matches("*.example.com", "example.com"));
+        assert!(!name_matches("*.example.com", "www.badexample.com"));
+    }
+
+    #[test]
+    fn scope_records() {
+        let scope = Scope::new(
+            vec!["*.example.com".to_owned()],
+            vec![Record::A, Record::AAAA],
+            vec![],
+        );
+        assert!(scope.allows("www.example.com", Record::A, Operation::Update));
+        assert!(!scope.allows("www.example.com", Record::TXT, Operation::Update));
+        assert!(!scope.allows("www.example.org", Record::A, Operation::Delete));
+        assert!(!scope.allows("www.example.com", Record::A, Operation::Override));
+        let scope = Scope::new(
+            vec!["*.example.com".to_owned()],
+            vec![],
+            vec![Operation::Update],
+        );
+        assert!(scope.allows("www.example.com", Record::TXT, Operation::Update));
+        assert!(!scope.allows("www.example.com", Record::TXT, Operation::Delete));
+        assert!(scope.covers("www.example.com", Record::TXT));
+    }
+
+    #[test]
+    fn scope_subset() {
+        let parent = Scope::new(vec!["*.example.com".to_owned()], vec![Record::TXT], vec![]);
+        let child = |names: &[&str], records, operations| {
+            Scope::new(
+                names.iter().map(|n| n.to_string()).collect(),
+                records,
+                operations,
+            )
+        };
+        assert!(parent.contains(&child(
+            &["_acme-challenge.www.example.com"],
+            vec![Record::TXT],
+            vec![Operation::Update]
+        )));
+        assert!(parent.contains(&child(&["*.ci.example.com"], vec![Record::TXT], vec![])));
+        assert!(!parent.contains(&child(&["example.com"], vec![Record::TXT], vec![])));
+        assert!(!parent.contains(&child(&["www.example.org"], vec![Record::TXT], vec![])));
+        assert!(!parent.contains(&child(&["www.example.com"], vec![Record::A], vec![])));
+        assert!(!parent.contains(&child(&["www.example.com"], vec![], vec![])));
+        let update = child(&["*.example.com"], vec![Record::TXT], vec![Operation::Update]);
+        assert!(!update.contains(&child(&["www.example.com"], vec![Record::TXT], vec![])));
+        assert!(!parent.contains(&child(
+            &["www.example.com"],
+            vec![Record::TXT],
+            vec![Operation::Override]
+        )));
+    }
+
+    #[test]
+    fn parse_keys() {
+        let tokens: Tokens = ::toml::from_str(
+            r#"
+            [[token]]
+            name = "ci"
+            names = ["*.ci.example.com"]
+
+            [[key]]
+            id = "ci"
+            public_key = "00ff"
+            "#,
+        ).unwrap();
+        assert_eq!(tokens.keys[0].public_key, &[0, 255]);
+        assert!(tokens.check().is_ok());
+        assert!(tokens.basic_auth("ci", "").is_none());
+        let orphan: Tokens =
+            ::toml::from_str("[[key]]\nid = \"ci\"\npublic_key = \"00ff\"").unwrap();
+        assert!(orphan.check().is_err());
+        assert!(::toml::from_str::<Tokens>("[[key]]\nid = \"ci\"\npublic_key = \"xyz\"").is_err());
+    }
+
+    #[test]
+    fn parse_dates() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2000-03-01"), Some(951_868_800));
+        assert_eq!(parse_date("2018-12-19T15:40:00Z"), Some(1_545_234_000));
+        assert_eq!(parse_date("2018-13-01"), None);
+        assert_eq!(parse_date("2018-12-19T25:00:00Z"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn rotate_secrets() {
+        let tokens: Tokens = ::toml::from_str(
+            r#"
+            [[token]]
+            name = "ci"
+            names = ["*.ci.example.com"]
+
+            [[secret]]
+            id = "ci"
+            secret = "old"
+            not_after = "2019-01-02"
+
+            [[secret]]
+            id = "ci"
+            secret = "new"
+            not_before = "2019-01-01"
+            "#,
+        ).unwrap();
+        let old = ::crypto::sign(b"old", b"msg");
+        let new = ::crypto::sign(b"new", b"msg");
+        let overlap = parse_date("2019-01-01T12:00:00Z").unwrap();
+        assert!(tokens.verify("ci", b"msg", &old, overlap).is_some());
+        assert!(tokens.verify("ci", b"msg", &new, overlap).is_some());
+        assert!(tokens.verify("ci", b"msg", &new, overlap - 86_400).is_none());
+        assert!(tokens.verify("ci", b"msg", &old, overlap + 86_400).is_none());
+        assert!(tokens.verify("other", b"msg", &new, overlap).is_none());
+    }
+
+    #[test]
+    fn scope_keys() {
+        let pkcs8 = ::crypto::generate_ed25519_key().unwrap();
+        let public_key = ::crypto::bytes_to_hex_str(&::crypto::ed25519_public_key(&pkcs8).unwrap());
+        let tokens: Tokens = ::toml::from_str(&format!(
+            r#"
+            [[token]]
+            name = "ci"
+            names = ["*.ci.example.com"]
+            records = ["TXT"]
+
+            [[key]]
+            id = "ci"
+            public_key = "{}"
+
+            [[key]]
+            id = "orphan"
+            public_key = "{}"
+            "#,
+            public_key, public_key
+        )).unwrap();
+        let signature = ::crypto::sign_ed25519(&pkcs8, b"msg").unwrap();
+        let grant = tokens.verify("ci", b"msg", &signature, 0).unwrap();
+        assert_eq!(grant.name(), "ci");
+        assert!(!grant.admin());
+        assert!(grant.allows("_acme-challenge.ci.example.com", Record::TXT, Operation::Update));
+        assert!(!grant.allows("www.example.com", Record::TXT, Operation::Update));
+        assert!(!grant.allows("www.ci.example.com", Record::A, Operation::Update));
+        assert!(tokens.verify("orphan", b"msg", &signature, 0).is_none());
+    }
+}
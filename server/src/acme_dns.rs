@@ -0,0 +1,513 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Implementation of the acme-dns API, so ACME clients with acme-dns support
+//! can solve DNS-01 challenges. Every registered account gets a dedicated name
+//! in the challenge zone, which is used as the target of the
+//! `_acme-challenge` CNAME of the domains to validate. The account is the
+//! identity that owns this name.
+
+use acl::{Grant, Scope};
+use actix_web::{
+    dev::HttpResponseBuilder, error::{self, ErrorInternalServerError}, HttpRequest, HttpResponse,
+};
+use data::{Delete, Record, Update};
+use failure::Error;
+use nsupdate;
+use owner::OwnerError;
+use std::{net::IpAddr, path::Path, sync::Arc};
+use store::JsonFile;
+use util::Config;
+
+const TTL: u32 = 60;
+const USER_HEADER: &str = "X-Api-User";
+const KEY_HEADER: &str = "X-Api-Key";
+const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+/// Maximum number of networks an account may be restricted to.
+const MAX_ALLOWFROM: usize = 16;
+
+#[derive(Deserialize, Serialize, Clone)]
+struct Account {
+    username: String,
+    salt: String,
+    password: String,
+    subdomain: String,
+    #[serde(default)]
+    allowfrom: Vec<String>,
+    #[serde(default)]
+    txt: Vec<String>,
+}
+
+pub struct AcmeDns {
+    zone: String,
+    accounts: JsonFile<Vec<Account>>,
+    register_from: Vec<String>,
+    max_accounts: usize,
+    proxies: Vec<IpAddr>,
+}
+
+impl AcmeDns {
+    pub fn open<P: AsRef<Path>>(zone: String, path: P) -> Result<Self, Error> {
+        Ok(Self {
+            zone: zone.trim_end_matches('.').to_owned(),
+            accounts: JsonFile::open(path.as_ref())?,
+            register_from: Vec::new(),
+            max_accounts: 1000,
+            proxies: Vec::new(),
+        })
+    }
+
+    /// Allows clients from the networks to register accounts. Registration is
+    /// disabled if no network is given.
+    pub fn set_register_from(&mut self, register_from: Vec<String>) -> Result<(), Error> {
+        if let Some(cidr) = register_from.iter().find(|c| parse_cidr(c).is_none()) {
+            bail!("invalid network {}", cidr);
+        }
+        self.register_from = register_from;
+        Ok(())
+    }
+
+    pub fn set_max_accounts(&mut self, max_accounts: usize) {
+        self.max_accounts = max_accounts;
+    }
+
+    /// Trusts the `X-Forwarded-For` header in requests from these reverse
+    /// proxies.
+    pub fn set_proxies(&mut self, proxies: Vec<IpAddr>) {
+        self.proxies = proxies;
+    }
+
+    fn fulldomain(&self, subdomain: &str) -> String {
+        format!("{}.{}", subdomain, self.zone)
+    }
+
+    /// Sets the TXT RRset of the account to its most recent value and `txt`,
+    /// so a wildcard and a non-wildcard certificate can be validated at once.
+    fn set_txt(&self, account: &Account, txt: &str, config: &Config) -> Result<(), Error> {
+        let name = self.fulldomain(&account.subdomain);
+        let mut values: Vec<_> = account.txt.iter().rev().take(1).cloned().collect();
+        values.push(txt.to_owned());
+        let updates: Vec<_> = values
+            .iter()
+            .map(|t| Update::new(name.clone(), t.clone(), Record::TXT, TTL))
+            .collect();
+        info!("acme-dns: updating TXT record for {}", name);
+        let grant = Grant::new(
+            format!("acme-dns:{}", account.username),
+            Some(Scope::new(vec![name.clone()], vec![Record::TXT], Vec::new())),
+            false,
+        );
+        nsupdate::apply(
+            &grant,
+            &[&Delete::new(name, Record::TXT)],
+            &updates.iter().collect::<Vec<_>>(),
+            false,
+            config,
+        )?;
+        self.accounts.update(|accounts| {
+            if let Some(a) = accounts.iter_mut().find(|a| a.username == account.username) {
+                a.txt = values;
+            }
+        })
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct RegisterRequest {
+    #[serde(default)]
+    allowfrom: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Registration {
+    username: String,
+    password: String,
+    fulldomain: String,
+    subdomain: String,
+    allowfrom: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct UpdateRequest {
+    subdomain: String,
+    txt: String,
+}
+
+#[derive(Serialize)]
+struct UpdateResponse {
+    txt: String,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse<'a> {
+    error: &'a str,
+}
+
+fn fail(mut builder: HttpResponseBuilder, error: &str) -> HttpResponse {
+    builder.json(ErrorResponse { error })
+}
+
+fn uuid() -> Result<String, Error> {
+    let mut b = ::crypto::random_bytes(16)?;
+    // random UUID (version 4, variant 1)
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let hex = ::crypto::bytes_to_hex_str(&b);
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+/// Parses a network like `192.0.2.0/24` into address and prefix length. A
+/// single address is a network with the full prefix length.
+fn parse_cidr(cidr: &str) -> Option<(IpAddr, u32)> {
+    let mut parts = cidr.splitn(2, '/');
+    let net: IpAddr = parts.next()?.parse().ok()?;
+    let max = if net.is_ipv4() { 32 } else { 128 };
+    let len = match parts.next() {
+        Some(len) if !len.is_empty() && len.bytes().all(|b| b.is_ascii_digit()) => {
+            len.parse().ok()?
+        }
+        Some(_) => return None,
+        None => max,
+    };
+    if len > max {
+        None
+    } else {
+        Some((net, len))
+    }
+}
+
+/// Returns `true` if `ip` is part of the network `cidr`, e.g. `192.0.2.0/24`.
+/// Returns `None` if `cidr` cannot be parsed.
+fn cidr_contains(cidr: &str, ip: IpAddr) -> Option<bool> {
+    let (net, len) = parse_cidr(cidr)?;
+    Some(match (net, ip) {
+        (IpAddr::V4(net), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - len).unwrap_or(0);
+            u32::from(net) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(net), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - len).unwrap_or(0);
+            u128::from(net) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    })
+}
+
+/// Returns the address of the client. Behind a trusted proxy, this is the
+/// address the proxy appended to `X-Forwarded-For`, otherwise the address of
+/// the peer. Headers sent by other peers are ignored, as they can be forged.
+fn client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let peer = peer?;
+    if !proxies.contains(&peer) {
+        return Some(peer);
+    }
+    forwarded_for?.rsplit(',').next()?.trim().parse().ok()
+}
+
+fn valid_txt(txt: &str) -> bool {
+    txt.len() == 43
+        && txt
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn remote_ip(req: &HttpRequest<Arc<Config>>, acme_dns: &AcmeDns) -> Option<IpAddr> {
+    let forwarded_for = req
+        .headers()
+        .get(FORWARDED_FOR_HEADER)
+        .and_then(|h| h.to_str().ok());
+    client_ip(
+        req.peer_addr().map(|addr| addr.ip()),
+        forwarded_for,
+        &acme_dns.proxies,
+    )
+}
+
+/// Handles `POST /register`, if registration is enabled. The body may restrict
+/// the addresses that are allowed to update the new account:
+/// `{"allowfrom": ["192.0.2.0/24"]}`.
+pub fn register(
+    (body, req): (String, HttpRequest<Arc<Config>>),
+) -> Result<HttpResponse, error::Error> {
+    let acme_dns = match req.state().acme_dns() {
+        Some(acme_dns) if !acme_dns.register_from.is_empty() => acme_dns,
+        _ => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let allowed = remote_ip(&req, acme_dns).is_some_and(|ip| {
+        acme_dns
+            .register_from
+            .iter()
+            .any(|c| cidr_contains(c, ip).unwrap_or(false))
+    });
+    if !allowed {
+        return Ok(fail(HttpResponse::Forbidden(), "forbidden"));
+    }
+    if acme_dns.accounts.read(Vec::len) >= acme_dns.max_accounts {
+        warn!("acme-dns: account limit of {} reached", acme_dns.max_accounts);
+        return Ok(fail(HttpResponse::Forbidden(), "account_limit_reached"));
+    }
+    let request: RegisterRequest = if body.trim().is_empty() {
+        RegisterRequest::default()
+    } else {
+        match ::serde_json::from_str(&body) {
+            Ok(request) => request,
+            Err(_) => return Ok(fail(HttpResponse::BadRequest(), "malformed_json_payload")),
+        }
+    };
+    if request.allowfrom.len() > MAX_ALLOWFROM
+        || request.allowfrom.iter().any(|c| parse_cidr(c).is_none())
+    {
+        return Ok(fail(HttpResponse::BadRequest(), "invalid_allowfrom_cidr"));
+    }
+    let internal = |_| ErrorInternalServerError("Error registering account");
+    let username = uuid().map_err(internal)?;
+    let subdomain = uuid().map_err(internal)?;
+    let password = ::crypto::random_bytes(20)
+        .map(|b| ::crypto::bytes_to_hex_str(&b))
+        .map_err(internal)?;
+    let salt = ::crypto::random_bytes(16).map_err(internal)?;
+    let account = Account {
+        username: username.clone(),
+        salt: ::crypto::bytes_to_hex_str(&salt),
+        password: ::crypto::bytes_to_hex_str(&::crypto::hash_password(
+            password.as_bytes(),
+            &salt,
+        )),
+        subdomain: subdomain.clone(),
+        allowfrom: request.allowfrom.clone(),
+        txt: Vec::new(),
+    };
+    acme_dns
+        .accounts
+        .update(|accounts| accounts.push(account))
+        .map_err(internal)?;
+    info!("acme-dns: registered account {}", username);
+    Ok(HttpResponse::Created().json(Registration {
+        username,
+        password,
+        fulldomain: acme_dns.fulldomain(&subdomain),
+        subdomain,
+        allowfrom: request.allowfrom,
+    }))
+}
+
+fn authenticate(req: &HttpRequest<Arc<Config>>, accounts: &[Account]) -> Option<Account> {
+    let header = |name: &str| req.headers().get(name)?.to_str().ok();
+    let (user, key) = (header(USER_HEADER)?, header(KEY_HEADER)?);
+    let account = accounts.iter().find(|a| a.username == user)?;
+    let salt = ::crypto::hex_str_to_bytes(&account.salt).ok()?;
+    let hash = ::crypto::hex_str_to_bytes(&account.password).ok()?;
+    if ::crypto::verify_password(key.as_bytes(), &salt, &hash) {
+        Some(account.clone())
+    } else {
+        None
+    }
+}
+
+/// Handles `POST /update`.
+pub fn update(
+    (body, req): (String, HttpRequest<Arc<Config>>),
+) -> Result<HttpResponse, error::Error> {
+    let config = req.state();
+    let acme_dns = match config.acme_dns() {
+        Some(acme_dns) => acme_dns,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let account = match acme_dns.accounts.read(|a| authenticate(&req, a)) {
+        Some(account) => account,
+        None => return Ok(fail(HttpResponse::Unauthorized(), "forbidden")),
+    };
+    if !account.allowfrom.is_empty() {
+        let allowed = remote_ip(&req, acme_dns).is_some_and(|ip| {
+            account
+                .allowfrom
+                .iter()
+                .any(|c| cidr_contains(c, ip).unwrap_or(false))
+        });
+        if !allowed {
+            return Ok(fail(HttpResponse::Unauthorized(), "forbidden"));
+        }
+    }
+    let request: UpdateRequest = match ::serde_json::from_str(&body) {
+        Ok(request) => request,
+        Err(_) => return Ok(fail(HttpResponse::BadRequest(), "malformed_json_payload")),
+    };
+    if request.subdomain != account.subdomain {
+        return Ok(fail(HttpResponse::Unauthorized(), "forbidden"));
+    }
+    if !valid_txt(&request.txt) {
+        return Ok(fail(HttpResponse::BadRequest(), "bad_txt"));
+    }
+    match acme_dns.set_txt(&account, &request.txt, config) {
+        Ok(()) => Ok(HttpResponse::Ok().json(UpdateResponse { txt: request.txt })),
+        Err(ref e) if e.downcast_ref::<OwnerError>().is_some() => {
+            Ok(fail(HttpResponse::Forbidden(), "forbidden"))
+        }
+        Err(e) => {
+            error!("acme-dns: cannot update TXT record: {}", e);
+            Err(ErrorInternalServerError("Error updating the challenge record"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Db;
+
+    #[test]
+    fn cidr() {
+        let ip = "192.0.2.17".parse().unwrap();
+        assert_eq!(cidr_contains("192.0.2.0/24", ip), Some(true));
+        assert_eq!(cidr_contains("192.0.3.0/24", ip), Some(false));
+        assert_eq!(cidr_contains("192.0.2.17", ip), Some(true));
+        assert_eq!(cidr_contains("0.0.0.0/0", ip), Some(true));
+        assert_eq!(cidr_contains("2001:db8::/32", ip), Some(false));
+        assert_eq!(cidr_contains("192.0.2.0/33", ip), None);
+        let ip = "2001:db8::1".parse().unwrap();
+        assert_eq!(cidr_contains("2001:db8::/32", ip), Some(true));
+        assert_eq!(cidr_contains("2001:db9::/32", ip), Some(false));
+        assert_eq!(cidr_contains("::/0", ip), Some(true));
+    }
+
+    #[test]
+    fn invalid_cidrs() {
+        for cidr in &[
+            "2001:db8::/999",
+            "2001:db8::/129",
+            "192.0.2.0/33",
+            "192.0.2.0/",
+            "192.0.2.0/+8",
+            "192.0.2.0/24/8",
+            "192.0.2/24",
+            "example.com/24",
+            "",
+        ] {
+            assert_eq!(parse_cidr(cidr), None, "{}", cidr);
+            // the address family of the client does not matter
+            assert_eq!(cidr_contains(cidr, "192.0.2.1".parse().unwrap()), None);
+            assert_eq!(cidr_contains(cidr, "2001:db8::1".parse().unwrap()), None);
+        }
+        assert_eq!(
+            parse_cidr("2001:db8::/128"),
+            Some(("2001:db8::".parse().unwrap(), 128))
+        );
+    }
+
+    #[test]
+    fn refuse_foreign_rrsets() {
+        let dir = ::testutil::temp_dir("acme-dns");
+        let mut config = ::testutil::fake_nsupdate(&dir, "cat > /dev/null\n");
+        config.set_dig(::testutil::script(
+            &dir,
+            "dig",
+            "case \"$*\" in\n*taken.acme.example.com*) \
+             echo 'taken.acme.example.com. 60 IN TXT \"a\"' ;;\nesac\n",
+        ));
+        config.set_enforce_owners(true);
+        let db = Db::open(dir.join("db")).unwrap();
+        let update =
+            Update::new("taken.acme.example.com".to_owned(), "a".to_owned(), Record::TXT, 60);
+        db.record(&Grant::new("alice".to_owned(), None, false), &[], &[&update])
+            .unwrap();
+        config.set_db(db);
+        let acme_dns =
+            AcmeDns::open("acme.example.com".to_owned(), dir.join("accounts.json")).unwrap();
+        let account = |subdomain: &str| Account {
+            username: subdomain.to_owned(),
+            salt: String::new(),
+            password: String::new(),
+            subdomain: subdomain.to_owned(),
+            allowfrom: Vec::new(),
+            txt: Vec::new(),
+        };
+        acme_dns
+            .accounts
+            .update(|accounts| accounts.extend(vec![account("taken"), account("free")]))
+            .unwrap();
+
+        let txt = "a".repeat(43);
+        match acme_dns
+            .set_txt(&account("taken"), &txt, &config)
+            .map_err(|e| e.downcast::<OwnerError>())
+        {
+            Err(Ok(OwnerError::Foreign(_, _, owner))) => assert_eq!(owner, "alice"),
+            _ => panic!("expected a foreign RRset"),
+        }
+        acme_dns.set_txt(&account("free"), &txt, &config).unwrap();
+        assert_eq!(acme_dns.accounts.read(|a| a[1].txt.clone()), vec![txt]);
+        let owner = config
+            .db()
+            .unwrap()
+            .owner("free.acme.example.com", Record::TXT)
+            .unwrap()
+            .unwrap();
+        assert_eq!(owner.owner(), "acme-dns:free");
+        drop(config);
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn forwarded_addresses() {
+        let proxy = "127.0.0.1".parse().ok();
+        let client = "192.0.2.1".parse().ok();
+        let proxies = ["127.0.0.1".parse().unwrap()];
+        // headers of untrusted peers are ignored
+        assert_eq!(client_ip(client, Some("198.51.100.1"), &proxies), client);
+        assert_eq!(client_ip(client, Some("198.51.100.1"), &[]), client);
+        // the last address was added by the proxy, earlier ones by the client
+        assert_eq!(
+            client_ip(proxy, Some("198.51.100.1, 192.0.2.1"), &proxies),
+            client
+        );
+        assert_eq!(client_ip(proxy, None, &proxies), None);
+        assert_eq!(client_ip(None, Some("192.0.2.1"), &proxies), None);
+    }
+}
@@ -0,0 +1,424 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Admin API to manage scoped tokens at runtime. Tokens are persisted in a
+//! JSON file. Passwords for HTTP basic auth are only stored as salted hash,
+//! secrets for HMAC signatures have to be stored as is, because the server
+//! needs them to verify signatures.
+
+use acl::{Grant, Scope};
+use actix_web::{
+    error::{
+        self, ErrorBadRequest, ErrorConflict, ErrorForbidden, ErrorInternalServerError,
+        ErrorNotFound,
+    },
+    HttpResponse, Json, Path, State,
+};
+use data::{CreatedToken, CredentialKind, Expiry, NewToken, TokenInfo};
+use failure::Error;
+use lease::now;
+use std::sync::Arc;
+use store::JsonFile;
+use util::{Authenticated, Config, Validated};
+
+#[derive(Debug, Fail)]
+pub enum TokenError {
+    #[fail(display = "Invalid token name")]
+    InvalidName,
+    #[fail(display = "Tokens need at least one name pattern")]
+    NoNames,
+    #[fail(display = "Missing or invalid public key")]
+    PublicKey,
+    #[fail(display = "Token exists")]
+    Exists,
+    #[fail(display = "No such token")]
+    NotFound,
+    #[fail(display = "The expiry of a token can only be set to an earlier time")]
+    Extended,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Credential {
+    Password { salt: String, hash: String },
+    Secret { secret: String },
+    Ed25519 { public_key: String },
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct StoredToken {
+    #[serde(flatten)]
+    info: TokenInfo,
+    credential: Credential,
+}
+
+impl StoredToken {
+    fn grant(&self) -> Grant {
+        let mut grant = Grant::new(
+            self.info.name().to_owned(),
+            Some(Scope::new(
+                self.info.names().to_vec(),
+                self.info.records().to_vec(),
+                self.info.operations().to_vec(),
+            )),
+            self.info.admin(),
+        );
+        grant.set_not_after(self.info.not_after());
+        grant
+    }
+}
+
+pub type TokenStore = JsonFile<Vec<StoredToken>>;
+
+/// Verifies a request signature using the active token named `key_id`.
+pub fn verify(store: &TokenStore, key_id: &str, msg: &[u8], signature: &[u8]) -> Option<Grant> {
+    let time = now();
+    store.read(|tokens| {
+        tokens
+            .iter()
+            .filter(|t| t.info.name() == key_id && t.info.active(time))
+            .find(|t| match t.credential {
+                Credential::Secret { ref secret } => {
+                    ::crypto::verify_signature(secret.as_bytes(), msg, signature)
+                }
+                Credential::Ed25519 { ref public_key } => ::crypto::hex_str_to_bytes(public_key)
+                    .map(|key| ::crypto::verify_ed25519(&key, msg, signature))
+                    .unwrap_or(false),
+                Credential::Password { .. } => false,
+            }).map(StoredToken::grant)
+    })
+}
+
+/// Returns the active token matching the HTTP basic auth credentials.
+pub fn basic_auth(store: &TokenStore, user: &str, password: &str) -> Option<Grant> {
+    let time = now();
+    store.read(|tokens| {
+        tokens
+            .iter()
+            .filter(|t| t.info.name() == user && t.info.active(time))
+            .find(|t| match t.credential {
+                Credential::Password { ref salt, ref hash } => {
+                    match (
+                        ::crypto::hex_str_to_bytes(salt),
+                        ::crypto::hex_str_to_bytes(hash),
+                    ) {
+                        (Ok(salt), Ok(hash)) => {
+                            ::crypto::verify_password(password.as_bytes(), &salt, &hash)
+                        }
+                        _ => false,
+                    }
+                }
+                _ => false,
+            }).map(StoredToken::grant)
+    })
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+fn random_secret() -> Result<String, Error> {
+    Ok(::crypto::bytes_to_hex_str(&::crypto::random_bytes(32)?))
+}
+
+/// Creates a token with a generated password or secret and returns it
+/// together with the password or secret.
+pub fn create_token(store: &TokenStore, request: &NewToken) -> Result<CreatedToken, Error> {
+    if !valid_name(request.name()) {
+        return Err(TokenError::InvalidName.into());
+    }
+    if request.names().is_empty() {
+        return Err(TokenError::NoNames.into());
+    }
+    let (credential, secret) = match request.kind() {
+        CredentialKind::Password => {
+            let password = random_secret()?;
+            let salt = ::crypto::random_bytes(16)?;
+            let hash = ::crypto::hash_password(password.as_bytes(), &salt);
+            (
+                Credential::Password {
+                    salt: ::crypto::bytes_to_hex_str(&salt),
+                    hash: ::crypto::bytes_to_hex_str(&hash),
+                },
+                Some(password),
+            )
+        }
+        CredentialKind::Secret => {
+            let secret = random_secret()?;
+            (
+                Credential::Secret {
+                    secret: secret.clone(),
+                },
+                Some(secret),
+            )
+        }
+        CredentialKind::Ed25519 => {
+            let public_key = request
+                .public_key()
+                .filter(|k| ::crypto::hex_str_to_bytes(k).map(|k| k.len() == 32).unwrap_or(false))
+                .ok_or(TokenError::PublicKey)?;
+            (
+                Credential::Ed25519 {
+                    public_key: public_key.to_owned(),
+                },
+                None,
+            )
+        }
+    };
+    let info = TokenInfo::new(request, now());
+    let token = StoredToken {
+        info: info.clone(),
+        credential,
+    };
+    store.update(|tokens| {
+        if tokens.iter().any(|t| t.info.name() == request.name()) {
+            return Err(TokenError::Exists.into());
+        }
+        tokens.push(token);
+        Ok(CreatedToken::new(info, secret))
+    })?
+}
+
+/// Returns all tokens, including revoked and expired ones.
+pub fn tokens(store: &TokenStore) -> Vec<TokenInfo> {
+    store.read(|tokens| tokens.iter().map(|t| t.info.clone()).collect())
+}
+
+/// Returns the token named `name`.
+pub fn token(store: &TokenStore, name: &str) -> Result<TokenInfo, Error> {
+    store
+        .read(|tokens| {
+            tokens
+                .iter()
+                .find(|t| t.info.name() == name)
+                .map(|t| t.info.clone())
+        }).ok_or_else(|| TokenError::NotFound.into())
+}
+
+/// Applies `f` to the token named `name` and returns the updated token.
+fn modify<F: FnOnce(&mut TokenInfo) -> Result<(), Error>>(
+    store: &TokenStore,
+    name: &str,
+    f: F,
+) -> Result<TokenInfo, Error> {
+    store.update(|tokens| {
+        let token = tokens
+            .iter_mut()
+            .find(|t| t.info.name() == name)
+            .ok_or(TokenError::NotFound)?;
+        f(&mut token.info)?;
+        Ok(token.info.clone())
+    })?
+}
+
+/// Revokes the token named `name`. Revoked tokens are kept, so their name
+/// cannot be reused by accident.
+pub fn revoke_token(store: &TokenStore, name: &str) -> Result<TokenInfo, Error> {
+    modify(store, name, |token| {
+        token.revoke();
+        Ok(())
+    })
+}
+
+/// Lets the token named `name` expire at `not_after`. The expiry cannot be
+/// extended, so an expired token cannot be reactivated.
+pub fn expire_token(store: &TokenStore, name: &str, not_after: u64) -> Result<TokenInfo, Error> {
+    modify(store, name, |token| {
+        if token.not_after().is_some_and(|current| not_after > current) {
+            return Err(TokenError::Extended.into());
+        }
+        token.set_not_after(Some(not_after));
+        Ok(())
+    })
+}
+
+fn store<'a>(config: &'a Config, grant: &Grant) -> Result<&'a TokenStore, error::Error> {
+    if !grant.admin() {
+        return Err(ErrorForbidden("Admin access required"));
+    }
+    config
+        .token_store()
+        .ok_or_else(|| ErrorNotFound("Token store is not enabled"))
+}
+
+fn respond_error(e: Error) -> error::Error {
+    match e.downcast::<TokenError>() {
+        Ok(TokenError::Exists) => ErrorConflict(TokenError::Exists.to_string()),
+        Ok(TokenError::NotFound) => ErrorNotFound(TokenError::NotFound.to_string()),
+        Ok(e) => ErrorBadRequest(e.to_string()),
+        Err(e) => {
+            error!("cannot update the token store: {}", e);
+            ErrorInternalServerError("Error persisting tokens")
+        }
+    }
+}
+
+/// Handles `POST /admin/tokens`. The generated password or secret is part of
+/// the response and cannot be retrieved later.
+pub fn create(
+    (request, state): (Validated<NewToken>, State<Arc<Config>>),
+) -> Result<HttpResponse, error::Error> {
+    let store = store(&state, request.grant())?;
+    let created = create_token(store, &request).map_err(respond_error)?;
+    info!(
+        "{} created token {}",
+        request.grant().name(),
+        request.name()
+    );
+    Ok(HttpResponse::Created().json(created))
+}
+
+/// Handles `GET /admin/tokens`.
+pub fn list(
+    (auth, state): (Authenticated, State<Arc<Config>>),
+) -> Result<Json<Vec<TokenInfo>>, error::Error> {
+    store(&state, &auth).map(|store| Json(tokens(store)))
+}
+
+/// Handles `GET /admin/tokens/{name}`.
+pub fn show(
+    (auth, name, state): (Authenticated, Path<String>, State<Arc<Config>>),
+) -> Result<Json<TokenInfo>, error::Error> {
+    let store = store(&state, &auth)?;
+    token(store, &name).map(Json).map_err(respond_error)
+}
+
+/// Handles `DELETE /admin/tokens/{name}`.
+pub fn revoke(
+    (auth, name, state): (Authenticated, Path<String>, State<Arc<Config>>),
+) -> Result<Json<TokenInfo>, error::Error> {
+    let store = store(&state, &auth)?;
+    info!("{} revoked token {}", auth.name(), name.as_str());
+    revoke_token(store, &name).map(Json).map_err(respond_error)
+}
+
+/// Handles `POST /admin/tokens/{name}/expire`. Without a timestamp in the
+/// body, the token expires immediately.
+pub fn expire(
+    (expiry, name, state): (Validated<Expiry>, Path<String>, State<Arc<Config>>),
+) -> Result<Json<TokenInfo>, error::Error> {
+    let store = store(&state, expiry.grant())?;
+    let not_after = expiry.not_after().unwrap_or_else(now);
+    info!(
+        "{} set expiry of token {} to {}",
+        expiry.grant().name(),
+        name.as_str(),
+        not_after
+    );
+    expire_token(store, &name, not_after)
+        .map(Json)
+        .map_err(respond_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::{Operation, Record};
+    use std::fs;
+
+    fn request(name: &str, kind: CredentialKind) -> NewToken {
+        NewToken::new(name.to_owned(), kind, vec!["*.ci.example.com".to_owned()])
+    }
+
+    fn token_error<T>(result: Result<T, Error>) -> Option<TokenError> {
+        result.err().and_then(|e| e.downcast().ok())
+    }
+
+    #[test]
+    fn manage_tokens() {
+        let dir = ::testutil::temp_dir("admin");
+        let path = dir.join("tokens.json");
+        let store = TokenStore::open(&path).unwrap();
+
+        let mut ci = request("ci", CredentialKind::Password);
+        ci.set_records(vec![Record::TXT]);
+        ci.set_operations(vec![Operation::Update]);
+        let password = create_token(&store, &ci).unwrap().secret().unwrap().to_owned();
+        match token_error(create_token(&store, &ci)) {
+            Some(TokenError::Exists) => (),
+            _ => panic!("expected an existing token"),
+        }
+        assert!(create_token(&store, &request("c i", CredentialKind::Secret)).is_err());
+        assert!(create_token(&store, &request("key", CredentialKind::Ed25519)).is_err());
+        let deploy = create_token(&store, &request("deploy", CredentialKind::Secret)).unwrap();
+        assert!(deploy.secret().is_some());
+        let names: Vec<_> = tokens(&store).iter().map(|t| t.name().to_owned()).collect();
+        assert_eq!(names, vec!["ci", "deploy"]);
+
+        // the grant is limited to the scope of the token
+        let grant = basic_auth(&store, "ci", &password).unwrap();
+        let name = "_acme-challenge.www.ci.example.com";
+        assert!(grant.allows(name, Record::TXT, Operation::Update));
+        assert!(!grant.allows(name, Record::TXT, Operation::Delete));
+        assert!(!grant.allows(name, Record::A, Operation::Update));
+        assert!(!grant.allows("www.example.com", Record::TXT, Operation::Update));
+        assert!(!grant.admin());
+        assert!(basic_auth(&store, "ci", "wrong").is_none());
+
+        // the expiry can only be set to an earlier time
+        let time = now();
+        let expired = expire_token(&store, "ci", time + 3600).unwrap();
+        assert_eq!(expired.not_after(), Some(time + 3600));
+        match token_error(expire_token(&store, "ci", time + 7200)) {
+            Some(TokenError::Extended) => (),
+            _ => panic!("expected a rejected expiry"),
+        }
+        assert_eq!(token(&store, "ci").unwrap().not_after(), Some(time + 3600));
+        assert!(basic_auth(&store, "ci", &password).is_some());
+        expire_token(&store, "ci", time).unwrap();
+        assert!(basic_auth(&store, "ci", &password).is_none());
+
+        assert!(revoke_token(&store, "deploy").unwrap().revoked());
+        assert!(!token(&store, "deploy").unwrap().active(time));
+        match token_error(revoke_token(&store, "missing")) {
+            Some(TokenError::NotFound) => (),
+            _ => panic!("expected a missing token"),
+        }
+
+        // the changes are persisted
+        let store = TokenStore::open(&path).unwrap();
+        assert!(token(&store, "deploy").unwrap().revoked());
+        assert_eq!(token(&store, "ci").unwrap().not_after(), Some(time));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -42,6 +42,7 @@ pub fn parse_args() -> ::clap::ArgMatches<'static> {
         (version: crate_version!())
         (author: crate_authors!())
         (about: crate_description!())
+        (@setting SubcommandsNegateReqs)
         (@arg TOKEN: -t --token +required +takes_value "Token to authenticate against the API")
         (@arg CMD: -c --command +takes_value "Nsupdate command (Defaults to nsupdate)")
         (@arg KEYPATH: -k --keypath +required +takes_value "Path to the DNS key")
@@ -49,5 +50,40 @@ pub fn parse_args() -> ::clap::ArgMatches<'static> {
         (@arg PORT: -p --port +takes_value "Port to listen on (Defaults to 8000)")
         (@arg HOST: -h --host +takes_value "Host to listen on (Defaults to 0.0.0.0)")
         (@arg SERVER: -s --server +takes_value "Bind server (Defaults to 127.0.0.1)")
+        (@arg DIG: -d --dig +takes_value "Dig command used to check propagation (Defaults to dig)")
+        (@arg MAXWAIT: --("max-wait") +takes_value "Maximum number of seconds a request may wait for propagation (Defaults to 120)")
+        (@arg TOKENS: -a --tokens +takes_value "TOML file with scoped tokens for HTTP basic auth, Ed25519 public keys and secrets")
+        (@arg ACMEDNSZONE: --("acme-dns-zone") +takes_value "Zone for acme-dns challenge records (acme-dns API is disabled if not set)")
+        (@arg ACMEDNSACCOUNTS: --("acme-dns-accounts") +takes_value "File to persist acme-dns accounts in")
+        (@arg ACMEDNSREGISTERFROM: --("acme-dns-register-from") +takes_value +multiple number_of_values(1) requires[ACMEDNSZONE] "Network allowed to register acme-dns accounts, e.g. 192.0.2.0/24 (Registration is disabled if not set, can be given multiple times)")
+        (@arg ACMEDNSMAXACCOUNTS: --("acme-dns-max-accounts") +takes_value requires[ACMEDNSZONE] "Maximum number of acme-dns accounts (Defaults to 1000)")
+        (@arg ACMEDNSPROXY: --("acme-dns-trusted-proxy") +takes_value +multiple number_of_values(1) requires[ACMEDNSZONE] "Address of a reverse proxy whose X-Forwarded-For header is used to check allowed networks (can be given multiple times)")
+        (@arg EXTERNALDNSLISTEN: --("external-dns-listen") +takes_value requires[EXTERNALDNSZONE] "Address to serve the external-dns webhook provider on, e.g. 127.0.0.1:8888")
+        (@arg EXTERNALDNSREMOTE: --("external-dns-allow-remote") requires[EXTERNALDNSLISTEN] "Allow serving the unauthenticated external-dns webhook provider on addresses other than loopback")
+        (@arg EXTERNALDNSZONE: --("external-dns-zone") +takes_value +multiple number_of_values(1) "Zone managed by external-dns (can be given multiple times)")
+        (@arg AUTHORIZEDKEYS: --("authorized-keys") +takes_value "File with SSH public keys in authorized_keys format, optionally restricted using names, zones, records and operations options")
+        (@arg CLIENTCERTS: --("client-certs") +takes_value "TOML file with the CA, trusted proxies and identities for TLS client certificates passed by a reverse proxy")
+        (@arg OIDC: --oidc +takes_value "TOML file with trusted OIDC issuers and the identities their tokens map to")
+        (@arg TOKENSTORE: --("token-store") +takes_value "File to persist tokens managed using the admin API in (The admin API is disabled if not set)")
+        (@arg LEGACYSIGNATURES: --("allow-legacy-signatures") "Accept requests signed over the body only, without timestamp (Vulnerable to replay attacks)")
+        (@arg STORE: --store +takes_value "Directory of the state store for managed records, their owners, leases and history (Leases are disabled if not set)")
+        (@arg RECONCILEINTERVAL: --("reconcile-interval") +takes_value requires[STORE] "Compare the state store with the primary every this many seconds (Drift is not detected if not set)")
+        (@arg REPAIRZONE: --("repair-zone") +takes_value +multiple number_of_values(1) requires[RECONCILEINTERVAL] "Zone whose drift is repaired instead of only reported (can be given multiple times)")
+        (@arg ENFORCEOWNERS: --("enforce-owners") requires[STORE] "Refuse changes to records created by other identities or outside the API")
+        (@subcommand export =>
+            (about: "Prints the content of the state store as JSON, e.g. for backups")
+            (@arg STORE: +required "Directory of the state store")
+        )
+        (@subcommand import =>
+            (about: "Restores a JSON export into an empty state store, migrating exports of older versions")
+            (@arg STORE: +required "Directory of the state store")
+            (@arg FILE: +required "Path to the export")
+        )
+        (@subcommand migrate =>
+            (about: "Moves the leases and owners from the JSON files of older versions into the state store")
+            (@arg STORE: +required "Directory of the state store")
+            (@arg LEASES: --leases +takes_value "Lease file previously passed using --leases")
+            (@arg OWNERS: --owners +takes_value "Owner file previously passed using --owners")
+        )
     ).get_matches()
 }
@@ -0,0 +1,451 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authentication using TLS client certificates. TLS is terminated by a
+//! reverse proxy, which passes the URL encoded client certificate in a header
+//! (e.g. `proxy_set_header X-Client-Cert $ssl_client_escaped_cert;` in nginx).
+//! The header is only trusted in requests from the proxy. The certificate is
+//! verified against the CA again, must not restrict its use to purposes other
+//! than TLS client authentication (`clientAuth` extended key usage), and is
+//! mapped to an identity by its subject or a DNS subject alternative name.
+//! Subjects are written as in RFC 4514, so special characters in values have
+//! to be escaped, e.g. `CN=Doe\, John`:
+//!
+//! ```toml
+//! ca = "/etc/bind9-api/client-ca.pem"
+//! proxies = ["127.0.0.1", "::1"]
+//!
+//! [[identity]]
+//! name = "web01"
+//! san = "web01.example.com"
+//! names = ["web01.example.com"]
+//! records = ["A", "AAAA"]
+//!
+//! [[identity]]
+//! name = "backup"
+//! subject = "CN=backup,O=Example"
+//! names = ["*.backup.example.com"]
+//! ```
+
+use acl::{Grant, Scope};
+use failure::Error;
+use openssl::{
+    stack::Stack,
+    x509::{store::X509StoreBuilder, X509, X509PurposeId, X509StoreContext, X509VerifyResult},
+};
+use std::{fs, net::IpAddr, path::{Path, PathBuf}};
+
+/// Header the proxy passes the certificate in, if not configured otherwise.
+const DEFAULT_HEADER: &str = "X-Client-Cert";
+
+#[derive(Debug, Fail)]
+pub enum ClientCertError {
+    #[fail(display = "Client certificate header from untrusted address")]
+    UntrustedProxy,
+    #[fail(display = "Malformed client certificate")]
+    Malformed,
+    #[fail(display = "Invalid client certificate: {}", _0)]
+    Invalid(String),
+    #[fail(display = "No identity matches the client certificate")]
+    NoIdentity,
+    #[fail(display = "Identity {} has neither subject nor san", _0)]
+    Unmatchable(String),
+}
+
+/// A distinguished name as attribute types and values, e.g.
+/// `[("CN", "web01"), ("O", "Example")]`.
+type Dn = Vec<(String, String)>;
+
+/// An identity, matched by subject or subject alternative name.
+#[derive(Deserialize)]
+pub struct Identity {
+    name: String,
+    #[serde(default, deserialize_with = "from_dn")]
+    subject: Option<Dn>,
+    #[serde(default)]
+    san: Option<String>,
+    #[serde(flatten)]
+    scope: Scope,
+}
+
+impl Identity {
+    fn matches(&self, subject: Option<&Dn>, sans: &[String]) -> bool {
+        let same = |a: &Dn, b: &Dn| {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|(a, b)| a.0.eq_ignore_ascii_case(&b.0) && a.1 == b.1)
+        };
+        self.subject
+            .as_ref()
+            .is_some_and(|own| subject.is_some_and(|subject| same(own, subject)))
+            || self
+                .san
+                .as_ref()
+                .map(|san| sans.iter().any(|s| s.eq_ignore_ascii_case(san)))
+                .unwrap_or(false)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ClientCerts {
+    ca: PathBuf,
+    proxies: Vec<IpAddr>,
+    #[serde(default = "default_header")]
+    header: String,
+    #[serde(default, rename = "identity")]
+    identities: Vec<Identity>,
+    #[serde(skip)]
+    roots: Vec<X509>,
+}
+
+fn default_header() -> String {
+    DEFAULT_HEADER.to_owned()
+}
+
+/// Decodes `%XX` escapes, as used by nginx's `$ssl_client_escaped_cert`.
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            ::std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+fn from_dn<'de, D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Dn>, D::Error> {
+    use serde::de::{Deserialize, Error};
+    let dn = String::deserialize(deserializer)?;
+    parse_dn(&dn)
+        .map(Some)
+        .ok_or_else(|| D::Error::custom(format!("invalid subject: {}", dn)))
+}
+
+/// Parses a distinguished name in the string representation of RFC 4514, e.g.
+/// `CN=backup,O=Example`. Special characters in values are escaped using a
+/// backslash, followed by the character or two hex digits. Multi-valued RDNs
+/// (`+`) are not supported.
+fn parse_dn(dn: &str) -> Option<Dn> {
+    let mut rdns = Vec::new();
+    let mut chars = dn.chars();
+    loop {
+        let mut name = String::new();
+        loop {
+            match chars.next()? {
+                '=' => break,
+                c => name.push(c),
+            }
+        }
+        let name = name.trim_start();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return None;
+        }
+        let mut value = Vec::new();
+        let mut last = true;
+        while let Some(c) = chars.next() {
+            match c {
+                ',' => {
+                    last = false;
+                    break;
+                }
+                '+' => return None,
+                '\\' => {
+                    let c = chars.next()?;
+                    if let Some(high) = c.to_digit(16) {
+                        let low = chars.next()?.to_digit(16)?;
+                        value.push((high * 16 + low) as u8);
+                    } else if ",=+<>#;\\\" ".contains(c) {
+                        value.push(c as u8);
+                    } else {
+                        return None;
+                    }
+                }
+                c => {
+                    let mut buf = [0; 4];
+                    value.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+        }
+        rdns.push((name.to_owned(), String::from_utf8(value).ok()?));
+        if last {
+            return Some(rdns);
+        }
+    }
+}
+
+/// Returns the subject of the certificate, or `None` if it contains values
+/// that cannot be represented as UTF-8.
+fn subject(cert: &X509) -> Option<Dn> {
+    cert.subject_name()
+        .entries()
+        .map(|entry| {
+            let name = entry.object().nid().short_name().ok()?;
+            let value = entry.data().as_utf8().ok()?;
+            Some((name.to_owned(), value.to_string()))
+        }).collect()
+}
+
+fn dns_names(cert: &X509) -> Vec<String> {
+    cert.subject_alt_names()
+        .map(|names| {
+            names
+                .iter()
+                .filter_map(|n| n.dnsname().map(str::to_owned))
+                .collect()
+        }).unwrap_or_default()
+}
+
+impl ClientCerts {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut certs: Self = ::toml::from_str(&fs::read_to_string(path)?)?;
+        if let Some(identity) = certs
+            .identities
+            .iter()
+            .find(|i| i.subject.is_none() && i.san.is_none())
+        {
+            return Err(ClientCertError::Unmatchable(identity.name.clone()).into());
+        }
+        certs.roots = X509::stack_from_pem(&fs::read(&certs.ca)?)?;
+        Ok(certs)
+    }
+
+    #[inline]
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    /// Verifies the certificate passed by the proxy at `peer` and returns the
+    /// grant of the first matching identity. The grant expires with the
+    /// certificate.
+    pub fn verify(&self, peer: Option<IpAddr>, value: &str) -> Result<Grant, Error> {
+        if !peer.map(|p| self.proxies.contains(&p)).unwrap_or(false) {
+            return Err(ClientCertError::UntrustedProxy.into());
+        }
+        let mut chain = X509::stack_from_pem(&percent_decode(value))
+            .map_err(|_| ClientCertError::Malformed)?
+            .into_iter();
+        let cert = chain.next().ok_or(ClientCertError::Malformed)?;
+        let mut intermediates = Stack::new()?;
+        for intermediate in chain {
+            intermediates.push(intermediate)?;
+        }
+        let mut store = X509StoreBuilder::new()?;
+        for root in &self.roots {
+            store.add_cert(root.clone())?;
+        }
+        store.set_purpose(X509PurposeId::SSL_CLIENT)?;
+        let store = store.build();
+        let mut context = X509StoreContext::new()?;
+        let result = context.init(&store, &cert, &intermediates, |c| {
+            c.verify_cert()?;
+            Ok(c.error())
+        })?;
+        if result != X509VerifyResult::OK {
+            return Err(ClientCertError::Invalid(result.error_string().to_owned()).into());
+        }
+
+        let subject = subject(&cert);
+        let sans = dns_names(&cert);
+        let identity = self
+            .identities
+            .iter()
+            .find(|i| i.matches(subject.as_ref(), &sans))
+            .ok_or(ClientCertError::NoIdentity)?;
+        let mut grant = Grant::new(identity.name.clone(), Some(identity.scope.clone()), false);
+        grant.set_not_after(not_after(&cert));
+        Ok(grant)
+    }
+}
+
+/// Returns the end of the certificate's validity in seconds since the epoch.
+fn not_after(cert: &X509) -> Option<u64> {
+    let epoch = ::openssl::asn1::Asn1Time::from_unix(0).ok()?;
+    let diff = epoch.diff(cert.not_after()).ok()?;
+    Some(i64::from(diff.days) as u64 * 86_400 + diff.secs as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CA: &str = "-----BEGIN CERTIFICATE-----\n\
+MIIBijCCATGgAwIBAgIUGERNFk6UxJOEhNn5a9x6RwS0q4MwCgYIKoZIzj0EAwIw\n\
+EjEQMA4GA1UEAwwHVGVzdCBDQTAgFw0yNjEwMTkwMjQ1NDZaGA8yMTI2MDkyNTAy\n\
+NDU0NlowEjEQMA4GA1UEAwwHVGVzdCBDQTBZMBMGByqGSM49AgEGCCqGSM49AwEH\n\
+A0IABB9DI5+jUCf/wbZl0VFKOp59ntaipMuIoGBsRBc4hV+HakqI0djh5DUGfmKG\n\
+0ixBu8ZndksyRJUd/gmAc7CfpUWjYzBhMB0GA1UdDgQWBBRKCD6M2MJTjMavmgA1\n\
+xDknK9+sMjAfBgNVHSMEGDAWgBRKCD6M2MJTjMavmgA1xDknK9+sMjAPBgNVHRMB\n\
+Af8EBTADAQH/MA4GA1UdDwEB/wQEAwIBBjAKBggqhkjOPQQDAgNHADBEAiAnODxy\n\
+XYYY4oCPsGDz9zDgFEr1DJ04ISp62u0kVk4T7gIgE30awsa1keBi8URtfEUf88iu\n\
+6VghQbd78QAG0ECG+lk=\n\
+-----END CERTIFICATE-----\n";
+
+    const CERT: &str = "-----BEGIN CERTIFICATE-----\n\
+MIIByjCCAXCgAwIBAgIUeJ1UQ8CdP0fm/JJvJbdqewILF3cwCgYIKoZIzj0EAwIw\n\
+EjEQMA4GA1UEAwwHVGVzdCBDQTAgFw0yNjEwMTkwMjQ1NDZaGA8yMTI2MDkyNTAy\n\
+NDU0NlowIjEOMAwGA1UEAwwFd2ViMDExEDAOBgNVBAoMB0V4YW1wbGUwWTATBgcq\n\
+hkjOPQIBBggqhkjOPQMBBwNCAAQGsial4LkVflNTegM0bMBVEv6jDvd9vThdREaS\n\
+1wZbhpnvQmbg/NqOQ+LiGn9cU8LDaNTE2MrlM1Agj3+J9UK/o4GRMIGOMAkGA1Ud\n\
+EwQCMAAwDgYDVR0PAQH/BAQDAgeAMBMGA1UdJQQMMAoGCCsGAQUFBwMCMBwGA1Ud\n\
+EQQVMBOCEXdlYjAxLmV4YW1wbGUuY29tMB0GA1UdDgQWBBSLFMRkPnhzDZPYrmF+\n\
+EjYa8Cm56TAfBgNVHSMEGDAWgBRKCD6M2MJTjMavmgA1xDknK9+sMjAKBggqhkjO\n\
+PQQDAgNIADBFAiEA4BkZRe4GDFa5fhaFt9tJj7rSSJ9dx141A6P37qwj5WQCIC4m\n\
+oxOUe41eEWSlY1TZy9M9FNn6iyEKPtgxK2E/MPZd\n\
+-----END CERTIFICATE-----\n";
+
+    // issued by `CA`, with the same subject as `CERT`, but only for servers
+    const SERVER: &str = "-----BEGIN CERTIFICATE-----\n\
+MIIByTCCAXCgAwIBAgIUeJ1UQ8CdP0fm/JJvJbdqewILF3gwCgYIKoZIzj0EAwIw\n\
+EjEQMA4GA1UEAwwHVGVzdCBDQTAgFw0yNjEwMTkwMjQ1NDZaGA8yMTI2MDkyNTAy\n\
+NDU0NlowIjEOMAwGA1UEAwwFd2ViMDExEDAOBgNVBAoMB0V4YW1wbGUwWTATBgcq\n\
+hkjOPQIBBggqhkjOPQMBBwNCAATHAZ5o0hxZlZfxbS73VqOFaokUoeeqAwg9WUZE\n\
+bFAjC78zU53dCwEX93kIXkMrxpZesmQE2XcMZl5q+Ld3t5qlo4GRMIGOMAkGA1Ud\n\
+EwQCMAAwDgYDVR0PAQH/BAQDAgeAMBMGA1UdJQQMMAoGCCsGAQUFBwMBMBwGA1Ud\n\
+EQQVMBOCEXdlYjAxLmV4YW1wbGUuY29tMB0GA1UdDgQWBBTLZQ5T12eH9yu5NARr\n\
+Aic1139YPjAfBgNVHSMEGDAWgBRKCD6M2MJTjMavmgA1xDknK9+sMjAKBggqhkjO\n\
+PQQDAgNHADBEAiAyhlUkVJWfCt05NBhm0HGQxU2CsF+P+wwxR7ixnKQP0QIgDAT1\n\
+vPEizgM3oOeYhUg7Av//6AJfUDsqIgwUyNEuumU=\n\
+-----END CERTIFICATE-----\n";
+
+    // issued by `CA`, with a single CN `backup,O=Example`
+    const SPOOFED: &str = "-----BEGIN CERTIFICATE-----\n\
+MIIBpDCCAUmgAwIBAgIUeJ1UQ8CdP0fm/JJvJbdqewILF3kwCgYIKoZIzj0EAwIw\n\
+EjEQMA4GA1UEAwwHVGVzdCBDQTAgFw0yNjEwMTkwMjQ1NDZaGA8yMTI2MDkyNTAy\n\
+NDU0NlowGzEZMBcGA1UEAwwQYmFja3VwLE89RXhhbXBsZTBZMBMGByqGSM49AgEG\n\
+CCqGSM49AwEHA0IABHkHIUNpbaqiv/kY8OKy2JS4hWPOGQGXkdh+Dichxjc4ifc9\n\
+zUJqzX8rpjh0YmuM1qSe9ZOrEpzcxFAALOlEHTijcjBwMAkGA1UdEwQCMAAwDgYD\n\
+VR0PAQH/BAQDAgeAMBMGA1UdJQQMMAoGCCsGAQUFBwMCMB0GA1UdDgQWBBTrbeoL\n\
+oiFf79W1vOoppOZK6NZ28DAfBgNVHSMEGDAWgBRKCD6M2MJTjMavmgA1xDknK9+s\n\
+MjAKBggqhkjOPQQDAgNJADBGAiEA0y0q71dbTUKJwsVgglcsBHhVIv7NQE6xRa79\n\
+POccILUCIQC8xRlaQaKyl2U5LbUZ6Mz3aFsnMv8uYWVkVeMi9Ta2TA==\n\
+-----END CERTIFICATE-----\n";
+
+    // self-signed, with the same subject as `CERT`
+    const OTHER: &str = "-----BEGIN CERTIFICATE-----\n\
+MIIBmzCCAUGgAwIBAgIUO1I3kq7xnbz4Bb5F92Ilj+vP5bgwCgYIKoZIzj0EAwIw\n\
+IjEOMAwGA1UEAwwFd2ViMDExEDAOBgNVBAoMB0V4YW1wbGUwIBcNMjYxMDE5MDEz\n\
+NDEwWhgPMjEyNjA5MjUwMTM0MTBaMCIxDjAMBgNVBAMMBXdlYjAxMRAwDgYDVQQK\n\
+DAdFeGFtcGxlMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAE1MbgWRNDHOzjQIAv\n\
+C+egImxZ2R6KrTX6VqH3JxhmW2uJ1SThl8CrmUPPDH2IRboQ1W1f61cYMkdHioeC\n\
+T2EAv6NTMFEwHQYDVR0OBBYEFMDKAYH7RjqNoxnFhd7zzmlUARUoMB8GA1UdIwQY\n\
+MBaAFMDKAYH7RjqNoxnFhd7zzmlUARUoMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZI\n\
+zj0EAwIDSAAwRQIgElGVsTBWpnPWl44fFJh17zj7dQ7ii+aZqBays7iG1gECIQCv\n\
+Hz1Bwbg6QRDvUOnNvKX0mzHLV4xBUn8N8WsGM3moFw==\n\
+-----END CERTIFICATE-----\n";
+
+    fn client_certs() -> ClientCerts {
+        let mut certs: ClientCerts = ::toml::from_str(
+            r#"
+            ca = "/etc/bind9-api/client-ca.pem"
+            proxies = ["127.0.0.1"]
+
+            [[identity]]
+            name = "web01"
+            san = "web01.example.com"
+            names = ["web01.example.com"]
+
+            [[identity]]
+            name = "backup"
+            subject = "CN=backup,O=Example"
+            names = ["*.backup.example.com"]
+            "#,
+        ).unwrap();
+        certs.roots = X509::stack_from_pem(CA.as_bytes()).unwrap();
+        certs
+    }
+
+    #[test]
+    fn verify_certificate() {
+        let certs = client_certs();
+        let proxy = "127.0.0.1".parse().ok();
+        let escaped = CERT.replace("\n", "%0A").replace(" ", "%20");
+        let grant = certs.verify(proxy, &escaped).unwrap();
+        assert_eq!(grant.name(), "web01");
+        assert!(grant.not_after().is_some());
+        assert!(certs.verify("10.0.0.1".parse().ok(), CERT).is_err());
+        assert!(certs.verify(proxy, OTHER).is_err());
+        assert!(certs.verify(proxy, SERVER).is_err());
+        assert_eq!(
+            subject(&X509::from_pem(CERT.as_bytes()).unwrap()),
+            parse_dn("CN=web01, O=Example")
+        );
+    }
+
+    #[test]
+    fn match_subjects() {
+        let certs = client_certs();
+        let proxy = "127.0.0.1".parse().ok();
+        match certs.verify(proxy, SPOOFED).map_err(|e| e.downcast::<ClientCertError>()) {
+            Err(Ok(ClientCertError::NoIdentity)) => (),
+            _ => panic!("spoofed subject matched an identity"),
+        }
+        let spoofed = subject(&X509::from_pem(SPOOFED.as_bytes()).unwrap());
+        assert_eq!(spoofed, parse_dn("CN=backup\\,O=Example"));
+        assert_eq!(spoofed, parse_dn("CN=backup\\2CO=Example"));
+        assert_eq!(
+            parse_dn("CN=backup,O=Example").unwrap(),
+            vec![
+                ("CN".to_owned(), "backup".to_owned()),
+                ("O".to_owned(), "Example".to_owned())
+            ]
+        );
+        assert!(parse_dn("CN=a+O=b").is_none());
+        assert!(parse_dn("CN=a,").is_none());
+        assert!(parse_dn("CN=a\\").is_none());
+        assert!(parse_dn("=a").is_none());
+        assert!(parse_dn("CN").is_none());
+    }
+}
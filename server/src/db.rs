@@ -0,0 +1,729 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persistent state of the records managed using the API, kept in an embedded
+//! sled database. The store holds the RRsets as they were set using the API,
+//! their owners and leases, the history of all changes and metadata like the
+//! schema version.
+//!
+//! Changes are written in a single transaction, so a crash never leaves the
+//! RRsets, their owners, leases and history out of sync. When the layout of
+//! the data changes, a migration is appended to `MIGRATIONS`, which upgrades
+//! existing stores on startup.
+
+use acl::Grant;
+use actix_web::{
+    error::{self, ErrorForbidden, ErrorInternalServerError, ErrorNotFound}, Json, State,
+};
+use data::{Delete, Lease, Record, Update};
+use failure::Error;
+use lease::now;
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError}, Transactional, Tree,
+};
+use std::{path::Path, sync::Arc};
+use util::{Authenticated, Config};
+
+const SCHEMA_VERSION: &[u8] = b"schema_version";
+const CREATED: &[u8] = b"created";
+
+type Migration = fn(&Db) -> Result<(), Error>;
+
+/// Migrations from each schema version to the next one. New stores start at
+/// version 0, the current version is the number of migrations.
+const MIGRATIONS: &[Migration] = &[initial_layout, initial_owners];
+
+#[derive(Debug, Fail)]
+pub enum DbError {
+    #[fail(
+        display = "Schema version {} is newer than the supported version {}",
+        _0,
+        _1
+    )]
+    SchemaTooNew(u32, u32),
+    #[fail(display = "Corrupt metadata: {}", _0)]
+    Corrupt(&'static str),
+    #[fail(display = "Cannot import into a store that is not empty")]
+    NotEmpty,
+}
+
+/// A RRset as it was set using the API.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ManagedRRset {
+    name: String,
+    record: Record,
+    ttl: u32,
+    values: Vec<String>,
+    identity: String,
+    updated: u64,
+}
+
+impl ManagedRRset {
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn record(&self) -> Record {
+        self.record
+    }
+
+    #[inline]
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    #[inline]
+    pub fn values(&self) -> &[String] {
+        &self.values
+    }
+
+    /// Returns the UNIX timestamp of the last change.
+    #[inline]
+    pub fn updated(&self) -> u64 {
+        self.updated
+    }
+}
+
+/// The identity that owns a RRset. Unlike the identity that changed the RRset
+/// last, it is kept when single values are deleted, and delegated tokens act
+/// on behalf of their parent.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Owner {
+    name: String,
+    record: Record,
+    owner: String,
+    since: u64,
+}
+
+impl Owner {
+    #[inline]
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+}
+
+/// A single change applied using the API.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Action {
+    Add {
+        name: String,
+        record: Record,
+        ttl: u32,
+        value: String,
+    },
+    Delete {
+        name: String,
+        record: Record,
+        value: Option<String>,
+    },
+}
+
+/// An entry of the change history.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Change {
+    id: u64,
+    time: u64,
+    identity: String,
+    #[serde(flatten)]
+    action: Action,
+}
+
+/// The content of a store, as written by `export` and read by `import`.
+/// Exports of schema version 1 have neither owners nor leases.
+#[derive(Deserialize, Serialize)]
+pub struct Export {
+    schema_version: u32,
+    created: u64,
+    rrsets: Vec<ManagedRRset>,
+    history: Vec<Change>,
+    #[serde(default)]
+    owners: Vec<Owner>,
+    #[serde(default)]
+    leases: Vec<Lease>,
+}
+
+pub struct Db {
+    db: ::sled::Db,
+    meta: Tree,
+    rrsets: Tree,
+    history: Tree,
+    owners: Tree,
+    leases: Tree,
+}
+
+/// Version 1: records the creation time of the store, unless it was imported.
+fn initial_layout(db: &Db) -> Result<(), Error> {
+    if !db.meta.contains_key(CREATED)? {
+        db.meta.insert(CREATED, &now().to_be_bytes()[..])?;
+    }
+    Ok(())
+}
+
+/// Version 2: tracks the owners of RRsets in the store. RRsets changed before
+/// are owned by the identity that changed them last.
+fn initial_owners(db: &Db) -> Result<(), Error> {
+    for rrset in db.rrsets()? {
+        let key = rrset_key(&rrset.name, rrset.record);
+        if !db.owners.contains_key(&key)? {
+            let owner = Owner {
+                name: rrset.name,
+                record: rrset.record,
+                owner: rrset.identity,
+                since: rrset.updated,
+            };
+            db.owners.insert(key, ::serde_json::to_vec(&owner)?)?;
+        }
+    }
+    Ok(())
+}
+
+fn rrset_key(name: &str, record: Record) -> Vec<u8> {
+    format!(
+        "{}\u{0}{}",
+        name.trim_end_matches('.').to_lowercase(),
+        record
+    ).into_bytes()
+}
+
+fn read_u64(bytes: &[u8]) -> Option<u64> {
+    let mut buf = [0; 8];
+    if bytes.len() != buf.len() {
+        return None;
+    }
+    buf.copy_from_slice(bytes);
+    Some(u64::from_be_bytes(buf))
+}
+
+/// Replaces the lease of the same record value, if there is one.
+fn add_lease(leases: &mut Vec<Lease>, lease: Lease) {
+    leases.retain(|l| !l.is_for(lease.name(), lease.record(), Some(lease.value())));
+    leases.push(lease);
+}
+
+impl Db {
+    /// Opens the store at `path`, creating it if it does not exist, and
+    /// migrates it to the current schema version.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        // changes are flushed explicitly, a background flusher would only
+        // keep the store locked after it is closed
+        let db = ::sled::Config::new()
+            .path(path)
+            .flush_every_ms(None)
+            .open()?;
+        let store = Self {
+            meta: db.open_tree("meta")?,
+            rrsets: db.open_tree("rrsets")?,
+            history: db.open_tree("history")?,
+            owners: db.open_tree("owners")?,
+            leases: db.open_tree("leases")?,
+            db,
+        };
+        store.migrate()?;
+        Ok(store)
+    }
+
+    pub fn schema_version(&self) -> Result<u32, Error> {
+        match self.meta.get(SCHEMA_VERSION)? {
+            Some(version) => read_u64(&version)
+                .map(|v| v as u32)
+                .ok_or_else(|| DbError::Corrupt("schema version").into()),
+            None => Ok(0),
+        }
+    }
+
+    fn set_schema_version(&self, version: u32) -> Result<(), Error> {
+        self.meta
+            .insert(SCHEMA_VERSION, &u64::from(version).to_be_bytes()[..])?;
+        Ok(())
+    }
+
+    fn migrate(&self) -> Result<(), Error> {
+        let current = MIGRATIONS.len() as u32;
+        let mut version = self.schema_version()?;
+        if version > current {
+            return Err(DbError::SchemaTooNew(version, current).into());
+        }
+        while version < current {
+            info!("migrating state store to schema version {}", version + 1);
+            MIGRATIONS[version as usize](self)?;
+            version += 1;
+            self.set_schema_version(version)?;
+            self.db.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Records changes that were applied using `grant`, together with the
+    /// owners and leases of the RRsets. Deletes are applied before updates,
+    /// like in a batch. Deleting single values keeps the owner of the RRset.
+    pub fn record(
+        &self,
+        grant: &Grant,
+        deletes: &[&Delete],
+        updates: &[&Update],
+    ) -> Result<(), Error> {
+        let time = now();
+        let identity = grant.name();
+        let mut changes = Vec::with_capacity(deletes.len() + updates.len());
+        for delete in deletes {
+            let change = Change {
+                id: self.db.generate_id()?,
+                time,
+                identity: identity.to_owned(),
+                action: Action::Delete {
+                    name: delete.name().to_owned(),
+                    record: delete.record(),
+                    value: delete.value().map(str::to_owned),
+                },
+            };
+            changes.push((change, None));
+        }
+        for update in updates {
+            let change = Change {
+                id: self.db.generate_id()?,
+                time,
+                identity: identity.to_owned(),
+                action: Action::Add {
+                    name: update.name().to_owned(),
+                    record: update.record(),
+                    ttl: update.ttl(),
+                    value: update.value().to_owned(),
+                },
+            };
+            changes.push((change, update.lease()));
+        }
+        let abort = ConflictableTransactionError::Abort;
+        (&self.rrsets, &self.history, &self.owners, &self.leases)
+            .transaction(|(rrsets, history, owners, leases)| {
+                for &(ref change, lease) in &changes {
+                    let key = match change.action {
+                        Action::Delete {
+                            ref name, record, ..
+                        }
+                        | Action::Add {
+                            ref name, record, ..
+                        } => rrset_key(name, record),
+                    };
+                    let mut rrset: Option<ManagedRRset> = match rrsets.get(&key)? {
+                        Some(rrset) => Some(::serde_json::from_slice(&rrset).map_err(abort)?),
+                        None => None,
+                    };
+                    let mut owner: Option<Owner> = match owners.get(&key)? {
+                        Some(owner) => Some(::serde_json::from_slice(&owner).map_err(abort)?),
+                        None => None,
+                    };
+                    let mut rrset_leases: Vec<Lease> = match leases.get(&key)? {
+                        Some(l) => ::serde_json::from_slice(&l).map_err(abort)?,
+                        None => Vec::new(),
+                    };
+                    match change.action {
+                        Action::Delete {
+                            ref name,
+                            record,
+                            ref value,
+                        } => {
+                            rrset = match (value, rrset) {
+                                (Some(value), Some(mut rrset)) => {
+                                    rrset.values.retain(|v| v != value);
+                                    rrset.identity = identity.to_owned();
+                                    rrset.updated = time;
+                                    Some(rrset).filter(|r| !r.values.is_empty())
+                                }
+                                _ => None,
+                            };
+                            if value.is_none() {
+                                owner = None;
+                            }
+                            rrset_leases.retain(|l| !l.is_for(name, record, value.as_deref()));
+                        }
+                        Action::Add {
+                            ref name,
+                            record,
+                            ttl,
+                            ref value,
+                        } => {
+                            let mut changed = rrset.unwrap_or_else(|| ManagedRRset {
+                                name: name.clone(),
+                                record,
+                                ttl,
+                                values: Vec::new(),
+                                identity: identity.to_owned(),
+                                updated: time,
+                            });
+                            if !changed.values.contains(value) {
+                                changed.values.push(value.clone());
+                            }
+                            changed.ttl = ttl;
+                            changed.identity = identity.to_owned();
+                            changed.updated = time;
+                            rrset = Some(changed);
+                            if owner.as_ref().map(|o| o.owner.as_str()) != Some(grant.owner()) {
+                                owner = Some(Owner {
+                                    name: name.clone(),
+                                    record,
+                                    owner: grant.owner().to_owned(),
+                                    since: time,
+                                });
+                            }
+                            if let Some(duration) = lease {
+                                let expires = time + u64::from(duration);
+                                add_lease(
+                                    &mut rrset_leases,
+                                    Lease::new(name.clone(), record, value.clone(), expires),
+                                );
+                            }
+                        }
+                    }
+                    match rrset {
+                        Some(rrset) => {
+                            let rrset = ::serde_json::to_vec(&rrset).map_err(abort)?;
+                            rrsets.insert(&key[..], rrset)?;
+                        }
+                        None => {
+                            rrsets.remove(&key[..])?;
+                        }
+                    }
+                    match owner {
+                        Some(owner) => {
+                            let owner = ::serde_json::to_vec(&owner).map_err(abort)?;
+                            owners.insert(&key[..], owner)?;
+                        }
+                        None => {
+                            owners.remove(&key[..])?;
+                        }
+                    }
+                    if rrset_leases.is_empty() {
+                        leases.remove(&key[..])?;
+                    } else {
+                        leases.insert(
+                            &key[..],
+                            ::serde_json::to_vec(&rrset_leases).map_err(abort)?,
+                        )?;
+                    }
+                    history.insert(
+                        &change.id.to_be_bytes()[..],
+                        ::serde_json::to_vec(change).map_err(abort)?,
+                    )?;
+                }
+                Ok(())
+            }).map_err(|e| match e {
+                TransactionError::Abort(e) => Error::from(e),
+                TransactionError::Storage(e) => Error::from(e),
+            })?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Returns all managed RRsets.
+    pub fn rrsets(&self) -> Result<Vec<ManagedRRset>, Error> {
+        self.rrsets
+            .iter()
+            .values()
+            .map(|rrset| Ok(::serde_json::from_slice(&rrset?)?))
+            .collect()
+    }
+
+    pub fn rrset(&self, name: &str, record: Record) -> Result<Option<ManagedRRset>, Error> {
+        match self.rrsets.get(rrset_key(name, record))? {
+            Some(rrset) => Ok(Some(::serde_json::from_slice(&rrset)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the owners of all RRsets.
+    pub fn owners(&self) -> Result<Vec<Owner>, Error> {
+        self.owners
+            .iter()
+            .values()
+            .map(|owner| Ok(::serde_json::from_slice(&owner?)?))
+            .collect()
+    }
+
+    pub fn owner(&self, name: &str, record: Record) -> Result<Option<Owner>, Error> {
+        match self.owners.get(rrset_key(name, record))? {
+            Some(owner) => Ok(Some(::serde_json::from_slice(&owner)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the leases of all records.
+    pub fn leases(&self) -> Result<Vec<Lease>, Error> {
+        let mut all = Vec::new();
+        for leases in self.leases.iter().values() {
+            let leases: Vec<Lease> = ::serde_json::from_slice(&leases?)?;
+            all.extend(leases);
+        }
+        Ok(all)
+    }
+
+    /// Returns the change history, oldest changes first.
+    pub fn history(&self) -> Result<Vec<Change>, Error> {
+        self.history
+            .iter()
+            .values()
+            .map(|change| Ok(::serde_json::from_slice(&change?)?))
+            .collect()
+    }
+
+    pub fn export(&self) -> Result<Export, Error> {
+        Ok(Export {
+            schema_version: self.schema_version()?,
+            created: self
+                .meta
+                .get(CREATED)?
+                .and_then(|created| read_u64(&created))
+                .ok_or(DbError::Corrupt("creation time"))?,
+            rrsets: self.rrsets()?,
+            history: self.history()?,
+            owners: self.owners()?,
+            leases: self.leases()?,
+        })
+    }
+
+    fn insert_owner(&self, owner: &Owner) -> Result<(), Error> {
+        self.owners.insert(
+            rrset_key(&owner.name, owner.record),
+            ::serde_json::to_vec(owner)?,
+        )?;
+        Ok(())
+    }
+
+    fn insert_lease(&self, lease: &Lease) -> Result<(), Error> {
+        let key = rrset_key(lease.name(), lease.record());
+        let mut leases: Vec<Lease> = match self.leases.get(&key)? {
+            Some(leases) => ::serde_json::from_slice(&leases)?,
+            None => Vec::new(),
+        };
+        add_lease(&mut leases, lease.clone());
+        self.leases.insert(key, ::serde_json::to_vec(&leases)?)?;
+        Ok(())
+    }
+
+    /// Restores an export into an empty store. The data is written with the
+    /// schema version of the export and then migrated to the current one.
+    pub fn import(&self, export: &Export) -> Result<(), Error> {
+        let current = MIGRATIONS.len() as u32;
+        if export.schema_version > current {
+            return Err(DbError::SchemaTooNew(export.schema_version, current).into());
+        }
+        let trees = [&self.rrsets, &self.history, &self.owners, &self.leases];
+        if !trees.iter().all(|tree| tree.is_empty()) {
+            return Err(DbError::NotEmpty.into());
+        }
+        self.meta.insert(CREATED, &export.created.to_be_bytes()[..])?;
+        for rrset in &export.rrsets {
+            self.rrsets.insert(
+                rrset_key(&rrset.name, rrset.record),
+                ::serde_json::to_vec(rrset)?,
+            )?;
+        }
+        for change in &export.history {
+            self.history
+                .insert(&change.id.to_be_bytes()[..], ::serde_json::to_vec(change)?)?;
+        }
+        for owner in &export.owners {
+            self.insert_owner(owner)?;
+        }
+        for lease in &export.leases {
+            self.insert_lease(lease)?;
+        }
+        self.set_schema_version(export.schema_version)?;
+        self.migrate()?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Moves the leases and owners, that older versions kept in JSON files,
+    /// into the store. They replace the entries for the same records.
+    pub fn import_files(&self, leases: &[Lease], owners: &[Owner]) -> Result<(), Error> {
+        for owner in owners {
+            self.insert_owner(owner)?;
+        }
+        for lease in leases {
+            self.insert_lease(lease)?;
+        }
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+/// Handles `GET /admin/store`, exports the store of a running server, e.g. for
+/// backups.
+pub fn export(
+    (auth, state): (Authenticated, State<Arc<Config>>),
+) -> Result<Json<Export>, error::Error> {
+    if !auth.admin() {
+        return Err(ErrorForbidden("Admin access required"));
+    }
+    state
+        .db()
+        .ok_or_else(|| ErrorNotFound("State store is not enabled"))?
+        .export()
+        .map(Json)
+        .map_err(|_| ErrorInternalServerError("Error reading the state store"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant(name: &str) -> Grant {
+        Grant::new(name.to_owned(), None, false)
+    }
+
+    #[test]
+    fn record_and_export() {
+        let path = ::std::env::temp_dir().join("bind9-api-db-test");
+        let _ = ::std::fs::remove_dir_all(&path);
+        {
+            let db = Db::open(&path).unwrap();
+            let update = |value: &str| {
+                Update::new("www.example.com".to_owned(), value.to_owned(), Record::TXT, 60)
+            };
+            db.record(&grant("alice"), &[], &[&update("a"), &update("b")])
+                .unwrap();
+            let mut delete = Delete::new("WWW.example.com.".to_owned(), Record::TXT);
+            delete.set_value(Some("a".to_owned()));
+            db.record(&grant("bob"), &[&delete], &[]).unwrap();
+            let rrsets = db.rrsets().unwrap();
+            assert_eq!(rrsets.len(), 1);
+            assert_eq!(rrsets[0].values(), &["b".to_owned()]);
+            assert_eq!(rrsets[0].identity, "bob");
+            assert_eq!(db.history().unwrap().len(), 3);
+        }
+        let db = Db::open(&path).unwrap();
+        assert_eq!(db.schema_version().unwrap(), MIGRATIONS.len() as u32);
+        let export = db.export().unwrap();
+        assert!(db.import(&export).is_err());
+        db.record(&grant("bob"), &[&Delete::new("www.example.com".to_owned(), Record::TXT)], &[])
+            .unwrap();
+        assert!(db.rrsets().unwrap().is_empty());
+        drop(db);
+        ::std::fs::remove_dir_all(&path).unwrap();
+
+        let db = Db::open(&path).unwrap();
+        db.import(&export).unwrap();
+        assert_eq!(db.rrsets().unwrap(), export.rrsets);
+        drop(db);
+        ::std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn owners_and_leases() {
+        let path = ::std::env::temp_dir().join("bind9-api-db-owners-test");
+        let _ = ::std::fs::remove_dir_all(&path);
+        let db = Db::open(&path).unwrap();
+        let mut delegated = grant("alice (delegated)");
+        delegated.set_owner(Some("alice".to_owned()));
+        let update = |value: &str, lease| {
+            let mut update =
+                Update::new("www.example.com".to_owned(), value.to_owned(), Record::TXT, 60);
+            update.set_lease(lease);
+            update
+        };
+        db.record(&delegated, &[], &[&update("a", Some(60)), &update("b", Some(60))])
+            .unwrap();
+        let owner = |db: &Db| {
+            db.owner("WWW.example.com.", Record::TXT)
+                .unwrap()
+                .map(|o| o.owner().to_owned())
+        };
+        assert_eq!(owner(&db), Some("alice".to_owned()));
+        assert_eq!(db.rrsets().unwrap()[0].identity, "alice (delegated)");
+        assert_eq!(db.leases().unwrap().len(), 2);
+
+        // renewing replaces the lease, deleting a value keeps the owner
+        db.record(&grant("bob"), &[], &[&update("a", Some(3600))]).unwrap();
+        let mut delete = Delete::new("www.example.com".to_owned(), Record::TXT);
+        delete.set_value(Some("b".to_owned()));
+        db.record(&grant("carol"), &[&delete], &[]).unwrap();
+        assert_eq!(owner(&db), Some("bob".to_owned()));
+        let leases = db.leases().unwrap();
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].value(), "a");
+        assert!(leases[0].expires() >= now() + 3600 - 1);
+
+        drop(db);
+        let db = Db::open(&path).unwrap();
+        assert_eq!(db.leases().unwrap().len(), 1);
+        let export = db.export().unwrap();
+        assert_eq!(export.owners.len(), 1);
+        db.record(&grant("bob"), &[&Delete::new("www.example.com".to_owned(), Record::TXT)], &[])
+            .unwrap();
+        assert_eq!(owner(&db), None);
+        assert!(db.leases().unwrap().is_empty());
+        drop(db);
+        ::std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn migrate_imports() {
+        let path = ::std::env::temp_dir().join("bind9-api-db-import-test");
+        let _ = ::std::fs::remove_dir_all(&path);
+        let export: Export = ::serde_json::from_str(
+            r#"{"schema_version": 1, "created": 1545234000, "history": [], "rrsets": [
+                {"name": "www.example.com", "record": "TXT", "ttl": 60, "values": ["a"],
+                 "identity": "alice", "updated": 1545234000}]}"#,
+        ).unwrap();
+        let db = Db::open(&path).unwrap();
+        db.import(&export).unwrap();
+        assert_eq!(db.schema_version().unwrap(), MIGRATIONS.len() as u32);
+        let owner = db.owner("www.example.com", Record::TXT).unwrap().unwrap();
+        assert_eq!(owner.owner(), "alice");
+        assert_eq!(owner.since, 1_545_234_000);
+        assert_eq!(db.export().unwrap().created, 1_545_234_000);
+
+        let mut export = db.export().unwrap();
+        export.schema_version = MIGRATIONS.len() as u32 + 1;
+        drop(db);
+        ::std::fs::remove_dir_all(&path).unwrap();
+        let db = Db::open(&path).unwrap();
+        assert!(db.import(&export).is_err());
+
+        let lease = Lease::new("www.example.com".to_owned(), Record::TXT, "a".to_owned(), 1);
+        db.import_files(&[lease], &[owner]).unwrap();
+        assert_eq!(db.leases().unwrap().len(), 1);
+        assert!(db.owner("www.example.com", Record::TXT).unwrap().is_some());
+        drop(db);
+        ::std::fs::remove_dir_all(&path).unwrap();
+    }
+}
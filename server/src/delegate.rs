@@ -0,0 +1,232 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Short-lived delegated tokens. A token can mint a child token whose scope is
+//! a subset of its own. The child token describes itself: its key id carries
+//! the scope and expiry, and its secret is a MAC over the key id using a key
+//! derived from the server's secret (see `crypto::delegated_secret`).
+//! Delegated tokens are therefore not stored on the server.
+//!
+//! As the parent is not looked up again, a child token stays valid until it
+//! expires, i.e. for up to `MAX_TTL` seconds after its parent was revoked.
+
+use acl::{Grant, Scope};
+use actix_web::{
+    error::{self, ErrorBadRequest, ErrorForbidden, ErrorInternalServerError}, Json, State,
+};
+use data::{DelegatedToken, Delegation, Operation, Record};
+use lease::now;
+use std::sync::Arc;
+use util::{Config, Validated};
+
+/// Maximum lifetime of delegated tokens in seconds.
+const MAX_TTL: u32 = 3600;
+
+/// The payload of a delegated key id.
+#[derive(Deserialize, Serialize)]
+struct Claims {
+    parent: String,
+    #[serde(flatten)]
+    scope: Scope,
+    not_after: u64,
+}
+
+fn secret(key: &[u8], key_id: &str) -> String {
+    ::crypto::bytes_to_hex_str(&::crypto::delegated_secret(key, key_id))
+}
+
+/// Returns the scope of a child token of `grant`, or `None` if it exceeds the
+/// scope of `grant`. Record types and operations that are not given are
+/// inherited from `grant`.
+fn child_scope(
+    grant: &Grant,
+    names: &[String],
+    records: &[Record],
+    operations: &[Operation],
+) -> Option<Scope> {
+    let records = match grant.scope() {
+        Some(parent) if records.is_empty() => parent.records().to_vec(),
+        _ => records.to_vec(),
+    };
+    let operations = match grant.scope() {
+        Some(parent) if operations.is_empty() => parent.operations().to_vec(),
+        _ => operations.to_vec(),
+    };
+    let scope = Scope::new(names.to_vec(), records, operations);
+    if grant.contains(&scope) {
+        Some(scope)
+    } else {
+        None
+    }
+}
+
+/// Issues a child token of `grant` using the server's secret `key`. It
+/// expires `ttl` seconds after `time`, but never after `grant`.
+fn issue(
+    key: &[u8],
+    grant: &Grant,
+    scope: Scope,
+    ttl: u32,
+    time: u64,
+) -> Result<DelegatedToken, ::serde_json::Error> {
+    let mut not_after = time + u64::from(ttl);
+    if let Some(parent) = grant.not_after() {
+        not_after = not_after.min(parent);
+    }
+    let claims = Claims {
+        parent: grant.name().to_owned(),
+        scope,
+        not_after,
+    };
+    let key_id = ::crypto::delegated_key_id(&::serde_json::to_vec(&claims)?);
+    let secret = secret(key, &key_id);
+    Ok(DelegatedToken::new(key_id, secret, not_after))
+}
+
+/// Verifies a signature made using a delegated token at `time`.
+fn verify_at(key: &[u8], key_id: &str, msg: &[u8], signature: &[u8], time: u64) -> Option<Grant> {
+    let payload = ::crypto::delegated_payload(key_id)?;
+    if !::crypto::verify_signature(secret(key, key_id).as_bytes(), msg, signature) {
+        return None;
+    }
+    let claims: Claims = ::serde_json::from_slice(&payload).ok()?;
+    if time >= claims.not_after {
+        return None;
+    }
+    let mut grant = Grant::new(
+        format!("{} (delegated)", claims.parent),
+        Some(claims.scope),
+        false,
+    );
+    grant.set_not_after(Some(claims.not_after));
+    grant.set_owner(Some(claims.parent));
+    Some(grant)
+}
+
+/// Verifies a request signed with a delegated token. Returns `None` if
+/// `key_id` is not a valid delegated key id, the signature is invalid or the
+/// token expired.
+pub fn verify(config: &Config, key_id: &str, msg: &[u8], signature: &[u8]) -> Option<Grant> {
+    verify_at(config.token().as_bytes(), key_id, msg, signature, now())
+}
+
+/// Handles `POST /tokens/delegate`. Record types and operations that are not
+/// given are inherited from the requesting token. The child token expires
+/// after `ttl` seconds, but never after the requesting token.
+pub fn delegate(
+    (request, state): (Validated<Delegation>, State<Arc<Config>>),
+) -> Result<Json<DelegatedToken>, error::Error> {
+    if request.ttl() == 0 || request.ttl() > MAX_TTL {
+        return Err(ErrorBadRequest(format!(
+            "ttl must be between 1 and {} seconds",
+            MAX_TTL
+        )));
+    }
+    if request.names().is_empty() {
+        return Err(ErrorBadRequest("Delegated tokens need at least one name"));
+    }
+    let grant = request.grant();
+    let scope = child_scope(grant, request.names(), request.records(), request.operations())
+        .ok_or_else(|| ErrorForbidden("Scope exceeds the scope of the token"))?;
+    let token = issue(state.token().as_bytes(), grant, scope, request.ttl(), now())
+        .map_err(|_| ErrorInternalServerError("Cannot encode token"))?;
+    info!("{} delegated token until {}", grant.name(), token.not_after());
+    Ok(Json(token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"topsecret";
+    const TIME: u64 = 1_545_234_000;
+
+    fn parent() -> Grant {
+        let scope = Scope::new(vec!["*.example.com".to_owned()], vec![Record::TXT], vec![]);
+        Grant::new("ci".to_owned(), Some(scope), false)
+    }
+
+    fn sign(token: &DelegatedToken, msg: &[u8]) -> Vec<u8> {
+        ::crypto::sign(token.secret().as_bytes(), msg)
+    }
+
+    #[test]
+    fn issue_and_verify() {
+        let names = vec!["_acme-challenge.www.example.com".to_owned()];
+        let scope = child_scope(&parent(), &names, &[], &[Operation::Update]).unwrap();
+        assert_eq!(scope.records(), &[Record::TXT]);
+        let token = issue(KEY, &parent(), scope, 600, TIME).unwrap();
+        assert_eq!(token.not_after(), TIME + 600);
+        let grant = verify_at(KEY, token.key_id(), b"msg", &sign(&token, b"msg"), TIME).unwrap();
+        assert_eq!(grant.owner(), "ci");
+        assert!(grant.allows(&names[0], Record::TXT, Operation::Update));
+        assert!(!grant.allows(&names[0], Record::TXT, Operation::Delete));
+        assert!(!grant.allows("www.example.com", Record::TXT, Operation::Update));
+        let signature = sign(&token, b"msg");
+        assert!(verify_at(KEY, token.key_id(), b"other", &signature, TIME).is_none());
+        assert!(verify_at(b"other", token.key_id(), b"msg", &signature, TIME).is_none());
+        assert!(verify_at(KEY, token.key_id(), b"msg", &signature, TIME + 600).is_none());
+        let signature = ::crypto::sign(KEY, b"msg");
+        assert!(verify_at(KEY, token.key_id(), b"msg", &signature, TIME).is_none());
+    }
+
+    #[test]
+    fn restrict_scope() {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert!(child_scope(&parent(), &names(&["example.org"]), &[], &[]).is_none());
+        assert!(child_scope(&parent(), &names(&["www.example.com"]), &[Record::A], &[]).is_none());
+        let mut grant = parent();
+        grant.set_not_after(Some(TIME + 60));
+        let scope = child_scope(&grant, &names(&["www.example.com"]), &[], &[]).unwrap();
+        assert_eq!(issue(KEY, &grant, scope, 600, TIME).unwrap().not_after(), TIME + 60);
+    }
+
+    #[test]
+    fn reject_forged_claims() {
+        let scope = child_scope(&parent(), &["www.example.com".to_owned()], &[], &[]).unwrap();
+        let token = issue(KEY, &parent(), scope, 600, TIME).unwrap();
+        let wider = Claims {
+            parent: "ci".to_owned(),
+            scope: Scope::new(vec!["*.example.com".to_owned()], vec![], vec![]),
+            not_after: TIME + 600,
+        };
+        let forged = ::crypto::delegated_key_id(&::serde_json::to_vec(&wider).unwrap());
+        assert!(verify_at(KEY, &forged, b"msg", &sign(&token, b"msg"), TIME).is_none());
+    }
+}
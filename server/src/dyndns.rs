@@ -0,0 +1,235 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compatibility endpoint for clients speaking the dyndns2 protocol.
+
+use acl::Grant;
+use actix_web::{HttpRequest, HttpResponse, Query};
+use data::{Delete, Operation, Record, Update};
+use nsupdate;
+use owner;
+use propagation;
+use std::{
+    net::{IpAddr, SocketAddr}, sync::Arc,
+};
+use util::{BasicAuth, Config};
+use zone;
+
+const TTL: u32 = 60;
+
+#[derive(Deserialize)]
+pub struct Params {
+    hostname: Option<String>,
+    myip: Option<String>,
+}
+
+fn parse_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse()
+        .ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|a| a.ip()))
+}
+
+/// Sets the A and AAAA RRsets of `host` to the addresses. Every RRset that
+/// differs from the addresses served by the primary is replaced, all of them in
+/// a single update.
+fn update_host(token: &Grant, host: &str, ips: &[IpAddr], config: &Config) -> String {
+    if !zone::valid_name(host) {
+        return "notfqdn".to_owned();
+    }
+    let rrsets: Vec<_> = [Record::A, Record::AAAA]
+        .iter()
+        .map(|&record| {
+            let mut values: Vec<_> = ips
+                .iter()
+                .filter(|ip| ip.is_ipv4() == (record == Record::A))
+                .map(IpAddr::to_string)
+                .collect();
+            values.sort();
+            values.dedup();
+            (record, values)
+        }).filter(|(_, values)| !values.is_empty())
+        .collect();
+    if !rrsets
+        .iter()
+        .all(|&(record, _)| token.allows(host, record, Operation::Update))
+    {
+        return "nohost".to_owned();
+    }
+    let names: Vec<_> = rrsets.iter().map(|&(record, _)| (host, record)).collect();
+    if owner::check_all(token, &names, config).is_err() {
+        return "nohost".to_owned();
+    }
+    let ips = ips
+        .iter()
+        .map(IpAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    let changed: Vec<_> = rrsets
+        .into_iter()
+        .filter(|&(record, ref values)| {
+            let mut current = propagation::query(config.server(), host, record, config)
+                .unwrap_or_default();
+            current.sort();
+            current != *values
+        }).collect();
+    if changed.is_empty() {
+        return format!("nochg {}", ips);
+    }
+    let mut deletes = Vec::new();
+    let mut updates = Vec::new();
+    for (record, values) in changed {
+        info!(
+            "dyndns: {} sets {} record for {} to {}",
+            token.name(),
+            record,
+            host,
+            values.join(",")
+        );
+        deletes.push(Delete::new(host.to_owned(), record));
+        updates.extend(
+            values
+                .into_iter()
+                .map(|value| Update::new(host.to_owned(), value, record, TTL)),
+        );
+    }
+    let deletes: Vec<_> = deletes.iter().collect();
+    let updates: Vec<_> = updates.iter().collect();
+    if nsupdate::apply(token, &deletes, &updates, false, config).is_err() {
+        return "911".to_owned();
+    }
+    format!("good {}", ips)
+}
+
+/// Handles `GET /nic/update?hostname=<hosts>&myip=<ips>`. If `myip` is missing,
+/// the address of the caller is used.
+pub fn update(
+    (token, params, req): (BasicAuth, Query<Params>, HttpRequest<Arc<Config>>),
+) -> HttpResponse {
+    let hosts = match params.hostname {
+        Some(ref hosts) if !hosts.is_empty() => hosts,
+        _ => return HttpResponse::Ok().content_type("text/plain").body("notfqdn"),
+    };
+    let ips: Option<Vec<_>> = match params.myip {
+        Some(ref ips) if !ips.is_empty() => {
+            ips.split(',').map(|ip| parse_ip(ip.trim())).collect()
+        }
+        _ => req
+            .connection_info()
+            .remote()
+            .and_then(parse_ip)
+            .map(|ip| vec![ip]),
+    };
+    let body = match ips {
+        Some(ips) => hosts
+            .split(',')
+            .map(|host| update_host(&token, host.trim(), &ips, req.state()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        None => "dnserr".to_owned(),
+    };
+    HttpResponse::Ok().content_type("text/plain").body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use acl::Scope;
+    use std::fs;
+
+    #[test]
+    fn reject_invalid_hostnames() {
+        let config = ::testutil::config();
+        let ips = ["192.0.2.1".parse().unwrap()];
+        for host in &[
+            "x\nupdate delete victim.org A\nhome.example.com",
+            "-t.example.com",
+            "home example.com",
+        ] {
+            assert_eq!(
+                update_host(&Grant::master(), host, &ips, &config),
+                "notfqdn"
+            );
+        }
+    }
+
+    #[test]
+    fn update_hosts() {
+        let dir = ::testutil::temp_dir("dyndns");
+        let input = dir.join("input");
+        let mut config = ::testutil::fake_nsupdate(&dir, &format!("cat > {}\n", input.display()));
+        config.set_dig(::testutil::script(
+            &dir,
+            "dig",
+            "case \"$*\" in\n*\\ AAAA) echo '2001:db8::1' ;;\n*\\ A) echo '192.0.2.1' ;;\nesac\n",
+        ));
+        let ips = |ips: &[&str]| -> Vec<IpAddr> {
+            ips.iter().map(|ip| ip.parse().unwrap()).collect()
+        };
+        let host = "home.example.com";
+
+        let result = update_host(&Grant::master(), host, &ips(&["192.0.2.1"]), &config);
+        assert_eq!(result, "nochg 192.0.2.1");
+        assert!(!input.exists());
+
+        // all addresses of a type end up in one RRset, which is replaced once
+        let result = update_host(
+            &Grant::master(),
+            host,
+            &ips(&["192.0.2.2", "2001:db8::1", "192.0.2.1"]),
+            &config,
+        );
+        assert_eq!(result, "good 192.0.2.2,2001:db8::1,192.0.2.1");
+        assert_eq!(
+            fs::read_to_string(&input).unwrap(),
+            "server 127.0.0.1\nupdate delete home.example.com A\n\
+             update add home.example.com 60 A 192.0.2.1\n\
+             update add home.example.com 60 A 192.0.2.2\nsend\n"
+        );
+
+        let token = Grant::new(
+            "home".to_owned(),
+            Some(Scope::new(vec![host.to_owned()], vec![Record::A], Vec::new())),
+            false,
+        );
+        assert_eq!(update_host(&token, host, &ips(&["2001:db8::2"]), &config), "nohost");
+
+        drop(config);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
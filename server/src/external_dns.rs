@@ -0,0 +1,457 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Webhook provider for Kubernetes external-dns. The provider is served on a
+//! separate listener without authentication, since external-dns runs it as a
+//! sidecar and connects via localhost. Other addresses must be allowed
+//! explicitly. Changes are applied as the `external-dns` identity, which is
+//! restricted to the managed zones.
+
+use acl::{name_matches, Grant, Scope};
+use actix_web::{
+    error::{self, ErrorBadRequest, ErrorForbidden, ErrorInternalServerError}, http,
+    middleware::Logger, App, HttpResponse, State,
+};
+use data::{Delete, Record, ResourceRecord, Update};
+use failure::Error;
+use nsupdate;
+use owner::OwnerError;
+use script::{self, ScriptError};
+use std::{
+    collections::HashMap, net::{IpAddr, ToSocketAddrs}, sync::Arc,
+};
+use util::Config;
+use zone;
+
+#[derive(Debug, Fail)]
+pub enum EndpointError {
+    #[fail(display = "Unsupported record type {}", _0)]
+    Record(String),
+    #[fail(display = "Invalid name {:?}", _0)]
+    Name(String),
+    #[fail(display = "Invalid target {:?} for {}", _1, _0)]
+    Target(String, String),
+    #[fail(display = "Name {} outside of the managed zones", _0)]
+    Zone(String),
+    #[fail(display = "Refusing to serve the webhook without authentication on {}", _0)]
+    Listen(String),
+}
+
+const MEDIA_TYPE: &str = "application/external.dns.webhook+json;version=1";
+const DEFAULT_TTL: u32 = 300;
+const SUPPORTED: &[Record] = &[
+    Record::A,
+    Record::AAAA,
+    Record::CNAME,
+    Record::TXT,
+    Record::SRV,
+    Record::NS,
+    Record::MX,
+    Record::PTR,
+    Record::CAA,
+];
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderSpecific {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Endpoint {
+    dns_name: String,
+    #[serde(default)]
+    targets: Vec<String>,
+    record_type: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    set_identifier: String,
+    #[serde(default, rename = "recordTTL", skip_serializing_if = "Option::is_none")]
+    record_ttl: Option<u32>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    labels: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    provider_specific: Vec<ProviderSpecific>,
+}
+
+impl Endpoint {
+    fn from_rrset(records: &[ResourceRecord]) -> Self {
+        let first = &records[0];
+        Self {
+            dns_name: first.name().trim_end_matches('.').to_owned(),
+            targets: records
+                .iter()
+                .map(|r| match r.record() {
+                    Record::CNAME | Record::NS | Record::PTR => {
+                        r.value().trim_end_matches('.').to_owned()
+                    }
+                    _ => r.value().to_owned(),
+                }).collect(),
+            record_type: first.record().to_string(),
+            set_identifier: String::new(),
+            record_ttl: Some(first.ttl()),
+            labels: HashMap::new(),
+            provider_specific: Vec::new(),
+        }
+    }
+
+    fn record(&self) -> Result<Record, EndpointError> {
+        self.record_type
+            .parse()
+            .ok()
+            .filter(|r| SUPPORTED.contains(r))
+            .ok_or_else(|| EndpointError::Record(self.record_type.clone()))
+    }
+
+    fn name(&self) -> Result<&str, EndpointError> {
+        if script::valid_name(&self.dns_name) {
+            Ok(&self.dns_name)
+        } else {
+            Err(EndpointError::Name(self.dns_name.clone()))
+        }
+    }
+
+    /// Checks that a target is an address of the record's family, a domain
+    /// name for records pointing to names or a single line otherwise.
+    fn check_target(&self, record: Record, target: &str) -> Result<(), EndpointError> {
+        let valid = match record {
+            Record::A => matches!(target.parse(), Ok(IpAddr::V4(_))),
+            Record::AAAA => matches!(target.parse(), Ok(IpAddr::V6(_))),
+            Record::CNAME | Record::NS | Record::PTR => zone::valid_name(target),
+            _ => script::valid_value(target),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(EndpointError::Target(self.dns_name.clone(), target.to_owned()))
+        }
+    }
+
+    fn delete(&self) -> Result<Delete, EndpointError> {
+        Ok(Delete::new(self.name()?.to_owned(), self.record()?))
+    }
+
+    fn updates(&self) -> Result<Vec<Update>, EndpointError> {
+        let name = self.name()?;
+        let record = self.record()?;
+        let ttl = self.record_ttl.filter(|t| *t > 0).unwrap_or(DEFAULT_TTL);
+        self.targets
+            .iter()
+            .map(|t| {
+                self.check_target(record, t)?;
+                let value = if record == Record::TXT && !t.starts_with('"') {
+                    format!("\"{}\"", t.replace('"', "\\\""))
+                } else {
+                    t.clone()
+                };
+                Ok(Update::new(name.to_owned(), value, record, ttl))
+            }).collect()
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct Changes {
+    #[serde(default)]
+    create: Option<Vec<Endpoint>>,
+    #[serde(default)]
+    update_old: Option<Vec<Endpoint>>,
+    #[serde(default)]
+    update_new: Option<Vec<Endpoint>>,
+    #[serde(default)]
+    delete: Option<Vec<Endpoint>>,
+}
+
+impl Changes {
+    /// Returns the RRsets to delete and the records to add. All names must be
+    /// inside of the managed `zones`.
+    fn apply_to(&self, zones: &[String]) -> Result<(Vec<Delete>, Vec<Update>), EndpointError> {
+        let mut deletes = Vec::new();
+        let mut updates = Vec::new();
+        for endpoint in self.delete.iter().chain(self.update_old.iter()).flatten() {
+            deletes.push(endpoint.delete()?);
+        }
+        for endpoint in self.create.iter().chain(self.update_new.iter()).flatten() {
+            updates.extend(endpoint.updates()?);
+        }
+        if let Some(name) = deletes
+            .iter()
+            .map(Delete::name)
+            .chain(updates.iter().map(Update::name))
+            .find(|n| !in_zones(n, zones))
+        {
+            return Err(EndpointError::Zone(name.to_owned()));
+        }
+        Ok((deletes, updates))
+    }
+}
+
+fn in_zones(name: &str, zones: &[String]) -> bool {
+    zones
+        .iter()
+        .any(|z| name_matches(z, name) || name_matches(&format!("*.{}", z), name))
+}
+
+/// Returns the identity external-dns acts as, which may change the supported
+/// records in the managed `zones`.
+fn grant(zones: &[String]) -> Grant {
+    let names = zones
+        .iter()
+        .flat_map(|z| vec![z.clone(), format!("*.{}", z)])
+        .collect();
+    Grant::new(
+        "external-dns".to_owned(),
+        Some(Scope::new(names, SUPPORTED.to_vec(), Vec::new())),
+        false,
+    )
+}
+
+/// Applies the changes in a single update message, so they are applied
+/// atomically.
+fn apply(changes: &Changes, config: &Config) -> Result<(), Error> {
+    let zones = config.external_dns_zones();
+    let (deletes, updates) = changes.apply_to(zones)?;
+    if deletes.is_empty() && updates.is_empty() {
+        return Ok(());
+    }
+    info!(
+        "external-dns: deleting {} RRsets, adding {} records",
+        deletes.len(),
+        updates.len()
+    );
+    nsupdate::apply(
+        &grant(zones),
+        &deletes.iter().collect::<Vec<_>>(),
+        &updates.iter().collect::<Vec<_>>(),
+        false,
+        config,
+    )?;
+    Ok(())
+}
+
+/// Checks that the webhook listener is only reachable via loopback, unless
+/// remote access is allowed explicitly.
+pub fn check_listen(listen: &str, allow_remote: bool) -> Result<(), Error> {
+    let addrs: Vec<_> = listen.to_socket_addrs()?.collect();
+    if !allow_remote && (addrs.is_empty() || addrs.iter().any(|a| !a.ip().is_loopback())) {
+        return Err(EndpointError::Listen(listen.to_owned()).into());
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct DomainFilter<'a> {
+    include: &'a [String],
+}
+
+fn respond<T: ::serde::Serialize>(body: &T) -> Result<HttpResponse, error::Error> {
+    Ok(HttpResponse::Ok()
+        .content_type(MEDIA_TYPE)
+        .body(::serde_json::to_string(body)?))
+}
+
+/// Handles `GET /` and tells external-dns which domains are managed.
+fn negotiate(state: State<Arc<Config>>) -> Result<HttpResponse, error::Error> {
+    respond(&DomainFilter {
+        include: state.external_dns_zones(),
+    })
+}
+
+/// Handles `GET /records` by transferring all managed zones.
+fn records(state: State<Arc<Config>>) -> Result<HttpResponse, error::Error> {
+    let mut endpoints = Vec::new();
+    for zone in state.external_dns_zones() {
+        let mut rrsets: Vec<(String, Record, Vec<ResourceRecord>)> = Vec::new();
+        for record in zone::transfer(zone, &state)
+            .map_err(|_| ErrorInternalServerError("Zone transfer failed"))?
+            .into_iter()
+            .filter(|r| SUPPORTED.contains(&r.record()))
+        {
+            let name = record.name().to_lowercase();
+            match rrsets
+                .iter_mut()
+                .find(|(n, t, _)| *n == name && *t == record.record())
+            {
+                Some((_, _, records)) => records.push(record),
+                None => rrsets.push((name, record.record(), vec![record])),
+            }
+        }
+        endpoints.extend(rrsets.iter().map(|(_, _, r)| Endpoint::from_rrset(r)));
+    }
+    respond(&endpoints)
+}
+
+/// Handles `POST /records`.
+fn apply_changes((body, state): (String, State<Arc<Config>>)) -> Result<HttpResponse, error::Error> {
+    let changes: Changes =
+        ::serde_json::from_str(&body).map_err(|_| ErrorBadRequest("Invalid changes"))?;
+    apply(&changes, &state).map_err(|e| {
+        let invalid = e.downcast_ref::<EndpointError>().is_some()
+            || e.downcast_ref::<ScriptError>().is_some();
+        if invalid {
+            return ErrorBadRequest(e.to_string());
+        }
+        if e.downcast_ref::<OwnerError>().is_some() {
+            return ErrorForbidden(e.to_string());
+        }
+        error!("external-dns: cannot apply changes: {}", e);
+        ErrorInternalServerError("Error applying the changes")
+    })?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Handles `POST /adjustendpoints`. Endpoints are used as they are.
+fn adjust_endpoints(body: String) -> Result<HttpResponse, error::Error> {
+    let endpoints: Vec<Endpoint> =
+        ::serde_json::from_str(&body).map_err(|_| ErrorBadRequest("Invalid endpoints"))?;
+    respond(&endpoints)
+}
+
+pub fn app(config: Arc<Config>) -> App<Arc<Config>> {
+    App::with_state(config)
+        .middleware(Logger::default())
+        .route("/", http::Method::GET, negotiate)
+        .route("/records", http::Method::GET, records)
+        .route("/records", http::Method::POST, apply_changes)
+        .route("/adjustendpoints", http::Method::POST, adjust_endpoints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::Db;
+
+    fn changes(changes: &str) -> Changes {
+        ::serde_json::from_str(changes).unwrap()
+    }
+
+    fn map(json: &str) -> Result<(Vec<Delete>, Vec<Update>), EndpointError> {
+        changes(json).apply_to(&["example.com".to_owned()])
+    }
+
+    #[test]
+    fn map_changes() {
+        let (deletes, updates) = map(
+            r#"{
+                "Delete": [{"dnsName": "old.example.com", "recordType": "A", "targets": []}],
+                "Create": [
+                    {"dnsName": "www.example.com", "recordType": "A",
+                     "targets": ["192.0.2.1", "192.0.2.2"], "recordTTL": 60},
+                    {"dnsName": "txt.example.com", "recordType": "TXT",
+                     "targets": ["heritage=external-dns"]}
+                ]
+            }"#,
+        ).unwrap();
+        assert_eq!(deletes.len(), 1);
+        assert_eq!(deletes[0].name(), "old.example.com");
+        assert_eq!(updates.len(), 3);
+        assert_eq!(updates[1].value(), "192.0.2.2");
+        assert_eq!(updates[1].ttl(), 60);
+        assert_eq!(updates[2].value(), "\"heritage=external-dns\"");
+        assert_eq!(updates[2].ttl(), DEFAULT_TTL);
+    }
+
+    #[test]
+    fn reject_invalid_endpoints() {
+        for endpoint in &[
+            r#"{"dnsName": "x\nupdate delete victim.org A\nwww.example.com", "recordType": "A"}"#,
+            r#"{"dnsName": "www.example.com", "recordType": "A", "targets": ["192.0.2.1\nsend"]}"#,
+            r#"{"dnsName": "www.example.com", "recordType": "A", "targets": ["2001:db8::1"]}"#,
+            r#"{"dnsName": "www.example.com", "recordType": "CNAME", "targets": ["a b"]}"#,
+            r#"{"dnsName": "www.example.com", "recordType": "TXT", "targets": ["a\nsend"]}"#,
+            r#"{"dnsName": "www.example.com", "recordType": "SOA", "targets": []}"#,
+            r#"{"dnsName": "www.example.org", "recordType": "A", "targets": ["192.0.2.1"]}"#,
+        ] {
+            assert!(
+                map(&format!(r#"{{"Create": [{}]}}"#, endpoint)).is_err(),
+                "{}",
+                endpoint
+            );
+        }
+    }
+
+    #[test]
+    fn refuse_foreign_rrsets() {
+        let dir = ::testutil::temp_dir("external-dns");
+        let mut config = ::testutil::fake_nsupdate(&dir, "cat > /dev/null\n");
+        config.set_dig(::testutil::script(
+            &dir,
+            "dig",
+            "case \"$*\" in\n*www.example.com*) \
+             echo 'www.example.com. 60 IN A 192.0.2.1' ;;\nesac\n",
+        ));
+        config.set_external_dns_zones(vec!["example.com".to_owned()]);
+        config.set_enforce_owners(true);
+        let db = Db::open(dir.join("db")).unwrap();
+        let update =
+            Update::new("www.example.com".to_owned(), "192.0.2.1".to_owned(), Record::A, 60);
+        db.record(&Grant::new("alice".to_owned(), None, false), &[], &[&update])
+            .unwrap();
+        config.set_db(db);
+
+        let create = |name: &str| {
+            changes(&format!(
+                r#"{{"Create": [{{"dnsName": "{}", "recordType": "A",
+                                  "targets": ["192.0.2.2"]}}]}}"#,
+                name
+            ))
+        };
+        match apply(&create("www.example.com"), &config).map_err(|e| e.downcast::<OwnerError>()) {
+            Err(Ok(OwnerError::Foreign(_, _, owner))) => assert_eq!(owner, "alice"),
+            _ => panic!("expected a foreign RRset"),
+        }
+        apply(&create("new.example.com"), &config).unwrap();
+        let db = config.db().unwrap();
+        let owner = db.owner("new.example.com", Record::A).unwrap().unwrap();
+        assert_eq!(owner.owner(), "external-dns");
+        assert_eq!(db.history().unwrap().len(), 2);
+        drop(config);
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn listen_addresses() {
+        assert!(check_listen("127.0.0.1:8888", false).is_ok());
+        assert!(check_listen("[::1]:8888", false).is_ok());
+        assert!(check_listen("0.0.0.0:8888", false).is_err());
+        assert!(check_listen("192.0.2.1:8888", false).is_err());
+        assert!(check_listen("0.0.0.0:8888", true).is_ok());
+    }
+}
@@ -0,0 +1,187 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Endpoints for the lego `httpreq` DNS provider, which is also used by
+//! cert-manager webhooks.
+
+use actix_web::{
+    error::{self, ErrorBadRequest, ErrorForbidden}, HttpResponse, Json, State,
+};
+use data::{Delete, Operation, Record, Update};
+use std::sync::Arc;
+use util::{BasicAuth, Config};
+use zone;
+
+const TTL: u32 = 60;
+
+/// Request body in the default and in the `RAW` mode of the provider.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Challenge {
+    Default {
+        fqdn: String,
+        value: String,
+    },
+    Raw {
+        domain: String,
+        #[serde(rename = "keyAuth")]
+        key_auth: String,
+    },
+}
+
+/// Returns `true` if `value` only consists of base64url characters, as the
+/// challenge values computed by ACME clients (and by the `RAW` mode) do.
+fn valid_value(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= 255
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Challenge {
+    /// Returns the name and value of the TXT record for the challenge, or
+    /// `None` if the name is no domain name or the value is no challenge
+    /// value.
+    fn record(&self) -> Option<(String, String)> {
+        let (name, value) = match *self {
+            Challenge::Default {
+                ref fqdn,
+                ref value,
+            } => (fqdn.clone(), value.clone()),
+            Challenge::Raw {
+                ref domain,
+                ref key_auth,
+                ..
+            } => (
+                format!("_acme-challenge.{}.", domain.trim_end_matches('.')),
+                ::base64::encode_config(
+                    &::crypto::sha256(key_auth.as_bytes()),
+                    ::base64::URL_SAFE_NO_PAD,
+                ),
+            ),
+        };
+        if zone::valid_name(&name) && valid_value(&value) {
+            Some((name, value))
+        } else {
+            None
+        }
+    }
+}
+
+/// Handles `POST /present` by creating the TXT record.
+pub fn present(
+    (token, challenge, state): (BasicAuth, Json<Challenge>, State<Arc<Config>>),
+) -> Result<HttpResponse, error::Error> {
+    let (name, value) = challenge
+        .record()
+        .ok_or_else(|| ErrorBadRequest("Invalid challenge"))?;
+    if !token.allows(&name, Record::TXT, Operation::Update) {
+        return Err(ErrorForbidden("Name not allowed"));
+    }
+    info!("httpreq: {} presents challenge for {}", token.name(), name);
+    let update = Update::new(name, value, Record::TXT, TTL);
+    ::apply(&token, &[], &[&update], false, &state)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Handles `POST /cleanup` by removing the TXT value of the challenge. Other
+/// values of the RRset are left alone.
+pub fn cleanup(
+    (token, challenge, state): (BasicAuth, Json<Challenge>, State<Arc<Config>>),
+) -> Result<HttpResponse, error::Error> {
+    let (name, value) = challenge
+        .record()
+        .ok_or_else(|| ErrorBadRequest("Invalid challenge"))?;
+    if !token.allows(&name, Record::TXT, Operation::Delete) {
+        return Err(ErrorForbidden("Name not allowed"));
+    }
+    info!("httpreq: {} cleans up challenge for {}", token.name(), name);
+    let mut delete = Delete::new(name, Record::TXT);
+    delete.set_value(Some(value));
+    ::apply(&token, &[&delete], &[], false, &state)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(json: &str) -> Option<(String, String)> {
+        ::serde_json::from_str::<Challenge>(json).unwrap().record()
+    }
+
+    const KEY_AUTH: &str = "LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0.\
+                            9jg46WB3rR_AHD-EBXdN7cBkH1WOu0tA3M9fm21mqTI";
+    const VALUE: &str = "LPsIwTo7o8BoG0-vjCyGQGBWSVIPxI-i_X336eUOQZo";
+
+    #[test]
+    fn raw_mode() {
+        let expected = Some(("_acme-challenge.example.com.".to_owned(), VALUE.to_owned()));
+        assert_eq!(
+            record(&format!(
+                r#"{{"domain": "example.com", "token": "foo", "keyAuth": "{}"}}"#,
+                KEY_AUTH
+            )),
+            expected
+        );
+        assert_eq!(
+            record(&format!(
+                r#"{{"fqdn": "_acme-challenge.example.com.", "value": "{}"}}"#,
+                VALUE
+            )),
+            expected
+        );
+    }
+
+    #[test]
+    fn reject_invalid_input() {
+        for json in &[
+            r#"{"fqdn": "x\nupdate delete victim.org A\nexample.com", "value": "foo"}"#,
+            r#"{"fqdn": "_acme-challenge.example.com.", "value": "foo\nsend"}"#,
+            r#"{"fqdn": "_acme-challenge.example.com.", "value": "foo bar"}"#,
+            r#"{"fqdn": "_acme-challenge.example.com.", "value": "\"foo\""}"#,
+            r#"{"fqdn": "_acme-challenge.example.com.", "value": ""}"#,
+            r#"{"fqdn": "-f.example.com", "value": "foo"}"#,
+            r#"{"domain": "example.com victim.org", "token": "foo", "keyAuth": "foo.bar"}"#,
+        ] {
+            assert_eq!(record(json), None, "{}", json);
+        }
+    }
+}
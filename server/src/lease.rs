@@ -0,0 +1,153 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Leases of record values. The leases are kept in the state store, which
+//! creates, renews and releases them together with the changes of the
+//! records. A background thread deletes records whose lease ended.
+
+use acl::Grant;
+use data::{Delete, Lease};
+use db::Db;
+use nsupdate;
+use std::{
+    sync::Arc, thread, time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use util::Config;
+
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Returns the current UNIX timestamp.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Returns the leases that have ended at `now`.
+fn expired(leases: Vec<Lease>, now: u64) -> Vec<Lease> {
+    leases.into_iter().filter(|l| l.expires() <= now).collect()
+}
+
+fn reap(db: &Db, config: &Config) {
+    let leases = match db.leases() {
+        Ok(leases) => leases,
+        Err(e) => {
+            error!("cannot read leases: {}", e);
+            return;
+        }
+    };
+    let reaper = Grant::new("lease".to_owned(), None, false);
+    for lease in expired(leases, now()) {
+        info!(
+            "lease expired, deleting {} record for {} with value \"{}\"",
+            lease.record(),
+            lease.name(),
+            lease.value()
+        );
+        let mut delete = Delete::new(lease.name().to_owned(), lease.record());
+        delete.set_value(Some(lease.value().to_owned()));
+        if let Err(e) = nsupdate::apply(&reaper, &[&delete], &[], false, config) {
+            warn!("cannot delete expired record, retrying later: {}", e);
+        }
+    }
+}
+
+/// Spawns a thread that periodically deletes records with expired leases.
+pub fn spawn_reaper(config: Arc<Config>) {
+    thread::spawn(move || loop {
+        if let Some(db) = config.db() {
+            reap(db, &config);
+        }
+        thread::sleep(REAP_INTERVAL);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::{Record, Update};
+
+    #[test]
+    fn expire_and_reopen() {
+        let path = ::std::env::temp_dir().join("bind9-api-leases-test");
+        let _ = ::std::fs::remove_dir_all(&path);
+        let grant = Grant::new("alice".to_owned(), None, false);
+        let update = |name: &str, value: &str, lease| {
+            let mut update = Update::new(name.to_owned(), value.to_owned(), Record::TXT, 60);
+            update.set_lease(Some(lease));
+            update
+        };
+        {
+            let db = Db::open(&path).unwrap();
+            db.record(&grant, &[], &[&update("a.example.com", "foo", 60)])
+                .unwrap();
+            db.record(&grant, &[], &[&update("b.example.com", "bar", 3600)])
+                .unwrap();
+            // renewing replaces the lease
+            db.record(&grant, &[], &[&update("a.example.com", "foo", 120)])
+                .unwrap();
+            assert_eq!(db.leases().unwrap().len(), 2);
+
+            let now = now();
+            assert!(expired(db.leases().unwrap(), now).is_empty());
+            let reaped = expired(db.leases().unwrap(), now + 120);
+            assert_eq!(reaped.len(), 1);
+            assert_eq!(reaped[0].name(), "a.example.com");
+            assert_eq!(expired(db.leases().unwrap(), now + 3600).len(), 2);
+        }
+
+        let db = Db::open(&path).unwrap();
+        assert_eq!(db.leases().unwrap().len(), 2);
+        db.record(&grant, &[&Delete::new("a.example.com".to_owned(), Record::TXT)], &[])
+            .unwrap();
+        drop(db);
+        let db = Db::open(&path).unwrap();
+        assert_eq!(
+            db.leases()
+                .unwrap()
+                .iter()
+                .map(|l| l.name().to_owned())
+                .collect::<Vec<_>>(),
+            vec!["b.example.com"]
+        );
+        drop(db);
+        ::std::fs::remove_dir_all(&path).unwrap();
+    }
+}
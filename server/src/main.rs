@@ -37,7 +37,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+extern crate actix;
 extern crate actix_web;
+extern crate base64;
 extern crate crypto;
 extern crate data;
 #[macro_use]
@@ -47,99 +49,401 @@ extern crate failure;
 extern crate futures;
 #[macro_use]
 extern crate log;
+extern crate openssl;
 extern crate pretty_env_logger;
 extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 extern crate serde_json;
+extern crate sled;
+extern crate toml;
 
+mod acl;
+mod acme_dns;
+mod admin;
 mod cli;
+mod client_cert;
+mod db;
+mod delegate;
+mod dyndns;
+mod external_dns;
+mod httpreq;
+mod lease;
+mod nsupdate;
+mod oidc;
+mod owner;
+mod poller;
+mod propagation;
+mod reconcile;
+mod script;
+mod ssh;
+mod store;
+#[cfg(test)]
+mod testutil;
 mod util;
+mod zone;
 
 use actix_web::{
-    error::{self, ErrorInternalServerError}, http, middleware::Logger, server, App, Result, State,
+    error::{self, ErrorBadRequest, ErrorForbidden, ErrorInternalServerError}, http,
+    middleware::Logger, server,
+    App, FutureResponse, HttpResponse, Json, Path, Query, Result, State,
 };
-use data::{Delete, Update};
-use failure::Error;
-use std::{
-    io::Write, process::{Command, Stdio}, sync::Arc,
+use data::{
+    Batch, Delete, Expectation, Lease, Operation, Propagation, Record, RecordQuery,
+    ResourceRecord, Update, Wait,
 };
-use util::{Config, ExecuteError, Validated};
+use futures::{future, Future};
+use std::sync::Arc;
+use acl::Grant;
+use owner::OwnerError;
+use script::ScriptError;
+use util::{Authenticated, Config, ExecuteError, Validated};
 
-fn execute_nsupdate(input: &str, config: &Config) -> Result<String, Error> {
-    info!("executing update: {}", input);
-    let mut cmd = Command::new(config.command())
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .args(&["-k", config.key_path()])
-        .spawn()?;
-    {
-        let stdin = cmd.stdin.as_mut().ok_or(ExecuteError::Stdin)?;
-        stdin.write_all(input.as_bytes())?;
+/// Applies the changes using `nsupdate::apply` and maps its errors to
+/// responses.
+fn apply(
+    grant: &Grant,
+    deletes: &[&Delete],
+    updates: &[&Update],
+    dry_run: bool,
+    config: &Config,
+) -> Result<String, error::Error> {
+    nsupdate::apply(grant, deletes, updates, dry_run, config).map_err(|e| {
+        if let Some(e) = e.downcast_ref::<ScriptError>() {
+            return ErrorBadRequest(e.to_string());
+        }
+        if let Some(e) = e.downcast_ref::<OwnerError>() {
+            return ErrorForbidden(e.to_string());
+        }
+        error!("cannot apply update: {}", e);
+        match e.downcast::<ExecuteError>() {
+            Ok(ExecuteError::Marker) => ErrorInternalServerError("Marker not found"),
+            _ => ErrorInternalServerError("Error applying the update"),
+        }
+    })
+}
+
+/// Responds with the output of the update or, if requested, waits for its
+/// propagation on the poller threads.
+fn respond(
+    output: Result<String, error::Error>,
+    dry_run: bool,
+    wait: Option<u32>,
+    name: &str,
+    record: Record,
+    expect: Expectation,
+    config: &Arc<Config>,
+) -> FutureResponse<HttpResponse> {
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    match wait {
+        Some(timeout) if !dry_run => Box::new(
+            poller::wait(config, name, record, expect, timeout)
+                .map(|propagation| HttpResponse::Ok().json(propagation)),
+        ),
+        _ => Box::new(future::ok(HttpResponse::Ok().body(output))),
     }
-    let output = cmd.wait_with_output()?.stdout;
-    let output = String::from_utf8(output)?;
-    info!("output: {}", output);
-    Ok(output)
 }
 
 fn delete(
     (delete, state): (Validated<Delete>, State<Arc<Config>>),
-) -> Result<&'static str, error::Error> {
+) -> FutureResponse<HttpResponse> {
     info!("Deleting {} record for {}", delete.record(), delete.name());
-    let stdin = format!(
-        "server {}\nupdate delete {} {}\nsend\n",
-        state.server(),
+    let output = if delete
+        .grant()
+        .allows(delete.name(), delete.record(), Operation::Delete)
+    {
+        apply(delete.grant(), &[&*delete], &[], delete.dry_run(), &state)
+    } else {
+        Err(ErrorForbidden("Name or record type not allowed"))
+    };
+    respond(
+        output,
+        delete.dry_run(),
+        delete.wait(),
         delete.name(),
-        delete.record()
-    );
-    Ok(execute_nsupdate(&stdin, &state)
-        .map_err(|_| ErrorInternalServerError("Error executing nsupdate"))
-        .and_then(|s| {
-            if s.contains(state.ok_marker()) {
-                Ok("OK")
-            } else {
-                Err(ErrorInternalServerError("Marker not found"))
-            }
-        })?)
+        delete.record(),
+        delete
+            .value()
+            .map_or(Expectation::Absent, |v| Expectation::Missing(v.to_owned())),
+        &state,
+    )
 }
 
 fn update(
     (update, state): (Validated<Update>, State<Arc<Config>>),
-) -> Result<&'static str, error::Error> {
+) -> FutureResponse<HttpResponse> {
     info!(
         "Updating {} record for {} with value \"{}\"",
         update.record(),
         update.name(),
         update.value()
     );
-    let stdin = format!(
-        "server {}\nupdate add {} {} {} {}\nsend\n",
-        state.server(),
+    let output = if !update
+        .grant()
+        .allows(update.name(), update.record(), Operation::Update)
+    {
+        Err(ErrorForbidden("Name or record type not allowed"))
+    } else if update.lease().is_some() && state.db().is_none() {
+        Err(ErrorBadRequest("Leases require the state store"))
+    } else {
+        apply(update.grant(), &[], &[&*update], update.dry_run(), &state)
+    };
+    respond(
+        output,
+        update.dry_run(),
+        update.wait(),
         update.name(),
-        update.ttl(),
         update.record(),
-        update.value()
+        Expectation::Present(update.value().to_owned()),
+        &state,
+    )
+}
+
+/// Applies all deletes and updates of the batch in a single update message.
+fn batch(
+    (batch, state): (Validated<Batch>, State<Arc<Config>>),
+) -> Result<HttpResponse, error::Error> {
+    info!(
+        "Applying batch of {} deletes and {} updates",
+        batch.deletes().len(),
+        batch.updates().len()
     );
-    Ok(execute_nsupdate(&stdin, &state)
-        .map_err(|_| ErrorInternalServerError("Error executing nsupdate"))
-        .and_then(|s| {
-            if s.contains(state.ok_marker()) {
-                Ok("OK")
-            } else {
-                Err(ErrorInternalServerError("Marker not found"))
-            }
-        })?)
+    let grant = batch.grant();
+    if !batch
+        .deletes()
+        .iter()
+        .all(|d| grant.allows(d.name(), d.record(), Operation::Delete))
+        || !batch
+            .updates()
+            .iter()
+            .all(|u| grant.allows(u.name(), u.record(), Operation::Update))
+    {
+        return Err(ErrorForbidden("Name or record type not allowed"));
+    }
+    if batch.updates().iter().any(|u| u.lease().is_some()) && state.db().is_none() {
+        return Err(ErrorBadRequest("Leases require the state store"));
+    }
+    let deletes: Vec<_> = batch.deletes().iter().collect();
+    let updates: Vec<_> = batch.updates().iter().collect();
+    let output = apply(grant, &deletes, &updates, batch.dry_run(), &state)?;
+    Ok(HttpResponse::Ok().body(output))
+}
+
+/// Returns the RRset as it is served by the primary server.
+fn read(
+    (auth, query, state): (Authenticated, Query<RecordQuery>, State<Arc<Config>>),
+) -> Result<Json<Vec<ResourceRecord>>, error::Error> {
+    if !auth.covers(query.name(), query.record()) {
+        return Err(ErrorForbidden("Name or record type not allowed"));
+    }
+    zone::check_name(query.name()).map_err(|e| ErrorBadRequest(e.to_string()))?;
+    zone::lookup(query.name(), query.record(), &state)
+        .map(Json)
+        .map_err(|_| ErrorInternalServerError("Error querying the server"))
+}
+
+/// Waits until the RRset reached the expected state on all authoritative
+/// servers.
+fn wait(
+    (wait, state): (Validated<Wait>, State<Arc<Config>>),
+) -> FutureResponse<Json<Propagation>> {
+    if !wait.grant().covers(wait.name(), wait.record()) {
+        return Box::new(future::err(ErrorForbidden(
+            "Name or record type not allowed",
+        )));
+    }
+    if let Err(e) = zone::check_name(wait.name()) {
+        return Box::new(future::err(ErrorBadRequest(e.to_string())));
+    }
+    Box::new(
+        poller::wait(
+            &state,
+            wait.name(),
+            wait.record(),
+            wait.expect().clone(),
+            wait.timeout(),
+        ).map(Json),
+    )
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    Zone,
+    Json,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: Option<ExportFormat>,
+}
+
+/// Transfers the zone from the primary and returns the records visible to the
+/// credential as master file (the default) or JSON.
+fn export_zone(
+    (auth, zone, query, state): (
+        Authenticated,
+        Path<String>,
+        Query<ExportQuery>,
+        State<Arc<Config>>,
+    ),
+) -> Result<HttpResponse, error::Error> {
+    if !zone::valid_name(&zone) {
+        return Err(ErrorBadRequest("Invalid zone name"));
+    }
+    let records: Vec<_> = zone::transfer(&zone, &state)
+        .map_err(|_| ErrorInternalServerError("Zone transfer failed"))?
+        .into_iter()
+        .filter(|r| auth.covers(r.name(), r.record()))
+        .collect();
+    if records.is_empty() {
+        return Err(ErrorForbidden("Zone not allowed"));
+    }
+    Ok(match query.format {
+        Some(ExportFormat::Json) => HttpResponse::Ok().json(records),
+        _ => HttpResponse::Ok()
+            .content_type("text/dns")
+            .body(zone::master_file(&zone, &records)),
+    })
+}
+
+fn leases(
+    (auth, state): (Authenticated, State<Arc<Config>>),
+) -> Result<Json<Vec<Lease>>, error::Error> {
+    let leases = match state.db() {
+        Some(db) => db
+            .leases()
+            .map_err(|_| ErrorInternalServerError("Error reading the state store"))?,
+        None => Vec::new(),
+    };
+    Ok(Json(
+        leases
+            .into_iter()
+            .filter(|l| auth.covers(l.name(), l.record()))
+            .collect(),
+    ))
 }
 
 fn main() {
     std::env::set_var("RUST_LOG", "info");
     pretty_env_logger::init();
     let matches = cli::parse_args();
+    if let Some(matches) = matches.subcommand_matches("export") {
+        let db = db::Db::open(matches.value_of("STORE").unwrap()).expect("Cannot open state store");
+        let export = db.export().expect("Cannot read state store");
+        serde_json::to_writer_pretty(std::io::stdout(), &export).expect("Cannot write export");
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("import") {
+        let db = db::Db::open(matches.value_of("STORE").unwrap()).expect("Cannot open state store");
+        let file = std::fs::File::open(matches.value_of("FILE").unwrap()).expect("Cannot open export");
+        let export = serde_json::from_reader(file).expect("Cannot parse export");
+        db.import(&export).expect("Cannot import state store");
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("migrate") {
+        let db = db::Db::open(matches.value_of("STORE").unwrap()).expect("Cannot open state store");
+        let leases: Vec<Lease> = match matches.value_of("LEASES") {
+            Some(path) => {
+                let file = std::fs::File::open(path).expect("Cannot open lease file");
+                serde_json::from_reader(file).expect("Cannot parse lease file")
+            }
+            None => Vec::new(),
+        };
+        let owners: Vec<db::Owner> = match matches.value_of("OWNERS") {
+            Some(path) => {
+                let file = std::fs::File::open(path).expect("Cannot open owner file");
+                serde_json::from_reader(file).expect("Cannot parse owner file")
+            }
+            None => Vec::new(),
+        };
+        db.import_files(&leases, &owners).expect("Cannot migrate into state store");
+        return;
+    }
     let token = matches.value_of("TOKEN").unwrap().to_owned();
     let command = matches.value_of("CMD").unwrap_or("nsupdate").to_owned();
     let key_path = matches.value_of("KEYPATH").unwrap().to_owned();
     let ok_marker = matches.value_of("OKMARK").unwrap_or("").to_owned();
     let server = matches.value_of("SERVER").unwrap_or("127.0.0.1").to_owned();
-    let config = Arc::new(Config::new(token, command, key_path, ok_marker, server));
+    let mut config = Config::new(token, command, key_path, ok_marker, server);
+    config.set_legacy_signatures(matches.is_present("LEGACYSIGNATURES"));
+    if let Some(dig) = matches.value_of("DIG") {
+        config.set_dig(dig.to_owned());
+    }
+    if let Some(max_wait) = matches.value_of("MAXWAIT") {
+        config.set_max_wait(max_wait.parse().expect("Cannot parse maximum wait"));
+    }
+    if let Some(path) = matches.value_of("STORE") {
+        config.set_db(db::Db::open(path).expect("Cannot open state store"));
+    }
+    if let Some(interval) = matches.value_of("RECONCILEINTERVAL") {
+        config.set_reconciler(reconcile::Reconciler::new(
+            std::time::Duration::from_secs(interval.parse().expect("Cannot parse interval")),
+            matches
+                .values_of("REPAIRZONE")
+                .map(|zones| zones.map(str::to_owned).collect())
+                .unwrap_or_default(),
+        ));
+    }
+    config.set_enforce_owners(matches.is_present("ENFORCEOWNERS"));
+    if let Some(zone) = matches.value_of("ACMEDNSZONE") {
+        let accounts = matches
+            .value_of("ACMEDNSACCOUNTS")
+            .expect("Missing acme-dns account file");
+        let mut acme_dns = acme_dns::AcmeDns::open(zone.to_owned(), accounts)
+            .expect("Cannot read acme-dns account file");
+        if let Some(networks) = matches.values_of("ACMEDNSREGISTERFROM") {
+            acme_dns
+                .set_register_from(networks.map(str::to_owned).collect())
+                .expect("Cannot parse acme-dns registration network");
+        }
+        if let Some(max_accounts) = matches.value_of("ACMEDNSMAXACCOUNTS") {
+            acme_dns.set_max_accounts(max_accounts.parse().expect("Cannot parse account limit"));
+        }
+        if let Some(proxies) = matches.values_of("ACMEDNSPROXY") {
+            acme_dns.set_proxies(
+                proxies
+                    .map(|p| p.parse().expect("Cannot parse proxy address"))
+                    .collect(),
+            );
+        }
+        config.set_acme_dns(acme_dns);
+    }
+    if let Some(zones) = matches.values_of("EXTERNALDNSZONE") {
+        config.set_external_dns_zones(
+            zones
+                .map(|z| z.trim_end_matches('.').to_owned())
+                .collect(),
+        );
+    }
+    if let Some(path) = matches.value_of("TOKENS") {
+        config.set_tokens(acl::Tokens::open(path).expect("Cannot read token file"));
+    }
+    if let Some(path) = matches.value_of("AUTHORIZEDKEYS") {
+        config.set_authorized_keys(
+            ssh::AuthorizedKeys::open(path).expect("Cannot read authorized keys file"),
+        );
+    }
+    if let Some(path) = matches.value_of("CLIENTCERTS") {
+        config.set_client_certs(
+            client_cert::ClientCerts::open(path).expect("Cannot read client certificate file"),
+        );
+    }
+    if let Some(path) = matches.value_of("OIDC") {
+        config.set_oidc(oidc::Oidc::open(path).expect("Cannot read OIDC configuration"));
+    }
+    if let Some(path) = matches.value_of("TOKENSTORE") {
+        config.set_token_store(admin::TokenStore::open(path).expect("Cannot read token store"));
+    }
+    let sys = actix::System::new("bind9-api");
+    config.set_poller(poller::start());
+    let config = Arc::new(config);
+    lease::spawn_reaper(config.clone());
+    reconcile::spawn_reconciler(config.clone());
     let port: u16 = matches
         .value_of("PORT")
         .unwrap_or("8000")
@@ -147,12 +451,41 @@ fn main() {
         .expect("Cannot parse port");
     let host = matches.value_of("HOST").unwrap_or("0.0.0.0");
     let host = format!("{}:{}", host, port);
+    if let Some(listen) = matches.value_of("EXTERNALDNSLISTEN") {
+        external_dns::check_listen(listen, matches.is_present("EXTERNALDNSREMOTE"))
+            .expect("Cannot serve the external-dns webhook provider");
+        let config = config.clone();
+        server::new(move || external_dns::app(config.clone()))
+            .bind(listen)
+            .unwrap()
+            .start();
+    }
     server::new(move || {
         App::with_state(config.clone())
             .middleware(Logger::default())
             .route("/record", http::Method::POST, update)
             .route("/record", http::Method::DELETE, delete)
+            .route("/record", http::Method::GET, read)
+            .route("/batch", http::Method::POST, batch)
+            .route("/wait", http::Method::POST, wait)
+            .route("/leases", http::Method::GET, leases)
+            .route("/zone/{zone}/export", http::Method::GET, export_zone)
+            .route("/drift", http::Method::GET, reconcile::drift)
+            .route("/metrics", http::Method::GET, reconcile::metrics)
+            .route("/nic/update", http::Method::GET, dyndns::update)
+            .route("/present", http::Method::POST, httpreq::present)
+            .route("/cleanup", http::Method::POST, httpreq::cleanup)
+            .route("/register", http::Method::POST, acme_dns::register)
+            .route("/update", http::Method::POST, acme_dns::update)
+            .route("/tokens/delegate", http::Method::POST, delegate::delegate)
+            .route("/admin/store", http::Method::GET, db::export)
+            .route("/admin/tokens", http::Method::POST, admin::create)
+            .route("/admin/tokens", http::Method::GET, admin::list)
+            .route("/admin/tokens/{name}", http::Method::GET, admin::show)
+            .route("/admin/tokens/{name}", http::Method::DELETE, admin::revoke)
+            .route("/admin/tokens/{name}/expire", http::Method::POST, admin::expire)
     }).bind(host)
     .unwrap()
-    .run();
+    .start();
+    sys.run();
 }
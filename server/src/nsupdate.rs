@@ -0,0 +1,131 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sends changes to the primary server using `nsupdate`. Every endpoint that
+//! changes records goes through `apply`, so the owners of the RRsets are
+//! checked before and the changes are recorded in the state store after.
+
+use acl::Grant;
+use data::{Delete, Update};
+use failure::Error;
+use owner;
+use script;
+use std::{
+    io::Write, process::{Command, Stdio},
+};
+use util::{Config, ExecuteError};
+
+/// Runs `nsupdate` with `input` as script and returns its output.
+pub fn execute(input: &str, config: &Config) -> Result<String, Error> {
+    info!("executing update: {}", input);
+    let mut cmd = Command::new(config.command())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .args(["-k", config.key_path()])
+        .spawn()?;
+    {
+        let stdin = cmd.stdin.as_mut().ok_or(ExecuteError::Stdin)?;
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = cmd.wait_with_output()?.stdout;
+    let output = String::from_utf8(output)?;
+    info!("output: {}", output);
+    Ok(output)
+}
+
+/// Applies all deletes and updates as `grant` in a single update message and
+/// records them in the state store. A dry run only checks the owners and
+/// returns the script instead.
+pub fn apply(
+    grant: &Grant,
+    deletes: &[&Delete],
+    updates: &[&Update],
+    dry_run: bool,
+    config: &Config,
+) -> Result<String, Error> {
+    let script = script::batch_script(deletes, updates, config)?;
+    let rrsets: Vec<_> = deletes
+        .iter()
+        .map(|d| (d.name(), d.record()))
+        .chain(updates.iter().map(|u| (u.name(), u.record())))
+        .collect();
+    owner::check_all(grant, &rrsets, config)?;
+    if dry_run {
+        info!("dry run, not executing update: {}", script);
+        return Ok(script);
+    }
+    if !execute(&script, config)?.contains(config.ok_marker()) {
+        return Err(ExecuteError::Marker.into());
+    }
+    if let Some(db) = config.db() {
+        db.record(grant, deletes, updates)?;
+    }
+    Ok("OK".to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::Record;
+    use db::Db;
+    use std::fs;
+
+    #[test]
+    fn dry_run() {
+        let dir = ::testutil::temp_dir("nsupdate");
+        let input = dir.join("input");
+        let mut config = ::testutil::fake_nsupdate(&dir, &format!("cat > {}\n", input.display()));
+        config.set_db(Db::open(dir.join("db")).unwrap());
+        let update =
+            Update::new("www.example.com".to_owned(), "192.0.2.1".to_owned(), Record::A, 60);
+        let script = "server 127.0.0.1\nupdate add www.example.com 60 A 192.0.2.1\nsend\n";
+
+        let output = apply(&Grant::master(), &[], &[&update], true, &config).unwrap();
+        assert_eq!(output, script);
+        assert!(!input.exists());
+        assert!(config.db().unwrap().history().unwrap().is_empty());
+
+        let output = apply(&Grant::master(), &[], &[&update], false, &config).unwrap();
+        assert_eq!(output, "OK");
+        assert_eq!(fs::read_to_string(&input).unwrap(), script);
+        assert_eq!(config.db().unwrap().history().unwrap().len(), 1);
+        drop(config);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ownership of the RRsets created through the API. The state store records
+//! the identity that owns each RRset. RRsets that are owned by another
+//! identity or exist without an owner, e.g. because they were created in the
+//! zone file, may only be changed by credentials with the `override`
+//! permission. An RRset that no longer exists can be claimed by anyone.
+
+use acl::Grant;
+use data::{Operation, Record};
+use db::Db;
+use failure::Error;
+use util::Config;
+use zone;
+
+#[derive(Debug, Fail)]
+pub enum OwnerError {
+    #[fail(display = "{} record for {} is owned by {}", _1, _0, _2)]
+    Foreign(String, Record, String),
+    #[fail(display = "{} record for {} was not created using the API", _1, _0)]
+    Unmanaged(String, Record),
+}
+
+/// Checks that `grant` may change the RRset.
+pub fn check(
+    db: &Db,
+    grant: &Grant,
+    name: &str,
+    record: Record,
+    config: &Config,
+) -> Result<(), Error> {
+    if grant.allows(name, record, Operation::Override) {
+        return Ok(());
+    }
+    let owner = db.owner(name, record)?.map(|o| o.owner().to_owned());
+    if owner.as_deref() == Some(grant.owner()) {
+        return Ok(());
+    }
+    if zone::lookup(name, record, config)?.is_empty() {
+        return Ok(());
+    }
+    Err(match owner {
+        Some(owner) => OwnerError::Foreign(name.to_owned(), record, owner),
+        None => OwnerError::Unmanaged(name.to_owned(), record),
+    }.into())
+}
+
+/// Checks that `grant` may change all RRsets, if ownership is enforced.
+pub fn check_all(grant: &Grant, rrsets: &[(&str, Record)], config: &Config) -> Result<(), Error> {
+    if let (true, Some(db)) = (config.enforce_owners(), config.db()) {
+        for &(name, record) in rrsets {
+            check(db, grant, name, record, config)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use acl::Scope;
+    use data::Update;
+    use std::fs;
+
+    #[test]
+    fn check_owners() {
+        let dir = ::testutil::temp_dir("owners");
+        let db = Db::open(dir.join("db")).unwrap();
+        // serves the RRset, so it cannot be claimed
+        let config = ::testutil::fake_dig(&dir, "echo 'www.example.com. 60 IN TXT \"foo\"'\n");
+        let scope = |operations| Scope::new(vec!["*.example.com".to_owned()], vec![], operations);
+        let alice = Grant::new("alice".to_owned(), Some(scope(vec![])), false);
+        let mut delegated = Grant::new("alice (delegated)".to_owned(), Some(scope(vec![])), false);
+        delegated.set_owner(Some("alice".to_owned()));
+        let bob = Grant::new("bob".to_owned(), Some(scope(vec![])), false);
+        let admin = Grant::new("admin".to_owned(), Some(scope(vec![Operation::Override])), false);
+
+        let check = |grant: &Grant| check(&db, grant, "www.example.com", Record::TXT, &config);
+        match check(&alice).map_err(|e| e.downcast::<OwnerError>()) {
+            Err(Ok(OwnerError::Unmanaged(..))) => (),
+            _ => panic!("expected an unmanaged RRset"),
+        }
+        let update = Update::new("www.example.com".to_owned(), "foo".to_owned(), Record::TXT, 60);
+        db.record(&delegated, &[], &[&update]).unwrap();
+        assert!(check(&alice).is_ok());
+        assert!(check(&delegated).is_ok());
+        match check(&bob).map_err(|e| e.downcast::<OwnerError>()) {
+            Err(Ok(OwnerError::Foreign(_, _, owner))) => assert_eq!(owner, "alice"),
+            _ => panic!("expected a foreign RRset"),
+        }
+        assert!(check(&admin).is_ok());
+        drop(db);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Waits for propagation on a pool of synchronous actors. Polling sleeps
+//! between the rounds, so it must not run on the workers of the server.
+
+use actix::{Actor, Addr, Handler, Message, SyncArbiter, SyncContext};
+use actix_web::{error::ErrorInternalServerError, FutureResponse};
+use data::{Expectation, Propagation, Record};
+use failure::Error;
+use futures::{future, Future};
+use propagation;
+use std::sync::Arc;
+use util::Config;
+
+/// Number of concurrent waits. Further waits are queued.
+const THREADS: usize = 8;
+
+pub struct Poller;
+
+impl Actor for Poller {
+    type Context = SyncContext<Self>;
+}
+
+/// Message to wait for the propagation of a RRset.
+pub struct Poll {
+    config: Arc<Config>,
+    name: String,
+    record: Record,
+    expect: Expectation,
+    timeout: u32,
+}
+
+impl Message for Poll {
+    type Result = Result<Propagation, Error>;
+}
+
+impl Handler<Poll> for Poller {
+    type Result = Result<Propagation, Error>;
+
+    fn handle(&mut self, poll: Poll, _: &mut Self::Context) -> Self::Result {
+        propagation::wait(
+            &poll.name,
+            poll.record,
+            &poll.expect,
+            poll.timeout,
+            &poll.config,
+        )
+    }
+}
+
+/// Starts the poller threads. This must be called after the actix system was
+/// created.
+pub fn start() -> Addr<Poller> {
+    SyncArbiter::start(THREADS, || Poller)
+}
+
+/// Resolves once the RRset reached the expected state on all authoritative
+/// servers or the timeout passed. See `propagation::wait`.
+pub fn wait(
+    config: &Arc<Config>,
+    name: &str,
+    record: Record,
+    expect: Expectation,
+    timeout: u32,
+) -> FutureResponse<Propagation> {
+    let poller = match config.poller() {
+        Some(poller) => poller,
+        None => {
+            return Box::new(future::err(ErrorInternalServerError(
+                "Error checking propagation",
+            )))
+        }
+    };
+    let poll = Poll {
+        config: config.clone(),
+        name: name.to_owned(),
+        record,
+        expect,
+        timeout,
+    };
+    Box::new(poller.send(poll).then(|result| match result {
+        Ok(Ok(propagation)) => Ok(propagation),
+        Ok(Err(e)) => {
+            error!("cannot check propagation: {}", e);
+            Err(ErrorInternalServerError("Error checking propagation"))
+        }
+        Err(e) => {
+            error!("cannot reach the poller: {}", e);
+            Err(ErrorInternalServerError("Error checking propagation"))
+        }
+    }))
+}
@@ -0,0 +1,221 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use data::{Expectation, Propagation, Record, ServerStatus};
+use failure::Error;
+use std::{
+    process::Command, thread, time::{Duration, Instant},
+};
+use util::{Config, ExecuteError};
+use zone;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Checks whether the answers of a server satisfy the expectation.
+fn matches(expect: &Expectation, record: Record, answers: &[String]) -> bool {
+    match *expect {
+        Expectation::Present(ref value) => {
+            let value = record.normalize_value(value);
+            answers.iter().any(|a| record.normalize_value(a) == value)
+        }
+        Expectation::Missing(ref value) => {
+            let value = record.normalize_value(value);
+            answers.iter().all(|a| record.normalize_value(a) != value)
+        }
+        Expectation::Absent => answers.is_empty(),
+    }
+}
+
+fn dig(config: &Config, args: &[&str]) -> Result<String, Error> {
+    let output = Command::new(config.dig()).args(args).output()?.stdout;
+    Ok(String::from_utf8(output)?)
+}
+
+/// Finds the zone `name` belongs to by looking at the owner of the SOA record
+/// returned by the primary server.
+fn find_zone(name: &str, config: &Config) -> Result<String, Error> {
+    zone::check_name(name)?;
+    let server = format!("@{}", config.server());
+    let output = dig(
+        config,
+        &["+noall", "+answer", "+authority", &server, name, "SOA"],
+    )?;
+    output
+        .lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        .find(|f| f.len() > 3 && f[3] == "SOA")
+        .map(|f| f[0].to_owned())
+        .ok_or_else(|| ExecuteError::NoZone(name.to_owned()).into())
+}
+
+fn nameservers(zone: &str, config: &Config) -> Result<Vec<String>, Error> {
+    zone::check_name(zone)?;
+    let server = format!("@{}", config.server());
+    let nameservers: Vec<_> = dig(config, &["+short", &server, zone, "NS"])?
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(str::to_owned)
+        .collect();
+    if nameservers.is_empty() {
+        return Err(ExecuteError::NoNameservers(zone.to_owned()).into());
+    }
+    Ok(nameservers)
+}
+
+/// Queries a single server for the RRset without recursion.
+pub fn query(
+    ns: &str,
+    name: &str,
+    record: Record,
+    config: &Config,
+) -> Result<Vec<String>, Error> {
+    zone::check_name(name)?;
+    let server = format!("@{}", ns);
+    Ok(dig(
+        config,
+        &["+short", "+norecurse", &server, name, &record.to_string()],
+    )?
+    .lines()
+    .map(str::trim)
+    .filter(|l| !l.is_empty())
+    .map(str::to_owned)
+    .collect())
+}
+
+/// Polls every authoritative server of the zone `name` belongs to, until the
+/// RRset reached the expected state or `timeout` seconds passed. The timeout
+/// is capped by `Config::max_wait`, as every wait occupies a poller thread.
+pub fn wait(
+    name: &str,
+    record: Record,
+    expect: &Expectation,
+    timeout: u32,
+    config: &Config,
+) -> Result<Propagation, Error> {
+    let zone = find_zone(name, config)?;
+    let mut servers: Vec<_> = nameservers(&zone, config)?
+        .into_iter()
+        .map(|ns| (ns, false))
+        .collect();
+    let timeout = timeout.min(config.max_wait());
+    let deadline = Instant::now() + Duration::from_secs(timeout.into());
+    loop {
+        for (ns, visible) in servers.iter_mut().filter(|(_, v)| !v) {
+            match query(ns, name, record, config) {
+                Ok(answers) => *visible = matches(expect, record, &answers),
+                Err(e) => warn!("cannot query {}: {}", ns, e),
+            }
+        }
+        if servers.iter().all(|(_, v)| *v) || Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    info!("propagation of {} {}: {:?}", name, record, servers);
+    Ok(Propagation::new(
+        zone,
+        servers
+            .into_iter()
+            .map(|(ns, visible)| ServerStatus::new(ns, visible))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn expectations() {
+        let present = |value: &str| Expectation::Present(value.to_owned());
+        let missing = |value: &str| Expectation::Missing(value.to_owned());
+        let answers = vec!["\"Foo\"".to_owned(), "\"bar\"".to_owned()];
+        assert!(matches(&present("Foo"), Record::TXT, &answers));
+        assert!(matches(&present("\"bar\""), Record::TXT, &answers));
+        // TXT values are case-sensitive, e.g. ACME challenges
+        assert!(!matches(&present("foo"), Record::TXT, &answers));
+        assert!(matches(&missing("foo"), Record::TXT, &answers));
+        assert!(!matches(&missing("Foo"), Record::TXT, &answers));
+        assert!(!matches(&Expectation::Absent, Record::TXT, &answers));
+        assert!(matches(&Expectation::Absent, Record::TXT, &[]));
+        let answers = vec!["ns1.example.com.".to_owned()];
+        assert!(matches(&present("NS1.example.com"), Record::NS, &answers));
+    }
+
+    /// Returns a config with a `dig` replacement that answers the SOA query
+    /// and prints `ns` for the NS query.
+    fn fake_dig(dir: &::std::path::Path, ns: &str) -> Config {
+        ::testutil::fake_dig(
+            dir,
+            &format!(
+                "case \"$*\" in\n*SOA*) echo 'example.com. 3600 IN SOA ns1.example.com. \
+                 hostmaster.example.com. 1 7200 3600 1209600 3600' ;;\n*NS*) echo '{}' ;;\nesac\n",
+                ns
+            ),
+        )
+    }
+
+    #[test]
+    fn wait_for_servers() {
+        let dir = ::testutil::temp_dir("propagation");
+        let config = fake_dig(&dir, "");
+        let err =
+            wait("www.example.com", Record::A, &Expectation::Absent, 10, &config).unwrap_err();
+        match err.downcast::<ExecuteError>() {
+            Ok(ExecuteError::NoNameservers(zone)) => assert_eq!(zone, "example.com."),
+            _ => panic!("expected missing nameservers"),
+        }
+
+        // the requested timeout is capped, so this returns after one round
+        let mut config = fake_dig(&dir, "ns1.example.com.");
+        config.set_max_wait(0);
+        let expect = Expectation::Present("192.0.2.1".to_owned());
+        let propagation = wait("www.example.com", Record::A, &expect, 3600, &config).unwrap();
+        assert_eq!(propagation.zone(), "example.com.");
+        assert_eq!(propagation.servers().len(), 1);
+        assert!(!propagation.complete());
+
+        assert!(wait("-f/etc/passwd", Record::A, &Expectation::Absent, 0, &config).is_err());
+        assert!(query("ns1.example.com.", "+tcp", Record::A, &config).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,378 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Background reconciler that compares the RRsets in the state store with the
+//! records served by the primary. Differences are reported by `GET /drift` and
+//! `GET /metrics`. In zones with the repair policy, the RRset is set back to
+//! the state in the store.
+//!
+//! RRsets that were changed using the API since the run started are skipped,
+//! so changes in flight are not mistaken for drift.
+
+use actix_web::{
+    error::{self, ErrorNotFound}, HttpResponse, Json, State,
+};
+use data::{Delete, Record, ResourceRecord, Update};
+use db::{Db, ManagedRRset};
+use failure::Error;
+use lease::now;
+use nsupdate;
+use script;
+use std::{
+    fmt::Write, sync::{Arc, Mutex}, thread, time::Duration,
+};
+use util::{Authenticated, Config};
+use zone;
+
+/// A RRset whose served records differ from the state in the store.
+#[derive(Serialize, Clone, Debug)]
+pub struct Drift {
+    name: String,
+    record: Record,
+    ttl: u32,
+    expected: Vec<String>,
+    actual: Vec<ResourceRecord>,
+    repair: bool,
+    detected: u64,
+}
+
+#[derive(Default)]
+struct Status {
+    drift: Vec<Drift>,
+    managed: usize,
+    last_run: u64,
+    runs: u64,
+    repairs: u64,
+    errors: u64,
+}
+
+pub struct Reconciler {
+    interval: Duration,
+    repair_zones: Vec<String>,
+    status: Mutex<Status>,
+}
+
+/// Returns `true` if the served records match the RRset in the store.
+fn in_sync(rrset: &ManagedRRset, actual: &[ResourceRecord]) -> bool {
+    let record = rrset.record();
+    let mut expected: Vec<_> = rrset.values().iter().map(|v| record.normalize_value(v)).collect();
+    let mut served: Vec<_> = actual.iter().map(|r| record.normalize_value(r.value())).collect();
+    expected.sort();
+    expected.dedup();
+    served.sort();
+    served.dedup();
+    expected == served && actual.iter().all(|r| r.ttl() == rrset.ttl())
+}
+
+fn in_zone(name: &str, zone: &str) -> bool {
+    let name = name.trim_end_matches('.').to_lowercase();
+    let zone = zone.trim_end_matches('.').to_lowercase();
+    name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+impl Reconciler {
+    /// Creates a new Reconciler that runs every `interval` and repairs drift
+    /// in `repair_zones`.
+    pub fn new(interval: Duration, repair_zones: Vec<String>) -> Self {
+        Self {
+            interval,
+            repair_zones,
+            status: Mutex::new(Status::default()),
+        }
+    }
+
+    fn repairs(&self, name: &str) -> bool {
+        self.repair_zones.iter().any(|zone| in_zone(name, zone))
+    }
+
+    /// Replaces the served RRset with the state in the store.
+    fn repair(&self, rrset: &ManagedRRset, config: &Config) -> Result<(), Error> {
+        let delete = Delete::new(rrset.name().to_owned(), rrset.record());
+        let updates: Vec<_> = rrset
+            .values()
+            .iter()
+            .map(|value| {
+                Update::new(
+                    rrset.name().to_owned(),
+                    value.clone(),
+                    rrset.record(),
+                    rrset.ttl(),
+                )
+            }).collect();
+        let script =
+            script::batch_script(&[&delete], &updates.iter().collect::<Vec<_>>(), config)?;
+        let output = nsupdate::execute(&script, config)?;
+        if !output.contains(config.ok_marker()) {
+            bail!("marker not found");
+        }
+        Ok(())
+    }
+
+    fn run(&self, db: &Db, config: &Config) -> Result<(), Error> {
+        let started = now();
+        let rrsets = db.rrsets()?;
+        let mut drift = Vec::new();
+        let (mut repairs, mut errors) = (0, 0);
+        for rrset in &rrsets {
+            let actual = match zone::lookup(rrset.name(), rrset.record(), config) {
+                Ok(actual) => actual,
+                Err(e) => {
+                    warn!("cannot query {} record for {}: {}", rrset.record(), rrset.name(), e);
+                    errors += 1;
+                    continue;
+                }
+            };
+            if in_sync(rrset, &actual) {
+                continue;
+            }
+            // skip RRsets that were changed since the run started
+            match db.rrset(rrset.name(), rrset.record())? {
+                Some(ref current) if current.updated() < started && current == rrset => {}
+                _ => continue,
+            }
+            let repair = self.repairs(rrset.name());
+            warn!(
+                "drift detected for {} record of {}{}",
+                rrset.record(),
+                rrset.name(),
+                if repair { ", repairing" } else { "" }
+            );
+            if repair {
+                match self.repair(rrset, config) {
+                    Ok(()) => repairs += 1,
+                    Err(e) => {
+                        error!("cannot repair {} record of {}: {}", rrset.record(), rrset.name(), e);
+                        errors += 1;
+                    }
+                }
+            }
+            drift.push(Drift {
+                name: rrset.name().to_owned(),
+                record: rrset.record(),
+                ttl: rrset.ttl(),
+                expected: rrset.values().to_vec(),
+                actual,
+                repair,
+                detected: started,
+            });
+        }
+        let mut status = self.status.lock().unwrap();
+        status.drift = drift;
+        status.managed = rrsets.len();
+        status.last_run = started;
+        status.runs += 1;
+        status.repairs += repairs;
+        status.errors += errors;
+        Ok(())
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn metrics(&self) -> String {
+        let status = self.status.lock().unwrap();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = write!(
+                out,
+                "# HELP bind9_api_{0} {1}\n# TYPE bind9_api_{0} {2}\nbind9_api_{0} {3}\n",
+                name, help, kind, value
+            );
+        };
+        metric(
+            "managed_rrsets",
+            "gauge",
+            "RRsets in the state store",
+            status.managed as u64,
+        );
+        metric(
+            "drifted_rrsets",
+            "gauge",
+            "RRsets whose served records differ from the state store",
+            status.drift.len() as u64,
+        );
+        metric(
+            "reconcile_runs_total",
+            "counter",
+            "Completed reconciler runs",
+            status.runs,
+        );
+        metric(
+            "reconcile_repairs_total",
+            "counter",
+            "RRsets repaired by the reconciler",
+            status.repairs,
+        );
+        metric(
+            "reconcile_errors_total",
+            "counter",
+            "Failed queries and repairs",
+            status.errors,
+        );
+        metric(
+            "reconcile_last_run_timestamp_seconds",
+            "gauge",
+            "Start of the last completed run",
+            status.last_run,
+        );
+        out
+    }
+}
+
+/// Spawns a thread that periodically compares the store with the primary.
+pub fn spawn_reconciler(config: Arc<Config>) {
+    thread::spawn(move || loop {
+        let interval = match (config.reconciler(), config.db()) {
+            (Some(reconciler), Some(db)) => {
+                if let Err(e) = reconciler.run(db, &config) {
+                    error!("reconciler failed: {}", e);
+                }
+                reconciler.interval
+            }
+            _ => return,
+        };
+        thread::sleep(interval);
+    });
+}
+
+fn reconciler(config: &Config) -> Result<&Reconciler, error::Error> {
+    config
+        .reconciler()
+        .ok_or_else(|| ErrorNotFound("Reconciler is not enabled"))
+}
+
+/// Handles `GET /drift`, lists the drifted RRsets visible to the credential.
+pub fn drift(
+    (auth, state): (Authenticated, State<Arc<Config>>),
+) -> Result<Json<Vec<Drift>>, error::Error> {
+    let status = reconciler(&state)?.status.lock().unwrap();
+    Ok(Json(
+        status
+            .drift
+            .iter()
+            .filter(|d| auth.covers(&d.name, d.record))
+            .cloned()
+            .collect(),
+    ))
+}
+
+/// Handles `GET /metrics`.
+pub fn metrics(state: State<Arc<Config>>) -> Result<HttpResponse, error::Error> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(reconciler(&state)?.metrics()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_drift() {
+        let rrset: ManagedRRset = ::serde_json::from_str(
+            r#"{"name": "www.example.com", "record": "TXT", "ttl": 60,
+                "values": ["a", "b"], "identity": "alice", "updated": 0}"#,
+        ).unwrap();
+        let rr = |ttl, value: &str| {
+            ResourceRecord::new("www.example.com.".to_owned(), ttl, Record::TXT, value.to_owned())
+        };
+        assert!(in_sync(&rrset, &[rr(60, "\"b\""), rr(60, "\"a\"")]));
+        assert!(!in_sync(&rrset, &[rr(60, "\"a\"")]));
+        assert!(!in_sync(&rrset, &[rr(60, "\"a\""), rr(60, "\"b\""), rr(60, "\"c\"")]));
+        assert!(!in_sync(&rrset, &[rr(300, "\"a\""), rr(300, "\"b\"")]));
+        assert!(!in_sync(&rrset, &[]));
+        assert!(!in_sync(&rrset, &[rr(60, "\"A\""), rr(60, "\"b\"")]));
+        let rrset: ManagedRRset = ::serde_json::from_str(
+            r#"{"name": "www.example.com", "record": "CNAME", "ttl": 60,
+                "values": ["Host.example.com"], "identity": "alice", "updated": 0}"#,
+        ).unwrap();
+        let cname = ResourceRecord::new(
+            "www.example.com.".to_owned(),
+            60,
+            Record::CNAME,
+            "host.example.com.".to_owned(),
+        );
+        assert!(in_sync(&rrset, &[cname]));
+
+        let reconciler = Reconciler::new(Duration::from_secs(60), vec!["example.com.".to_owned()]);
+        assert!(reconciler.repairs("www.Example.com"));
+        assert!(reconciler.repairs("example.com"));
+        assert!(!reconciler.repairs("www.badexample.com"));
+        assert!(reconciler.metrics().contains("bind9_api_drifted_rrsets 0\n"));
+    }
+
+    #[test]
+    fn report_drift() {
+        let dir = ::testutil::temp_dir("reconcile");
+        let db = Db::open(dir.join("db")).unwrap();
+        let update = |name: &str, value: &str| {
+            Update::new(name.to_owned(), value.to_owned(), Record::TXT, 60)
+        };
+        db.record(
+            &::acl::Grant::new("alice".to_owned(), None, false),
+            &[],
+            &[&update("www.example.com", "a"), &update("other.example.com", "b")],
+        ).unwrap();
+        let rrset = db.rrset("WWW.example.com.", Record::TXT).unwrap().unwrap();
+        assert_eq!(rrset.values(), &["a".to_owned()]);
+        assert!(rrset.updated() > 0);
+
+        // serves the RRset of www.example.com, but nothing for other.example.com
+        let config = ::testutil::fake_dig(
+            &dir,
+            "case \"$*\" in\n*www.example.com*) echo 'www.example.com. 60 IN TXT \"a\"' ;;\nesac\n",
+        );
+
+        // changes recorded in the same second count as in flight, so the run
+        // must start after them
+        thread::sleep(Duration::from_millis(1100));
+        let reconciler = Reconciler::new(Duration::from_secs(60), Vec::new());
+        reconciler.run(&db, &config).unwrap();
+        {
+            let status = reconciler.status.lock().unwrap();
+            assert_eq!(status.managed, 2);
+            assert_eq!(status.drift.len(), 1);
+            assert_eq!(status.drift[0].name, "other.example.com");
+            assert!(!status.drift[0].repair);
+        }
+        let metrics = reconciler.metrics();
+        assert!(metrics.contains("bind9_api_drifted_rrsets 1\n"));
+        assert!(metrics.contains("bind9_api_reconcile_runs_total 1\n"));
+        drop(db);
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Scripts for `nsupdate`. Every name and value is checked before it is
+//! written into a script, as a line break would let it inject arbitrary
+//! commands, e.g. updates of zones outside of the credential's scope.
+
+use data::{Delete, Update};
+use util::Config;
+use zone;
+
+#[derive(Debug, Fail)]
+pub enum ScriptError {
+    #[fail(display = "Invalid name {:?}", _0)]
+    Name(String),
+    #[fail(display = "Invalid value {:?}", _0)]
+    Value(String),
+}
+
+/// Returns `true` if `name` is a domain name, optionally with a wildcard as
+/// first label.
+pub fn valid_name(name: &str) -> bool {
+    zone::valid_name(name.strip_prefix("*.").unwrap_or(name))
+}
+
+/// Returns `true` if `value` is a single line. Values may contain spaces, as
+/// MX, SRV, CAA or TXT values consist of several fields, but no other
+/// whitespace or control characters.
+pub fn valid_value(value: &str) -> bool {
+    !value.trim().is_empty()
+        && value
+            .chars()
+            .all(|c| c == ' ' || !(c.is_whitespace() || c.is_control()))
+}
+
+fn check(name: &str, value: Option<&str>) -> Result<(), ScriptError> {
+    if !valid_name(name) {
+        return Err(ScriptError::Name(name.to_owned()));
+    }
+    match value {
+        Some(value) if !valid_value(value) => Err(ScriptError::Value(value.to_owned())),
+        _ => Ok(()),
+    }
+}
+
+/// Builds a script that sends all deletes and updates in a single update
+/// message, so they are applied atomically.
+pub fn batch_script(
+    deletes: &[&Delete],
+    updates: &[&Update],
+    config: &Config,
+) -> Result<String, ScriptError> {
+    let mut script = format!("server {}\n", config.server());
+    for delete in deletes {
+        check(delete.name(), delete.value())?;
+        script.push_str(&match delete.value() {
+            Some(value) => format!(
+                "update delete {} {} {}\n",
+                delete.name(),
+                delete.record(),
+                value
+            ),
+            None => format!("update delete {} {}\n", delete.name(), delete.record()),
+        });
+    }
+    for update in updates {
+        check(update.name(), Some(update.value()))?;
+        script.push_str(&format!(
+            "update add {} {} {} {}\n",
+            update.name(),
+            update.ttl(),
+            update.record(),
+            update.value()
+        ));
+    }
+    script.push_str("send\n");
+    Ok(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::Record;
+
+    #[test]
+    fn build_scripts() {
+        let config = ::testutil::config();
+        let update = Update::new(
+            "*.example.com".to_owned(),
+            "10 mail.example.com.".to_owned(),
+            Record::MX,
+            60,
+        );
+        let delete = Delete::new(update.name().to_owned(), update.record());
+        assert_eq!(
+            batch_script(&[&delete], &[&update], &config).unwrap(),
+            "server 127.0.0.1\nupdate delete *.example.com MX\n\
+             update add *.example.com 60 MX 10 mail.example.com.\nsend\n"
+        );
+    }
+
+    #[test]
+    fn reject_injection() {
+        let config = ::testutil::config();
+        let update = Update::new(
+            "x\nupdate delete victim.org A\nwww.example.com".to_owned(),
+            "192.0.2.1".to_owned(),
+            Record::A,
+            60,
+        );
+        match batch_script(&[], &[&update], &config) {
+            Err(ScriptError::Name(_)) => (),
+            _ => panic!("name accepted"),
+        }
+        let update = Update::new(
+            "www.example.com".to_owned(),
+            "foo\nupdate delete victim.org A".to_owned(),
+            Record::TXT,
+            60,
+        );
+        match batch_script(&[], &[&update], &config) {
+            Err(ScriptError::Value(_)) => (),
+            _ => panic!("value accepted"),
+        }
+        let mut delete = Delete::new("www.example.com".to_owned(), Record::TXT);
+        delete.set_value(Some("foo\tbar".to_owned()));
+        assert!(batch_script(&[&delete], &[], &config).is_err());
+        let delete = Delete::new("www.example.com victim.org".to_owned(), Record::TXT);
+        assert!(batch_script(&[&delete], &[], &config).is_err());
+        let delete = Delete::new("-www.example.com".to_owned(), Record::TXT);
+        assert!(batch_script(&[&delete], &[], &config).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verification of requests signed with SSH keys (see `crypto::sshsig`). The
+//! keys are read from a file in the format of OpenSSH's `authorized_keys`, the
+//! options of a key restrict what it may change:
+//!
+//! ```text
+//! # restricted to names, record types and operations
+//! names="www.example.com,*.dyn.example.com",records="A,AAAA" ssh-ed25519 AAAAC3Nz... alice@laptop
+//! # all names in a zone
+//! zones="example.com",operations="update" ssh-ed25519 AAAAC3Nz... bob@desktop
+//! ```
+//!
+//! `zones="example.com"` allows `example.com` and all names below it. If both
+//! `zones` and `names` are given, the names have to be inside the zones. Keys
+//! without options are unrestricted, like `[[key]]` entries of the token file.
+//! The comment is used as name of the key in logs.
+
+use acl::{name_matches, Grant, Scope};
+use data::{Operation, Record};
+use failure::Error;
+use std::{fs, path::Path};
+
+#[derive(Debug, Fail)]
+pub enum SshError {
+    #[fail(display = "Malformed key in line {}", _0)]
+    Malformed(usize),
+    #[fail(display = "Unknown option {} in line {}", _1, _0)]
+    UnknownOption(usize, String),
+    #[fail(display = "Name {} in line {} is outside of the zones", _1, _0)]
+    OutsideZone(usize, String),
+    #[fail(display = "Records or operations without names or zones in line {}", _0)]
+    Unscoped(usize),
+}
+
+struct AuthorizedKey {
+    key_id: String,
+    public_key: Vec<u8>,
+    name: String,
+    scope: Option<Scope>,
+}
+
+/// Splits at the first whitespace that is not inside double quotes.
+fn split_field(line: &str) -> (&str, &str) {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => return (&line[..i], line[i..].trim_start()),
+            _ => {}
+        }
+    }
+    (line, "")
+}
+
+/// Parses the options `name="value1,value2"`, separated by commas outside of
+/// quotes.
+fn parse_options(options: &str) -> Vec<(&str, Vec<&str>)> {
+    let mut parsed = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in options.char_indices().chain(Some((options.len(), ','))) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                let option = &options[start..i];
+                start = i + 1;
+                let (name, value) = match option.find('=') {
+                    Some(eq) => (&option[..eq], option[eq + 1..].trim_matches('"')),
+                    None => (option, ""),
+                };
+                let values = value.split(',').filter(|v| !v.is_empty()).collect();
+                parsed.push((name, values));
+            }
+            _ => {}
+        }
+    }
+    parsed
+}
+
+fn is_key_type(field: &str) -> bool {
+    field.starts_with("ssh-") || field.starts_with("ecdsa-")
+}
+
+fn parse_line(number: usize, line: &str) -> Result<AuthorizedKey, Error> {
+    let (first, rest) = split_field(line);
+    let (options, line) = if is_key_type(first) {
+        (Vec::new(), line)
+    } else {
+        (parse_options(first), rest)
+    };
+    let (key_type, rest) = split_field(line);
+    let (key, comment) = split_field(rest);
+    let public_key = ::base64::decode(key).map_err(|_| SshError::Malformed(number))?;
+    if ::crypto::sshsig::key_type(&public_key) != Some(key_type) {
+        return Err(SshError::Malformed(number).into());
+    }
+
+    let (mut names, mut zones, mut records, mut operations) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (option, values) in options {
+        match option {
+            "names" => names.extend(values.into_iter().map(str::to_owned)),
+            "zones" => zones.extend(values.into_iter().map(str::to_owned)),
+            "records" => for value in values {
+                records.push(value.parse::<Record>()?);
+            },
+            "operations" => for value in values {
+                operations.push(value.parse::<Operation>()?);
+            },
+            _ => return Err(SshError::UnknownOption(number, option.to_owned()).into()),
+        }
+    }
+    for name in &names {
+        let inside = |zone: &String| {
+            let name = name.trim_start_matches("*.");
+            name_matches(zone, name) || name_matches(&format!("*.{}", zone), name)
+        };
+        if !zones.is_empty() && !zones.iter().any(inside) {
+            return Err(SshError::OutsideZone(number, name.clone()).into());
+        }
+    }
+    if names.is_empty() {
+        names = zones
+            .iter()
+            .flat_map(|zone| vec![zone.clone(), format!("*.{}", zone)])
+            .collect();
+    }
+    let scope = if !names.is_empty() {
+        Some(Scope::new(names, records, operations))
+    } else if records.is_empty() && operations.is_empty() {
+        None
+    } else {
+        return Err(SshError::Unscoped(number).into());
+    };
+
+    let key_id = ::crypto::sshsig::key_id(&public_key);
+    Ok(AuthorizedKey {
+        name: if comment.is_empty() {
+            key_id.clone()
+        } else {
+            comment.to_owned()
+        },
+        key_id,
+        public_key,
+        scope,
+    })
+}
+
+#[derive(Default)]
+pub struct AuthorizedKeys {
+    keys: Vec<AuthorizedKey>,
+}
+
+impl AuthorizedKeys {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Verifies a `sshsig` signature of the key with id `key_id`.
+    pub fn verify(&self, key_id: &str, msg: &[u8], signature: &[u8]) -> Option<Grant> {
+        self.keys
+            .iter()
+            .find(|k| k.key_id == key_id)
+            .filter(|k| ::crypto::sshsig::verify(&k.public_key, msg, signature))
+            .map(|k| Grant::new(k.name.clone(), k.scope.clone(), false))
+    }
+}
+
+impl ::std::str::FromStr for AuthorizedKeys {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let keys = s
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(number, line)| parse_line(number, line))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { keys })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIMrJ7LtOZl7ZpWAdOjJj9BGcFsKeCiwkqoM53vL8Bw43";
+
+    #[test]
+    fn parse_restrictions() {
+        let keys: AuthorizedKeys = format!(
+            "# operators\n\
+             zones=\"example.com\",records=\"TXT\",operations=\"update,delete\" ssh-ed25519 {} alice@laptop\n\
+             ssh-ed25519 {}\n",
+            KEY, KEY
+        ).parse()
+        .unwrap();
+        let alice = &keys.keys[0];
+        assert_eq!(alice.name, "alice@laptop");
+        let scope = alice.scope.as_ref().unwrap();
+        assert!(scope.allows("example.com", Record::TXT, Operation::Delete));
+        assert!(scope.allows("_acme-challenge.www.example.com", Record::TXT, Operation::Update));
+        assert!(!scope.covers("www.example.com", Record::A));
+        assert!(!scope.covers("example.org", Record::TXT));
+        assert!(keys.keys[1].scope.is_none());
+        assert!(keys.keys[1].name.starts_with(::crypto::sshsig::KEY_ID_PREFIX));
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        for line in &[
+            format!("names=\"www.example.org\",zones=\"example.com\" ssh-ed25519 {}", KEY),
+            format!("no-pty ssh-ed25519 {}", KEY),
+            format!("records=\"A\" ssh-ed25519 {}", KEY),
+            format!("ssh-rsa {}", KEY),
+            "ssh-ed25519 invalid".to_owned(),
+        ] {
+            assert!(line.parse::<AuthorizedKeys>().is_err(), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use failure::Error;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs::{self, File, OpenOptions}, io::{ErrorKind, Write}, os::unix::fs::OpenOptionsExt,
+    path::PathBuf, sync::Mutex,
+};
+
+/// State that is kept in memory and persisted as JSON on every change.
+///
+/// Changes are written to a temporary file which is then renamed over the old
+/// one, so a crash never leaves a partially written file behind. The files are
+/// only readable by the owner, as they contain secrets.
+pub struct JsonFile<T> {
+    path: PathBuf,
+    data: Mutex<T>,
+}
+
+impl<T: Serialize + DeserializeOwned + Default> JsonFile<T> {
+    /// Loads the state from `path`. A missing file yields the default state.
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        let data = match File::open(&path) {
+            Ok(file) => ::serde_json::from_reader(file)?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => T::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            data: Mutex::new(data),
+        })
+    }
+
+    pub fn read<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        f(&self.data.lock().unwrap())
+    }
+
+    /// Applies `f` to a copy of the state and persists it. The copy replaces
+    /// the state only once it was written, so a failed write changes nothing.
+    pub fn update<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Result<R, Error>
+    where
+        T: Clone,
+    {
+        let mut data = self.data.lock().unwrap();
+        let mut updated = data.clone();
+        let result = f(&mut updated);
+        self.persist(&updated)?;
+        *data = updated;
+        Ok(result)
+    }
+
+    fn persist(&self, data: &T) -> Result<(), Error> {
+        let tmp = self.path.with_extension("tmp");
+        {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&tmp)?;
+            ::serde_json::to_writer(&mut file, data)?;
+            file.flush()?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn update() {
+        let dir = ::testutil::temp_dir("store");
+        let path = dir.join("state.json");
+        let store: JsonFile<Vec<u32>> = JsonFile::open(&path).unwrap();
+        store.update(|data| data.push(1)).unwrap();
+        assert_eq!(store.read(|data| data.clone()), vec![1]);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let store: JsonFile<Vec<u32>> = JsonFile::open(&path).unwrap();
+        assert_eq!(store.read(|data| data.clone()), vec![1]);
+
+        // the state stays unchanged if it cannot be written
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(store.update(|data| data.push(2)).is_err());
+        assert_eq!(store.read(|data| data.clone()), vec![1]);
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fixtures shared by the tests of the server modules.
+
+use std::{
+    env, fs, os::unix::fs::PermissionsExt, path::{Path, PathBuf},
+};
+use util::Config;
+
+/// Returns a config for a primary server on localhost.
+pub fn config() -> Config {
+    Config::new(
+        String::new(),
+        String::new(),
+        String::new(),
+        String::new(),
+        "127.0.0.1".to_owned(),
+    )
+}
+
+/// Creates the empty directory `bind9-api-<name>-test` in the temporary
+/// directory.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("bind9-api-{}-test", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes an executable shell script with the given body to `dir` and returns
+/// its path.
+pub fn script(dir: &Path, name: &str, body: &str) -> String {
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{}", body)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+/// Returns a config that runs an `nsupdate` replacement with the given body.
+pub fn fake_nsupdate(dir: &Path, body: &str) -> Config {
+    Config::new(
+        String::new(),
+        script(dir, "nsupdate", body),
+        String::new(),
+        String::new(),
+        "127.0.0.1".to_owned(),
+    )
+}
+
+/// Returns a config that runs a `dig` replacement with the given body.
+pub fn fake_dig(dir: &Path, body: &str) -> Config {
+    let mut config = config();
+    config.set_dig(script(dir, "dig", body));
+    config
+}
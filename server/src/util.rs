@@ -37,17 +37,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use actix::Addr;
 use actix_web::{
     error::{Error, ErrorInternalServerError, ErrorUnauthorized, JsonPayloadError, ParseError},
-    FromRequest, HttpMessage, HttpRequest, Result,
+    http::header::{AUTHORIZATION, HOST}, FromRequest, HttpMessage, HttpRequest, Result,
 };
+use acl::{Grant, Tokens};
+use acme_dns::AcmeDns;
+use admin::TokenStore;
+use client_cert::ClientCerts;
+use db::Db;
 use futures::future::{err as FutErr, Future};
+use oidc::Oidc;
+use poller::Poller;
+use reconcile::Reconciler;
+use ssh::AuthorizedKeys;
 use std::{ops::Deref, sync::Arc};
 
 #[derive(Debug, Fail)]
 pub enum ExecuteError {
     #[fail(display = "Stdin error")]
     Stdin,
+    #[fail(display = "Marker not found")]
+    Marker,
+    #[fail(display = "No SOA record found for {}", _0)]
+    NoZone(String),
+    #[fail(display = "No NS records found for {}", _0)]
+    NoNameservers(String),
 }
 
 pub struct Config {
@@ -56,6 +72,20 @@ pub struct Config {
     key_path: String,
     ok_marker: String,
     server: String,
+    dig: String,
+    max_wait: u32,
+    tokens: Tokens,
+    acme_dns: Option<AcmeDns>,
+    external_dns_zones: Vec<String>,
+    legacy_signatures: bool,
+    token_store: Option<TokenStore>,
+    oidc: Option<Oidc>,
+    authorized_keys: Option<AuthorizedKeys>,
+    client_certs: Option<ClientCerts>,
+    enforce_owners: bool,
+    db: Option<Db>,
+    reconciler: Option<Reconciler>,
+    poller: Option<Addr<Poller>>,
 }
 
 impl Config {
@@ -72,8 +102,81 @@ impl Config {
             key_path,
             ok_marker,
             server,
+            dig: "dig".to_owned(),
+            max_wait: 120,
+            tokens: Tokens::default(),
+            acme_dns: None,
+            external_dns_zones: Vec::new(),
+            legacy_signatures: false,
+            token_store: None,
+            oidc: None,
+            authorized_keys: None,
+            client_certs: None,
+            enforce_owners: false,
+            db: None,
+            reconciler: None,
+            poller: None,
         }
     }
+
+    pub fn set_dig(&mut self, dig: String) {
+        self.dig = dig;
+    }
+
+    pub fn set_max_wait(&mut self, max_wait: u32) {
+        self.max_wait = max_wait;
+    }
+
+    pub fn set_tokens(&mut self, tokens: Tokens) {
+        self.tokens = tokens;
+    }
+
+    pub fn set_acme_dns(&mut self, acme_dns: AcmeDns) {
+        self.acme_dns = Some(acme_dns);
+    }
+
+    pub fn set_external_dns_zones(&mut self, zones: Vec<String>) {
+        self.external_dns_zones = zones;
+    }
+
+    pub fn set_legacy_signatures(&mut self, legacy_signatures: bool) {
+        self.legacy_signatures = legacy_signatures;
+    }
+
+    pub fn set_token_store(&mut self, token_store: TokenStore) {
+        self.token_store = Some(token_store);
+    }
+
+    pub fn set_oidc(&mut self, oidc: Oidc) {
+        self.oidc = Some(oidc);
+    }
+
+    pub fn set_authorized_keys(&mut self, authorized_keys: AuthorizedKeys) {
+        self.authorized_keys = Some(authorized_keys);
+    }
+
+    pub fn set_client_certs(&mut self, client_certs: ClientCerts) {
+        self.client_certs = Some(client_certs);
+    }
+
+    /// Refuses changes to RRsets owned by other identities. Requires the
+    /// state store, which records the owners.
+    pub fn set_enforce_owners(&mut self, enforce_owners: bool) {
+        self.enforce_owners = enforce_owners;
+    }
+
+    pub fn set_db(&mut self, db: Db) {
+        self.db = Some(db);
+    }
+
+    pub fn set_reconciler(&mut self, reconciler: Reconciler) {
+        self.reconciler = Some(reconciler);
+    }
+
+    pub fn set_poller(&mut self, poller: Addr<Poller>) {
+        self.poller = Some(poller);
+    }
+
     #[inline]
     pub fn token(&self) -> &str {
         &self.token
@@ -98,33 +201,328 @@ impl Config {
     pub fn server(&self) -> &str {
         &self.server
     }
+
+    #[inline]
+    pub fn dig(&self) -> &str {
+        &self.dig
+    }
+
+    /// Returns the maximum number of seconds a request may wait for
+    /// propagation.
+    #[inline]
+    pub fn max_wait(&self) -> u32 {
+        self.max_wait
+    }
+
+    #[inline]
+    pub fn tokens(&self) -> &Tokens {
+        &self.tokens
+    }
+
+    #[inline]
+    pub fn acme_dns(&self) -> Option<&AcmeDns> {
+        self.acme_dns.as_ref()
+    }
+
+    #[inline]
+    pub fn external_dns_zones(&self) -> &[String] {
+        &self.external_dns_zones
+    }
+
+    #[inline]
+    pub fn legacy_signatures(&self) -> bool {
+        self.legacy_signatures
+    }
+
+    #[inline]
+    pub fn token_store(&self) -> Option<&TokenStore> {
+        self.token_store.as_ref()
+    }
+
+    #[inline]
+    pub fn oidc(&self) -> Option<&Oidc> {
+        self.oidc.as_ref()
+    }
+
+    #[inline]
+    pub fn authorized_keys(&self) -> Option<&AuthorizedKeys> {
+        self.authorized_keys.as_ref()
+    }
+
+    #[inline]
+    pub fn client_certs(&self) -> Option<&ClientCerts> {
+        self.client_certs.as_ref()
+    }
+
+    #[inline]
+    pub fn enforce_owners(&self) -> bool {
+        self.enforce_owners
+    }
+
+    #[inline]
+    pub fn db(&self) -> Option<&Db> {
+        self.db.as_ref()
+    }
+
+    #[inline]
+    pub fn reconciler(&self) -> Option<&Reconciler> {
+        self.reconciler.as_ref()
+    }
+
+    #[inline]
+    pub fn poller(&self) -> Option<&Addr<Poller>> {
+        self.poller.as_ref()
+    }
 }
 
-pub struct Validated<T>(T);
+pub struct Validated<T>(T, Grant);
+
+impl<T> Validated<T> {
+    /// Returns what the signer of the request is allowed to do.
+    #[inline]
+    pub fn grant(&self) -> &Grant {
+        &self.1
+    }
+}
 
 impl<T: 'static + ::serde::de::DeserializeOwned> FromRequest<Arc<Config>> for Validated<T> {
     type Config = ();
     type Result = Box<dyn Future<Item = Self, Error = Error>>;
 
     fn from_request(req: &HttpRequest<Arc<Config>>, _: &Self::Config) -> Self::Result {
-        let state = req.state().clone();
-        let sig = extract_signature(&req);
-        if sig.is_err() {
-            return Box::new(FutErr(sig.unwrap_err()));
+        Box::new(verified_body(req).and_then(|(body, grant)| {
+            let delete: T = ::serde_json::from_slice(&body)
+                .map_err(|e| ErrorInternalServerError(JsonPayloadError::Deserialize(e)))?;
+            Ok(Validated(delete, grant))
+        }))
+    }
+}
+
+/// Extractor for requests without a payload, e.g. `GET` requests. The signature
+/// is verified over the (empty) body.
+pub struct Authenticated(Grant);
+
+impl FromRequest<Arc<Config>> for Authenticated {
+    type Config = ();
+    type Result = Box<dyn Future<Item = Self, Error = Error>>;
+
+    fn from_request(req: &HttpRequest<Arc<Config>>, _: &Self::Config) -> Self::Result {
+        Box::new(verified_body(req).map(|(_, grant)| Authenticated(grant)))
+    }
+}
+
+impl Deref for Authenticated {
+    type Target = Grant;
+
+    fn deref(&self) -> &Grant {
+        &self.0
+    }
+}
+
+/// Maximum difference between the signing timestamp of a request and the
+/// server's clock, in seconds.
+const MAX_CLOCK_SKEW: u64 = 300;
+
+/// Verifies the signature of a request and returns its body. The signature
+/// covers the canonical request (see `crypto::canonical_request`) built from
+/// method, path, query, host, key id and timestamp headers and the body.
+/// Requests without timestamp, where the signature covers only the body, are
+/// only accepted if legacy signatures are enabled.
+///
+/// Requests carrying an OIDC JWT as bearer token or, if passed by the TLS
+/// terminating proxy, a client certificate are authenticated by the token or
+/// certificate instead and need no signature.
+///
+/// If the request names a key using the key id header, the signature is
+/// checked using the currently valid Ed25519 public keys and secrets with this
+/// id, the SSH key with this fingerprint, the token of this name in the token
+/// store or, for delegated tokens, the secret derived from the id. Otherwise
+/// it is a HMAC using the shared secret.
+fn verified_body(
+    req: &HttpRequest<Arc<Config>>,
+) -> Box<dyn Future<Item = (Vec<u8>, Grant), Error = Error>> {
+    let state = req.state().clone();
+    if let Some(token) = extract_bearer_token(req) {
+        let grant = match state.oidc() {
+            Some(oidc) => oidc
+                .verify(&token, ::lease::now())
+                .map_err(|e| ErrorUnauthorized(e.to_string())),
+            None => Err(ErrorUnauthorized("Bearer tokens are not enabled")),
+        };
+        return body_with_grant(req, grant);
+    }
+    if let Some(client_certs) = state.client_certs() {
+        if let Ok(Some(cert)) = header(req, client_certs.header()) {
+            let peer = req.peer_addr().map(|addr| addr.ip());
+            let grant = client_certs
+                .verify(peer, &cert)
+                .map_err(|e| ErrorUnauthorized(e.to_string()));
+            return body_with_grant(req, grant);
         }
-        let sig = sig.unwrap();
-        Box::new(req.clone().body().from_err().and_then(move |body| {
-            if ::crypto::verify_signature(state.token().as_bytes(), &body, &sig) {
-                let delete: T = ::serde_json::from_slice(&body)
-                    .map_err(|e| ErrorInternalServerError(JsonPayloadError::Deserialize(e)))?;
-                Ok(Validated(delete))
-            } else {
-                Err(ErrorUnauthorized(ParseError::Header))
+    }
+    let parsed = (
+        extract_signature(req),
+        header(req, ::data::KEY_ID_HEADER),
+        header(req, ::data::TIMESTAMP_HEADER),
+        request_host(req),
+    );
+    let (sig, key_id, timestamp, host) = match parsed {
+        (Ok(sig), Ok(key_id), Ok(timestamp), Ok(host)) => (sig, key_id, timestamp, host),
+        _ => return Box::new(FutErr(ErrorUnauthorized(ParseError::Header))),
+    };
+    let canonical = match timestamp {
+        Some(timestamp) => {
+            if !timestamp_valid(&timestamp) {
+                return Box::new(FutErr(ErrorUnauthorized("Request expired")));
             }
-        }))
+            let mut headers = vec![
+                ("host", host),
+                ("x-api-timestamp", timestamp),
+            ];
+            if let Some(ref key_id) = key_id {
+                headers.push(("x-api-key-id", key_id.clone()));
+            }
+            Some((
+                req.method().as_str().to_owned(),
+                req.path().to_owned(),
+                req.query_string().to_owned(),
+                headers,
+            ))
+        }
+        None if state.legacy_signatures() => None,
+        None => return Box::new(FutErr(ErrorUnauthorized(ParseError::Header))),
+    };
+    Box::new(req.clone().body().from_err().and_then(move |body| {
+        let msg = match canonical {
+            Some((method, path, query, headers)) => {
+                let headers: Vec<_> = headers.iter().map(|(n, v)| (*n, v.as_str())).collect();
+                ::crypto::canonical_request(&method, &path, &query, &headers, &body)
+            }
+            None => body.to_vec(),
+        };
+        let grant = match key_id {
+            Some(id) => state
+                .tokens()
+                .verify(&id, &msg, &sig, ::lease::now())
+                .or_else(|| {
+                    state
+                        .authorized_keys()
+                        .and_then(|keys| keys.verify(&id, &msg, &sig))
+                }).or_else(|| {
+                    state
+                        .token_store()
+                        .and_then(|store| ::admin::verify(store, &id, &msg, &sig))
+                }).or_else(|| ::delegate::verify(&state, &id, &msg, &sig)),
+            None if ::crypto::verify_signature(state.token().as_bytes(), &msg, &sig) => {
+                Some(Grant::master())
+            }
+            None => None,
+        };
+        grant
+            .map(|grant| (body.to_vec(), grant))
+            .ok_or_else(|| ErrorUnauthorized(ParseError::Header))
+    }))
+}
+
+/// Returns the body of a request that is authenticated without signature.
+fn body_with_grant(
+    req: &HttpRequest<Arc<Config>>,
+    grant: Result<Grant>,
+) -> Box<dyn Future<Item = (Vec<u8>, Grant), Error = Error>> {
+    match grant {
+        Ok(grant) => Box::new(
+            req.clone()
+                .body()
+                .from_err()
+                .map(move |body| (body.to_vec(), grant)),
+        ),
+        Err(e) => Box::new(FutErr(e)),
     }
 }
 
+fn timestamp_valid(timestamp: &str) -> bool {
+    let now = ::lease::now();
+    timestamp
+        .parse::<u64>()
+        .map(|t| t.max(now) - t.min(now) <= MAX_CLOCK_SKEW)
+        .unwrap_or(false)
+}
+
+/// Returns the value of an optional header. Values that are not valid strings
+/// are an error.
+/// Returns the host the request was sent to, as signed by the client. Unlike
+/// the connection info, this ignores the `Forwarded` and `X-Forwarded-Host`
+/// headers, which any client can set.
+fn request_host<S>(req: &HttpRequest<S>) -> Result<String> {
+    match header(req, HOST.as_str())? {
+        Some(host) => Ok(host.to_lowercase()),
+        None => req
+            .uri()
+            .authority_part()
+            .map(|authority| authority.as_str().to_lowercase())
+            .ok_or_else(|| ErrorUnauthorized(ParseError::Header)),
+    }
+}
+
+fn header<S>(req: &HttpRequest<S>, name: &str) -> Result<Option<String>> {
+    req.headers()
+        .get(name)
+        .map(|value| {
+            value
+                .to_str()
+                .map(str::to_owned)
+                .map_err(|_| ErrorUnauthorized(ParseError::Header))
+        }).transpose()
+}
+
+/// Extractor for requests that are authenticated with HTTP basic auth against
+/// the scoped tokens and the password tokens in the token store.
+pub struct BasicAuth(Grant);
+
+impl FromRequest<Arc<Config>> for BasicAuth {
+    type Config = ();
+    type Result = Result<Self>;
+
+    fn from_request(req: &HttpRequest<Arc<Config>>, _: &Self::Config) -> Self::Result {
+        let (user, password) =
+            extract_basic_auth(req).ok_or_else(|| ErrorUnauthorized("badauth"))?;
+        let state = req.state();
+        state
+            .tokens()
+            .basic_auth(&user, &password)
+            .map(Grant::from)
+            .or_else(|| {
+                state
+                    .token_store()
+                    .and_then(|store| ::admin::basic_auth(store, &user, &password))
+            }).map(BasicAuth)
+            .ok_or_else(|| ErrorUnauthorized("badauth"))
+    }
+}
+
+impl Deref for BasicAuth {
+    type Target = Grant;
+
+    fn deref(&self) -> &Grant {
+        &self.0
+    }
+}
+
+fn extract_bearer_token<S>(req: &HttpRequest<S>) -> Option<String> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    header
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_owned())
+}
+
+fn extract_basic_auth<S>(req: &HttpRequest<S>) -> Option<(String, String)> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let decoded = ::base64::decode(header.strip_prefix("Basic ")?.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let mut parts = decoded.splitn(2, ':');
+    Some((parts.next()?.to_owned(), parts.next()?.to_owned()))
+}
+
 impl<T> Deref for Validated<T> {
     type Target = T;
 
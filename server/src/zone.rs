@@ -0,0 +1,249 @@
+// Copyright (c) 2018 Brandl, Valentin <mail+rust@vbrandl.net>
+// Author: Brandl, Valentin <mail+rust@vbrandl.net>
+//
+// Licensed unter the Apache License, Version 2.0 or the MIT license, at your
+// option.
+//
+// ********************************************************************************
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies of
+// the Software, and to permit persons to whom the Software is furnished to do so,
+// subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS
+// FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR
+// COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+// IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN
+// CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+//
+// ********************************************************************************
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use data::{Record, ResourceRecord};
+use failure::Error;
+use std::process::Command;
+use util::Config;
+
+#[derive(Debug, Fail)]
+pub enum ZoneError {
+    #[fail(display = "Zone transfer of {} failed", _0)]
+    Transfer(String),
+    #[fail(display = "Invalid name {}", _0)]
+    Name(String),
+}
+
+/// Parses a line of `dig` output in presentation format. Returns `None` for
+/// comments and record types that are not supported.
+fn parse_line(line: &str) -> Option<ResourceRecord> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with(';') {
+        return None;
+    }
+    let mut rest = line;
+    let mut fields = Vec::with_capacity(4);
+    for _ in 0..4 {
+        let end = rest.find(char::is_whitespace)?;
+        fields.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    Some(ResourceRecord::new(
+        fields[0].to_owned(),
+        fields[1].parse().ok()?,
+        fields[3].parse().ok()?,
+        rest.to_owned(),
+    ))
+}
+
+/// Parses the output of a zone transfer.
+pub fn parse(output: &str) -> Vec<ResourceRecord> {
+    output.lines().filter_map(parse_line).collect()
+}
+
+/// Queries the primary server for the RRset of `name`.
+pub fn lookup(name: &str, record: Record, config: &Config) -> Result<Vec<ResourceRecord>, Error> {
+    check_name(name)?;
+    let server = format!("@{}", config.server());
+    let output = Command::new(config.dig())
+        .args([
+            &server,
+            name,
+            &record.to_string(),
+            "+norecurse",
+            "+noall",
+            "+answer",
+        ]).output()?;
+    Ok(parse(&String::from_utf8(output.stdout)?)
+        .into_iter()
+        .filter(|r| r.record() == record)
+        .collect())
+}
+
+/// Transfers the zone from the primary server using a TSIG signed AXFR.
+pub fn transfer(zone: &str, config: &Config) -> Result<Vec<ResourceRecord>, Error> {
+    if !valid_name(zone) {
+        return Err(ZoneError::Name(zone.to_owned()).into());
+    }
+    let server = format!("@{}", config.server());
+    let output = Command::new(config.dig())
+        .args([
+            "-k",
+            config.key_path(),
+            &server,
+            zone,
+            "AXFR",
+            "+noall",
+            "+answer",
+        ]).output()?;
+    let success = output.status.success();
+    let output = String::from_utf8(output.stdout)?;
+    let mut records = parse(&output);
+    // dig exits successfully if the server refuses the transfer, but prints a
+    // comment instead of the records
+    if !success
+        || output.contains("; Transfer failed")
+        || records.first().map(ResourceRecord::record) != Some(Record::SOA)
+    {
+        return Err(ZoneError::Transfer(zone.to_owned()).into());
+    }
+    // the transfer ends with the SOA record it started with
+    if records.len() > 1 && records.first() == records.last() {
+        records.pop();
+    }
+    Ok(records)
+}
+
+/// Returns `true` if `zone` is a domain name, that cannot be mistaken for an
+/// option by `dig` or `nsupdate`.
+pub fn valid_name(zone: &str) -> bool {
+    zone.len() <= 254
+        && !zone.starts_with('-')
+        && zone
+            .strip_suffix('.')
+            .unwrap_or(zone)
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63)
+        && zone
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_')
+}
+
+/// Checks that `name` is a domain name or a wildcard (`*.example.com`), before
+/// it is passed to `dig`.
+pub fn check_name(name: &str) -> Result<(), ZoneError> {
+    if valid_name(name.strip_prefix("*.").unwrap_or(name)) {
+        Ok(())
+    } else {
+        Err(ZoneError::Name(name.to_owned()))
+    }
+}
+
+/// Renders records as RFC 1035 master file.
+pub fn master_file(zone: &str, records: &[ResourceRecord]) -> String {
+    let mut out = format!("$ORIGIN {}.\n", zone.trim_end_matches('.'));
+    for record in records {
+        out.push_str(&format!(
+            "{}\t{}\tIN\t{}\t{}\n",
+            record.name(),
+            record.ttl(),
+            record.record(),
+            record.value()
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data::Record;
+
+    #[test]
+    fn parse_axfr() {
+        let output = "example.com.\t\t3600\tIN\tSOA\tns1.example.com. hostmaster.example.com. 2018121901 7200 3600 1209600 3600
+www.example.com.\t300\tIN\tA\t192.0.2.1
+_acme-challenge.example.com. 60\tIN\tTXT\t\"foo bar\" \"baz\"
+example.com.\t\t3600\tIN\tTYPE65534 \\# 5 0A0F380001
+; Transfer failed.
+";
+        let records = parse(output);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].record(), Record::SOA);
+        assert_eq!(
+            records[1],
+            ResourceRecord::new(
+                "www.example.com.".to_owned(),
+                300,
+                Record::A,
+                "192.0.2.1".to_owned()
+            )
+        );
+        assert_eq!(records[2].value(), "\"foo bar\" \"baz\"");
+        assert_eq!(
+            master_file("example.com", &records[1..2]),
+            "$ORIGIN example.com.\nwww.example.com.\t300\tIN\tA\t192.0.2.1\n"
+        );
+    }
+
+    #[test]
+    fn zone_names() {
+        assert!(valid_name("example.com"));
+        assert!(valid_name("_tcp.example-1.com."));
+        assert!(!valid_name(""));
+        assert!(!valid_name("-fexample.com"));
+        assert!(!valid_name("+tcp"));
+        assert!(!valid_name("example.com @evil"));
+        assert!(!valid_name("www..example.com"));
+        assert!(!valid_name("."));
+        assert!(!valid_name("www.example.com\nsend"));
+        assert!(check_name("*.example.com").is_ok());
+        assert!(check_name("*.-example.com").is_err());
+        assert!(check_name("-f/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn reject_options() {
+        let mut config = ::testutil::config();
+        // prints nothing, so valid names have no records
+        config.set_dig("true".to_owned());
+        assert!(lookup("www.example.com", Record::A, &config).unwrap().is_empty());
+        assert!(lookup("-f/etc/passwd", Record::A, &config).is_err());
+        assert!(lookup("+tcp", Record::A, &config).is_err());
+        assert!(transfer("-fexample.com", &config).is_err());
+    }
+
+    #[test]
+    fn failed_transfers() {
+        let dir = ::testutil::temp_dir("zone");
+        let soa = "example.com. 3600 IN SOA ns1.example.com. hostmaster.example.com. \
+                   1 7200 3600 1209600 3600";
+        let transfer = |body: &str| transfer("example.com", &::testutil::fake_dig(&dir, body));
+
+        let www = "www.example.com. 300 IN A 192.0.2.1";
+        let records = transfer(&format!("echo '{0}'\necho '{1}'\necho '{0}'\n", soa, www)).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(transfer(&format!("echo '{}'\nexit 9\n", soa)).is_err());
+        assert!(transfer("echo '; Transfer failed.'\n").is_err());
+        // a comment mentioning the SOA is no record
+        assert!(transfer("echo '; SOA query refused'\necho '; Transfer failed.'\n").is_err());
+        assert!(transfer("echo '; <<>> DiG <<>> SOA'\n").is_err());
+        ::std::fs::remove_dir_all(&dir).unwrap();
+    }
+}